@@ -0,0 +1,32 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use evkms_metrics_simulation::{
+    rng::rng_from_seed,
+    topology::random::{initialize_network, initialize_network_reference},
+};
+
+// Degree-bounded topologies with 10% gateways and 10 to 30 neighbors per node
+fn topology(c: &mut Criterion) {
+    let mut group = c.benchmark_group("initialize_network");
+    group.sample_size(10);
+    for number_of_nodes in [1_000, 10_000, 100_000] {
+        group.bench_with_input(
+            BenchmarkId::new("candidate_set", number_of_nodes),
+            &number_of_nodes,
+            |b, &n| b.iter(|| initialize_network(n, n / 10, 10, 30, &mut rng_from_seed(1))),
+        );
+    }
+    // The reference generator is cubic, larger networks take minutes per sample
+    for number_of_nodes in [1_000, 2_000] {
+        group.bench_with_input(
+            BenchmarkId::new("reference", number_of_nodes),
+            &number_of_nodes,
+            |b, &n| {
+                b.iter(|| initialize_network_reference(n, n / 10, 10, 30, &mut rng_from_seed(1)))
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, topology);
+criterion_main!(benches);
@@ -0,0 +1,398 @@
+use std::{
+    error::Error,
+    fmt, fs,
+    hint::black_box,
+    io::{self, Write},
+    path::Path,
+    time::Instant,
+};
+
+use aes::{
+    cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::methods::evkms::protocol::{field_mul, FIELD_PRIME};
+
+#[derive(Debug)]
+pub enum CalibrationError {
+    Io(io::Error),
+    Parse(String),
+    UnknownProfile(String),
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalibrationError::Io(error) => write!(f, "could not read profile: {}", error),
+            CalibrationError::Parse(error) => write!(f, "could not parse profile: {}", error),
+            CalibrationError::UnknownProfile(name) => write!(
+                f,
+                "unknown profile: {} (expected one of {} or a TOML file)",
+                name,
+                BUILTIN_PROFILES.join(", ")
+            ),
+        }
+    }
+}
+
+impl Error for CalibrationError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Primitive {
+    // One 16 byte AES-128 block
+    AesEncrypt,
+    AesDecrypt,
+    // One 64 byte SHA-256 block
+    Sha256,
+    // HMAC-SHA-256 of a one block message
+    HmacSha256,
+    // Multiplication modulo 2^61 - 1
+    FieldMultiplication,
+}
+
+impl Primitive {
+    pub const ALL: [Primitive; 5] = [
+        Primitive::AesEncrypt,
+        Primitive::AesDecrypt,
+        Primitive::Sha256,
+        Primitive::HmacSha256,
+        Primitive::FieldMultiplication,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Primitive::AesEncrypt => "aes_encrypt",
+            Primitive::AesDecrypt => "aes_decrypt",
+            Primitive::Sha256 => "sha256",
+            Primitive::HmacSha256 => "hmac_sha256",
+            Primitive::FieldMultiplication => "field_multiplication",
+        }
+    }
+
+    // Configuration parameter priced by the primitive, HMAC is only reported
+    pub fn parameter(&self) -> Option<&'static str> {
+        match self {
+            Primitive::AesEncrypt => Some("ENCRYPTION_ENERGY"),
+            Primitive::AesDecrypt => Some("DECRYPTION_ENERGY"),
+            Primitive::Sha256 => Some("HASH_ENERGY"),
+            Primitive::HmacSha256 => None,
+            Primitive::FieldMultiplication => Some("MULTIPLICATION_ENERGY"),
+        }
+    }
+}
+
+// Cycles taken by each primitive on the target CPU
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PrimitiveCycles {
+    pub aes_encrypt: f64,
+    pub aes_decrypt: f64,
+    pub sha256: f64,
+    pub hmac_sha256: f64,
+    pub field_multiplication: f64,
+}
+
+impl PrimitiveCycles {
+    pub fn get(&self, primitive: Primitive) -> f64 {
+        match primitive {
+            Primitive::AesEncrypt => self.aes_encrypt,
+            Primitive::AesDecrypt => self.aes_decrypt,
+            Primitive::Sha256 => self.sha256,
+            Primitive::HmacSha256 => self.hmac_sha256,
+            Primitive::FieldMultiplication => self.field_multiplication,
+        }
+    }
+}
+
+// CPU the energies are calibrated for. Without a cycle table the primitives are priced from
+// their running time on the host.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CpuProfile {
+    pub name: String,
+    pub clock_hz: f64,
+    // Power drawn while the CPU is active, in W
+    pub active_power: f64,
+    #[serde(default)]
+    pub cycles: Option<PrimitiveCycles>,
+}
+
+pub const BUILTIN_PROFILES: [&str; 3] = ["msp430", "cortex_m0", "host"];
+
+impl CpuProfile {
+    // Approximate cycle counts of published implementations: AES-128 from the TI application
+    // note for the MSP430 and bitsliced AES for the Cortex-M0, plain C SHA-256, and the HMAC
+    // costing four compressions
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "msp430" => Some(Self {
+                name: name.to_string(),
+                clock_hz: 8_000_000.0,
+                active_power: 0.012,
+                cycles: Some(PrimitiveCycles {
+                    aes_encrypt: 5432.0,
+                    aes_decrypt: 8802.0,
+                    sha256: 36000.0,
+                    hmac_sha256: 144000.0,
+                    field_multiplication: 400.0,
+                }),
+            }),
+            "cortex_m0" => Some(Self {
+                name: name.to_string(),
+                clock_hz: 48_000_000.0,
+                active_power: 0.033,
+                cycles: Some(PrimitiveCycles {
+                    aes_encrypt: 2400.0,
+                    aes_decrypt: 3600.0,
+                    sha256: 4800.0,
+                    hmac_sha256: 19200.0,
+                    field_multiplication: 120.0,
+                }),
+            }),
+            "host" => Some(Self {
+                name: name.to_string(),
+                clock_hz: 0.0,
+                active_power: 15.0,
+                cycles: None,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, CalibrationError> {
+        let contents = fs::read_to_string(path).map_err(CalibrationError::Io)?;
+        toml::from_str(&contents).map_err(|error| CalibrationError::Parse(error.to_string()))
+    }
+
+    // A built-in profile name, or else the path of a TOML profile
+    pub fn find(name: &str) -> Result<Self, CalibrationError> {
+        if let Some(profile) = Self::builtin(name) {
+            return Ok(profile);
+        }
+        let path = Path::new(name);
+        if path.exists() {
+            Self::from_path(path)
+        } else {
+            Err(CalibrationError::UnknownProfile(name.to_string()))
+        }
+    }
+
+    // Energy of one run of the primitive, `host_seconds` being its running time on the host
+    pub fn energy(&self, primitive: Primitive, host_seconds: f64) -> f64 {
+        match &self.cycles {
+            Some(cycles) => cycles.get(primitive) / self.clock_hz * self.active_power,
+            None => host_seconds * self.active_power,
+        }
+    }
+}
+
+// Mean running time of one primitive on the host
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Timing {
+    pub primitive: Primitive,
+    pub seconds: f64,
+}
+
+fn time_per_iteration<F: FnMut(u64)>(iterations: u32, mut operation: F) -> f64 {
+    // Warm the caches and the branch predictors first
+    for i in 0..(iterations / 10).max(1) {
+        operation(i as u64);
+    }
+    let start = Instant::now();
+    for i in 0..iterations {
+        operation(i as u64);
+    }
+    start.elapsed().as_secs_f64() / iterations.max(1) as f64
+}
+
+fn time_primitive(primitive: Primitive, iterations: u32) -> f64 {
+    let cipher = Aes128::new(&GenericArray::from([0x2b; 16]));
+    let block = [0x5a_u8; 64];
+    match primitive {
+        Primitive::AesEncrypt => {
+            let mut state = GenericArray::from([0u8; 16]);
+            time_per_iteration(iterations, |i| {
+                state[0] ^= i as u8;
+                cipher.encrypt_block(black_box(&mut state));
+            })
+        }
+        Primitive::AesDecrypt => {
+            let mut state = GenericArray::from([0u8; 16]);
+            time_per_iteration(iterations, |i| {
+                state[0] ^= i as u8;
+                cipher.decrypt_block(black_box(&mut state));
+            })
+        }
+        Primitive::Sha256 => time_per_iteration(iterations, |i| {
+            let mut hasher = Sha256::new();
+            hasher.update(i.to_be_bytes());
+            hasher.update(&block[8..55]);
+            black_box(hasher.finalize());
+        }),
+        Primitive::HmacSha256 => time_per_iteration(iterations, |i| {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&block[..32])
+                .expect("HMAC accepts keys of any length");
+            mac.update(&i.to_be_bytes());
+            mac.update(&block[8..40]);
+            black_box(mac.finalize());
+        }),
+        Primitive::FieldMultiplication => {
+            let mut value = FIELD_PRIME - 2;
+            time_per_iteration(iterations, |i| {
+                value = field_mul(black_box(value), black_box(i | 1));
+            })
+        }
+    }
+}
+
+// Time every primitive on the host, `iterations` runs each
+pub fn benchmark(iterations: u32) -> Vec<Timing> {
+    let mut timings: Vec<Timing> = Vec::new();
+    for primitive in Primitive::ALL {
+        timings.push(Timing {
+            primitive,
+            seconds: time_primitive(primitive, iterations),
+        });
+    }
+    timings
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Calibration {
+    pub profile: CpuProfile,
+    pub timings: Vec<Timing>,
+}
+
+impl Calibration {
+    pub fn new(profile: CpuProfile, iterations: u32) -> Self {
+        Self {
+            profile,
+            timings: benchmark(iterations),
+        }
+    }
+
+    pub fn energy(&self, primitive: Primitive) -> Option<f64> {
+        let timing = self
+            .timings
+            .iter()
+            .find(|timing| timing.primitive == primitive)?;
+        Some(self.profile.energy(primitive, timing.seconds))
+    }
+
+    // Environment fragment pricing the operations, the measurements as `#` comments
+    pub fn write_env<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let profile = &self.profile;
+        writeln!(
+            writer,
+            "# Operation energies calibrated for the {} profile, {} W",
+            profile.name, profile.active_power
+        )?;
+        match &profile.cycles {
+            Some(_) => writeln!(writer, "# from its cycle counts at {} Hz", profile.clock_hz)?,
+            None => writeln!(writer, "# from the running times measured on the host")?,
+        }
+        writeln!(
+            writer,
+            "# Load it after the base environment: --env-file .env --env-file <this file>"
+        )?;
+        for timing in self.timings.iter() {
+            let primitive = timing.primitive;
+            writeln!(
+                writer,
+                "# {}: {:.1} ns on the host, {:.4e} J",
+                primitive.name(),
+                timing.seconds * 1e9,
+                profile.energy(primitive, timing.seconds)
+            )?;
+        }
+        for timing in self.timings.iter() {
+            if let Some(parameter) = timing.primitive.parameter() {
+                writeln!(
+                    writer,
+                    "{}={:.4e}",
+                    parameter,
+                    profile.energy(timing.primitive, timing.seconds)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::schema;
+
+    #[test]
+    fn cycles_are_priced_at_the_clock_and_power_of_the_profile() {
+        let profile = CpuProfile::builtin("cortex_m0").unwrap();
+        // 4800 cycles at 48 MHz drawing 33 mW, whatever the host took
+        let expected = 4800.0 / 48_000_000.0 * 0.033;
+        assert!((profile.energy(Primitive::Sha256, 1.0) - expected).abs() < 1e-15);
+        let host = CpuProfile::builtin("host").unwrap();
+        assert_eq!(host.energy(Primitive::Sha256, 2e-6), 2e-6 * 15.0);
+    }
+
+    #[test]
+    fn profiles_are_found_by_name_or_path() {
+        assert_eq!(
+            CpuProfile::find("msp430").unwrap(),
+            CpuProfile::builtin("msp430").unwrap()
+        );
+
+        let path = std::env::temp_dir().join(format!("profile-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "name = \"board\"\nclock_hz = 16000000.0\nactive_power = 0.02\n",
+        )
+        .unwrap();
+        let profile = CpuProfile::find(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        let profile = profile.unwrap();
+        assert_eq!(profile.name, "board");
+        assert_eq!(profile.clock_hz, 16_000_000.0);
+        assert_eq!(profile.cycles, None);
+
+        assert!(matches!(
+            CpuProfile::find("no_such_profile"),
+            Err(CalibrationError::UnknownProfile(name)) if name == "no_such_profile"
+        ));
+    }
+
+    #[test]
+    fn the_environment_sets_every_priced_parameter_once() {
+        let calibration = Calibration {
+            profile: CpuProfile::builtin("msp430").unwrap(),
+            timings: Primitive::ALL
+                .iter()
+                .map(|&primitive| Timing {
+                    primitive,
+                    seconds: 1e-6,
+                })
+                .collect(),
+        };
+        let mut text: Vec<u8> = Vec::new();
+        calibration.write_env(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let mut keys: Vec<&str> = Vec::new();
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let (key, value) = line.split_once('=').unwrap();
+            schema::require(key).unwrap().check(value).unwrap();
+            keys.push(key);
+        }
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                "DECRYPTION_ENERGY",
+                "ENCRYPTION_ENERGY",
+                "HASH_ENERGY",
+                "MULTIPLICATION_ENERGY"
+            ]
+        );
+    }
+}
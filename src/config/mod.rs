@@ -0,0 +1,629 @@
+use std::{env, error::Error, fmt, str::FromStr};
+
+pub mod schema;
+
+use rand::Rng;
+use serde::Serialize;
+
+use schema::Section;
+
+use crate::{
+    initialize_network,
+    radio::{FirstOrderRadio, FlatRadio, RadioKind, SharedRadio},
+    resilience::CaptureTarget,
+    simulation::LossModel,
+    topology::{
+        assign_gateway_clusters,
+        geometric::{Area, GeometricTopology, Placement},
+        Attachment, TopologyKind,
+    },
+    NodeType, NodesVec,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    Missing(String),
+    Invalid { key: String, value: String },
+    OutOfRange { key: String, reason: String },
+    UnknownKey(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing(key) => write!(f, "{} must be set", key),
+            ConfigError::Invalid { key, value } => {
+                write!(f, "{} has an invalid value: {:?}", key, value)
+            }
+            ConfigError::OutOfRange { key, reason } => write!(f, "{} {}", key, reason),
+            ConfigError::UnknownKey(key) => match schema::closest(key) {
+                Some(candidate) => write!(
+                    f,
+                    "unknown configuration key: {}, did you mean {}?",
+                    key, candidate
+                ),
+                None => write!(f, "unknown configuration key: {}", key),
+            },
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    value.trim().parse::<T>().map_err(|_| ConfigError::Invalid {
+        key: key.to_string(),
+        value: value.to_string(),
+    })
+}
+
+pub(crate) fn out_of_range(key: &str, reason: &str) -> ConfigError {
+    ConfigError::OutOfRange {
+        key: key.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+// Radio, crypto and message size parameters shared by every scheme
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SimulationConfig {
+    // Radio energy per sent and received byte
+    pub epsb: f32,
+    pub eprb: f32,
+    // Energy of a single cryptographic operation
+    pub hash_energy: f32,
+    pub encryption_energy: f32,
+    pub decryption_energy: f32,
+    // Energy of a multiplication in the field of the key material
+    pub multiplication_energy: f32,
+    // Key predistribution parameters
+    pub key_ring_size: u32,
+    pub key_pool_size: u32,
+    pub aes_block_size: u32,
+    // Blom matrices: any λ + 1 captured nodes recover the secret matrix
+    pub matrix_lambda: u32,
+    pub matrix_field_bits: u32,
+    // Longest chain of secured links a path key may travel through
+    pub path_key_max_hops: u32,
+    // Radio energy model, EPSB and EPRB are only used by the flat model
+    pub radio: RadioKind,
+    pub first_order: FirstOrderRadio,
+    // Model selected by RADIO_MODEL, built again by `set` whenever a radio parameter changes
+    #[serde(skip)]
+    radio_model: SharedRadio,
+    // Link length in meters assumed for nodes without coordinates
+    pub default_link_distance: f32,
+    // Timing of the message-level protocol simulation
+    pub link_data_rate: f32,
+    pub processing_delay: f32,
+    pub mac_backoff: f32,
+    // Frame loss and link-layer retransmissions, only when the loss model is not none
+    pub loss_model: LossModel,
+    pub link_loss_probability: f32,
+    pub loss_half_distance: f32,
+    pub max_retransmissions: u32,
+    pub ack_timeout: f32,
+    pub link_ack_size: u32,
+    // Rekeying schedule, intervals in rounds
+    pub rekey_group_interval: u32,
+    pub rekey_pairwise_interval: u32,
+    pub membership_change_rate: f32,
+    pub compromise_rate: f32,
+    // Message sizes in bytes
+    pub sent_message_size: u32,
+    pub received_message_size: u32,
+    pub node_id_size: u32,
+    pub message_type_size: u32,
+    pub nonce_size: u32,
+}
+
+// Defaults come from the schema
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        let mut config = Self {
+            epsb: 0.0,
+            eprb: 0.0,
+            hash_energy: 0.0,
+            encryption_energy: 0.0,
+            decryption_energy: 0.0,
+            multiplication_energy: 0.0,
+            key_ring_size: 0,
+            key_pool_size: 0,
+            aes_block_size: 0,
+            matrix_lambda: 0,
+            matrix_field_bits: 0,
+            path_key_max_hops: 0,
+            radio: RadioKind::Flat,
+            first_order: FirstOrderRadio {
+                electronics_energy: 0.0,
+                free_space_amplifier: 0.0,
+                multipath_amplifier: 0.0,
+            },
+            radio_model: SharedRadio::new(FlatRadio {
+                epsb: 0.0,
+                eprb: 0.0,
+            }),
+            default_link_distance: 0.0,
+            link_data_rate: 0.0,
+            processing_delay: 0.0,
+            mac_backoff: 0.0,
+            loss_model: LossModel::None,
+            link_loss_probability: 0.0,
+            loss_half_distance: 0.0,
+            max_retransmissions: 0,
+            ack_timeout: 0.0,
+            link_ack_size: 0,
+            rekey_group_interval: 0,
+            rekey_pairwise_interval: 0,
+            membership_change_rate: 0.0,
+            compromise_rate: 0.0,
+            sent_message_size: 0,
+            received_message_size: 0,
+            node_id_size: 0,
+            message_type_size: 0,
+            nonce_size: 0,
+        };
+        for parameter in schema::section(Section::Simulation) {
+            if let Some(default) = parameter.default {
+                config
+                    .set(parameter.key, default)
+                    .expect("schema defaults are valid");
+            }
+        }
+        config
+    }
+}
+
+impl SimulationConfig {
+    // Build the configuration from the environment, falling back to the defaults for unset keys
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_lookup(|key| env::var(key).ok())
+    }
+
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        for parameter in schema::section(Section::Simulation) {
+            if let Some(value) = lookup(parameter.key) {
+                config.set(parameter.key, &value)?;
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        schema::require(key)?.check(value)?;
+        match key {
+            "EPSB" => self.epsb = parse(key, value)?,
+            "EPRB" => self.eprb = parse(key, value)?,
+            "HASH_ENERGY" => self.hash_energy = parse(key, value)?,
+            "ENCRYPTION_ENERGY" => self.encryption_energy = parse(key, value)?,
+            "DECRYPTION_ENERGY" => self.decryption_energy = parse(key, value)?,
+            "MULTIPLICATION_ENERGY" => self.multiplication_energy = parse(key, value)?,
+            "KEY_RING_SIZE" => self.key_ring_size = parse(key, value)?,
+            "KEY_POOL_SIZE" => self.key_pool_size = parse(key, value)?,
+            "AES_BLOCK_SIZE" => self.aes_block_size = parse(key, value)?,
+            "MATRIX_LAMBDA" => self.matrix_lambda = parse(key, value)?,
+            "MATRIX_FIELD_BITS" => self.matrix_field_bits = parse(key, value)?,
+            "PATH_KEY_MAX_HOPS" => self.path_key_max_hops = parse(key, value)?,
+            "RADIO_MODEL" => self.radio = parse(key, value)?,
+            "RADIO_ELECTRONICS_ENERGY" => self.first_order.electronics_energy = parse(key, value)?,
+            "RADIO_FREE_SPACE_AMPLIFIER" => {
+                self.first_order.free_space_amplifier = parse(key, value)?
+            }
+            "RADIO_MULTIPATH_AMPLIFIER" => {
+                self.first_order.multipath_amplifier = parse(key, value)?
+            }
+            "DEFAULT_LINK_DISTANCE" => self.default_link_distance = parse(key, value)?,
+            "LINK_DATA_RATE" => self.link_data_rate = parse(key, value)?,
+            "PROCESSING_DELAY" => self.processing_delay = parse(key, value)?,
+            "MAC_BACKOFF" => self.mac_backoff = parse(key, value)?,
+            "LOSS_MODEL" => self.loss_model = parse(key, value)?,
+            "LINK_LOSS_PROBABILITY" => self.link_loss_probability = parse(key, value)?,
+            "LOSS_HALF_DISTANCE" => self.loss_half_distance = parse(key, value)?,
+            "MAX_RETRANSMISSIONS" => self.max_retransmissions = parse(key, value)?,
+            "ACK_TIMEOUT" => self.ack_timeout = parse(key, value)?,
+            "LINK_ACK_SIZE" => self.link_ack_size = parse(key, value)?,
+            "REKEY_GROUP_INTERVAL" => self.rekey_group_interval = parse(key, value)?,
+            "REKEY_PAIRWISE_INTERVAL" => self.rekey_pairwise_interval = parse(key, value)?,
+            "MEMBERSHIP_CHANGE_RATE" => self.membership_change_rate = parse(key, value)?,
+            "COMPROMISE_RATE" => self.compromise_rate = parse(key, value)?,
+            "SENT_MESSAGE_SIZE" => self.sent_message_size = parse(key, value)?,
+            "RECEIVED_MESSAGE_SIZE" => self.received_message_size = parse(key, value)?,
+            "NODE_ID_SIZE" => self.node_id_size = parse(key, value)?,
+            "MESSAGE_TYPE_SIZE" => self.message_type_size = parse(key, value)?,
+            "NONCE_SIZE" => self.nonce_size = parse(key, value)?,
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        if matches!(key, "EPSB" | "EPRB") || key.starts_with("RADIO_") {
+            self.radio_model = self.build_radio_model();
+        }
+        Ok(())
+    }
+
+    // Current value of a parameter, `None` for keys of another section
+    pub fn get(&self, key: &str) -> Option<String> {
+        let value = match key {
+            "EPSB" => self.epsb.to_string(),
+            "EPRB" => self.eprb.to_string(),
+            "HASH_ENERGY" => self.hash_energy.to_string(),
+            "ENCRYPTION_ENERGY" => self.encryption_energy.to_string(),
+            "DECRYPTION_ENERGY" => self.decryption_energy.to_string(),
+            "MULTIPLICATION_ENERGY" => self.multiplication_energy.to_string(),
+            "KEY_RING_SIZE" => self.key_ring_size.to_string(),
+            "KEY_POOL_SIZE" => self.key_pool_size.to_string(),
+            "AES_BLOCK_SIZE" => self.aes_block_size.to_string(),
+            "MATRIX_LAMBDA" => self.matrix_lambda.to_string(),
+            "MATRIX_FIELD_BITS" => self.matrix_field_bits.to_string(),
+            "PATH_KEY_MAX_HOPS" => self.path_key_max_hops.to_string(),
+            "RADIO_MODEL" => self.radio.to_string(),
+            "RADIO_ELECTRONICS_ENERGY" => self.first_order.electronics_energy.to_string(),
+            "RADIO_FREE_SPACE_AMPLIFIER" => self.first_order.free_space_amplifier.to_string(),
+            "RADIO_MULTIPATH_AMPLIFIER" => self.first_order.multipath_amplifier.to_string(),
+            "DEFAULT_LINK_DISTANCE" => self.default_link_distance.to_string(),
+            "LINK_DATA_RATE" => self.link_data_rate.to_string(),
+            "PROCESSING_DELAY" => self.processing_delay.to_string(),
+            "MAC_BACKOFF" => self.mac_backoff.to_string(),
+            "LOSS_MODEL" => self.loss_model.to_string(),
+            "LINK_LOSS_PROBABILITY" => self.link_loss_probability.to_string(),
+            "LOSS_HALF_DISTANCE" => self.loss_half_distance.to_string(),
+            "MAX_RETRANSMISSIONS" => self.max_retransmissions.to_string(),
+            "ACK_TIMEOUT" => self.ack_timeout.to_string(),
+            "LINK_ACK_SIZE" => self.link_ack_size.to_string(),
+            "REKEY_GROUP_INTERVAL" => self.rekey_group_interval.to_string(),
+            "REKEY_PAIRWISE_INTERVAL" => self.rekey_pairwise_interval.to_string(),
+            "MEMBERSHIP_CHANGE_RATE" => self.membership_change_rate.to_string(),
+            "COMPROMISE_RATE" => self.compromise_rate.to_string(),
+            "SENT_MESSAGE_SIZE" => self.sent_message_size.to_string(),
+            "RECEIVED_MESSAGE_SIZE" => self.received_message_size.to_string(),
+            "NODE_ID_SIZE" => self.node_id_size.to_string(),
+            "MESSAGE_TYPE_SIZE" => self.message_type_size.to_string(),
+            "NONCE_SIZE" => self.nonce_size.to_string(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // Constraints between parameters, the range of each one is checked when it is set
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.key_ring_size > self.key_pool_size {
+            return Err(out_of_range(
+                "KEY_RING_SIZE",
+                "must not be greater than the key pool size",
+            ));
+        }
+        Ok(())
+    }
+
+    fn build_radio_model(&self) -> SharedRadio {
+        match self.radio {
+            RadioKind::Flat => SharedRadio::new(FlatRadio {
+                epsb: self.epsb,
+                eprb: self.eprb,
+            }),
+            RadioKind::FirstOrder => SharedRadio::new(self.first_order),
+        }
+    }
+
+    // Energy to transmit `bytes` over `distance` meters with the configured radio model
+    pub fn transmit_energy(&self, bytes: f32, distance: f32) -> f32 {
+        self.radio_model.model().transmit_energy(bytes, distance)
+    }
+
+    pub fn receive_energy(&self, bytes: f32) -> f32 {
+        self.radio_model.model().receive_energy(bytes)
+    }
+
+    // Size of a hello message carrying a MAC of the given size
+    pub fn hello_message_size(&self, mac_size: u32) -> f32 {
+        (self.message_type_size + self.node_id_size + self.nonce_size + mac_size) as f32
+    }
+}
+
+// Parameters of the generated network topology
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NetworkConfig {
+    pub number_of_nodes: i32,
+    pub number_of_gateways: i32,
+    pub number_of_min_possible_neighbors: i32,
+    pub number_of_max_possible_neighbors: i32,
+    pub number_of_gateway_members: i32,
+    // Optional, degree-bounded random pairing unless TOPOLOGY=geometric
+    pub topology: TopologyKind,
+    pub geometric: GeometricTopology,
+}
+
+impl NetworkConfig {
+    // Build the network configuration from the environment, keys without a default are required
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_lookup(|key| env::var(key).ok())
+    }
+
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut config = Self {
+            number_of_nodes: 0,
+            number_of_gateways: 0,
+            number_of_min_possible_neighbors: 0,
+            number_of_max_possible_neighbors: 0,
+            number_of_gateway_members: 0,
+            topology: TopologyKind::Random,
+            geometric: GeometricTopology {
+                area: Area {
+                    width: 0.0,
+                    height: 0.0,
+                    depth: 0.0,
+                },
+                placement: Placement::Uniform,
+                radio_range: 0.0,
+                number_of_clusters: 0,
+                cluster_spread: 0.0,
+            },
+        };
+        for parameter in schema::section(Section::Network) {
+            match (lookup(parameter.key), parameter.default) {
+                (Some(value), _) => config.set(parameter.key, &value)?,
+                (None, Some(default)) => config.set(parameter.key, default)?,
+                (None, None) => return Err(ConfigError::Missing(parameter.key.to_string())),
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        schema::require(key)?.check(value)?;
+        match key {
+            "NUMBER_OF_NODES" => self.number_of_nodes = parse(key, value)?,
+            "NUMBER_OF_GATEWAYS" => self.number_of_gateways = parse(key, value)?,
+            "NUMBER_OF_MIN_POSSIBLE_NEIGHBORS" => {
+                self.number_of_min_possible_neighbors = parse(key, value)?
+            }
+            "NUMBER_OF_MAX_POSSIBLE_NEIGHBORS" => {
+                self.number_of_max_possible_neighbors = parse(key, value)?
+            }
+            "NUMBER_OF_GATEWAY_MEMBERS" => self.number_of_gateway_members = parse(key, value)?,
+            "TOPOLOGY" => self.topology = parse(key, value)?,
+            "AREA_WIDTH" => self.geometric.area.width = parse(key, value)?,
+            "AREA_HEIGHT" => self.geometric.area.height = parse(key, value)?,
+            "AREA_DEPTH" => self.geometric.area.depth = parse(key, value)?,
+            "PLACEMENT" => self.geometric.placement = parse(key, value)?,
+            "RADIO_RANGE" => self.geometric.radio_range = parse(key, value)?,
+            "NUMBER_OF_CLUSTERS" => self.geometric.number_of_clusters = parse(key, value)?,
+            "CLUSTER_SPREAD" => self.geometric.cluster_spread = parse(key, value)?,
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.number_of_gateways < 0 || self.number_of_gateways > self.number_of_nodes {
+            return Err(out_of_range(
+                "NUMBER_OF_GATEWAYS",
+                "must be between 0 and the number of nodes",
+            ));
+        }
+        if self.number_of_max_possible_neighbors < self.number_of_min_possible_neighbors {
+            return Err(out_of_range(
+                "NUMBER_OF_MAX_POSSIBLE_NEIGHBORS",
+                "must not be smaller than NUMBER_OF_MIN_POSSIBLE_NEIGHBORS",
+            ));
+        }
+        if self.topology == TopologyKind::Geometric && self.geometric.radio_range == 0.0 {
+            return Err(out_of_range("RADIO_RANGE", "must be greater than 0"));
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        let value = match key {
+            "NUMBER_OF_NODES" => self.number_of_nodes.to_string(),
+            "NUMBER_OF_GATEWAYS" => self.number_of_gateways.to_string(),
+            "NUMBER_OF_MIN_POSSIBLE_NEIGHBORS" => self.number_of_min_possible_neighbors.to_string(),
+            "NUMBER_OF_MAX_POSSIBLE_NEIGHBORS" => self.number_of_max_possible_neighbors.to_string(),
+            "NUMBER_OF_GATEWAY_MEMBERS" => self.number_of_gateway_members.to_string(),
+            "TOPOLOGY" => self.topology.to_string(),
+            "AREA_WIDTH" => self.geometric.area.width.to_string(),
+            "AREA_HEIGHT" => self.geometric.area.height.to_string(),
+            "AREA_DEPTH" => self.geometric.area.depth.to_string(),
+            "PLACEMENT" => self.geometric.placement.to_string(),
+            "RADIO_RANGE" => self.geometric.radio_range.to_string(),
+            "NUMBER_OF_CLUSTERS" => self.geometric.number_of_clusters.to_string(),
+            "CLUSTER_SPREAD" => self.geometric.cluster_spread.to_string(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // Generate one topology following this configuration, with its gateway clusters assigned
+    pub fn build_network<R: Rng + ?Sized>(&self, rng: &mut R) -> NodesVec {
+        let mut nodes = match self.topology {
+            TopologyKind::Random => initialize_network(
+                self.number_of_nodes,
+                self.number_of_gateways,
+                self.number_of_min_possible_neighbors,
+                self.number_of_max_possible_neighbors,
+                rng,
+            ),
+            TopologyKind::Geometric => {
+                self.geometric
+                    .build(self.number_of_nodes, self.number_of_gateways, rng)
+            }
+        };
+        assign_gateway_clusters(&mut nodes, self.number_of_gateway_members as usize, rng);
+        nodes
+    }
+
+    // Add a node to a built network, placed and wired the way the topology does it
+    pub fn add_node<R: Rng + ?Sized>(
+        &self,
+        nodes: &mut NodesVec,
+        kind: NodeType,
+        rng: &mut R,
+    ) -> usize {
+        let attachment = match self.topology {
+            TopologyKind::Random => Attachment::Random {
+                max_possible_neighbors: rng.gen_range(
+                    self.number_of_min_possible_neighbors..=self.number_of_max_possible_neighbors,
+                ) as usize,
+            },
+            TopologyKind::Geometric => Attachment::InRange {
+                position: self.geometric.joining_position(rng),
+                radio_range: self.geometric.radio_range,
+            },
+        };
+        nodes.add_node(
+            kind,
+            attachment,
+            self.number_of_gateway_members as usize,
+            rng,
+        )
+    }
+}
+
+// Parameters the experiment runner hands to the schemes rather than to the topology
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExperimentParameters {
+    // MAC size of the pairwise messages, AES block size for pool_hash
+    pub mac_size: u32,
+    // Adversary of the resilience metrics
+    pub captured_nodes: u32,
+    pub capture_target: CaptureTarget,
+}
+
+// Defaults come from the schema
+impl Default for ExperimentParameters {
+    fn default() -> Self {
+        let mut parameters = Self {
+            mac_size: 0,
+            captured_nodes: 0,
+            capture_target: CaptureTarget::Nodes,
+        };
+        for parameter in schema::section(Section::Experiment) {
+            if let Some(default) = parameter.default {
+                parameters
+                    .set(parameter.key, default)
+                    .expect("schema defaults are valid");
+            }
+        }
+        parameters
+    }
+}
+
+impl ExperimentParameters {
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        schema::require(key)?.check(value)?;
+        match key {
+            "MAC_SIZE" => self.mac_size = parse(key, value)?,
+            "CAPTURED_NODES" => self.captured_nodes = parse(key, value)?,
+            "CAPTURE_TARGET" => self.capture_target = parse(key, value)?,
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        let value = match key {
+            "MAC_SIZE" => self.mac_size.to_string(),
+            "CAPTURED_NODES" => self.captured_nodes.to_string(),
+            "CAPTURE_TARGET" => self.capture_target.to_string(),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+// Every parameter with its value, in schema order, as lines for the log
+pub fn effective_configuration(
+    config: &SimulationConfig,
+    network: &NetworkConfig,
+    parameters: &ExperimentParameters,
+) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::with_capacity(schema::PARAMETERS.len());
+    for parameter in schema::PARAMETERS.iter() {
+        let value = match parameter.section {
+            Section::Simulation => config.get(parameter.key),
+            Section::Network => network.get(parameter.key),
+            Section::Experiment => parameters.get(parameter.key),
+        };
+        let value = value.unwrap_or_default();
+        if parameter.unit.is_empty() {
+            lines.push(format!("{} = {}", parameter.key, value));
+        } else {
+            lines.push(format!("{} = {} {}", parameter.key, value, parameter.unit));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup<'a>(values: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |key| {
+            values
+                .iter()
+                .find(|(candidate, _)| *candidate == key)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    const NETWORK: &[(&str, &str)] = &[
+        ("NUMBER_OF_NODES", "50"),
+        ("NUMBER_OF_GATEWAYS", "5"),
+        ("NUMBER_OF_MIN_POSSIBLE_NEIGHBORS", "2"),
+        ("NUMBER_OF_MAX_POSSIBLE_NEIGHBORS", "6"),
+        ("NUMBER_OF_GATEWAY_MEMBERS", "10"),
+    ];
+
+    #[test]
+    fn unset_keys_fall_back_to_the_schema_defaults() {
+        let config = SimulationConfig::from_lookup(lookup(&[("HASH_ENERGY", "0.5")])).unwrap();
+        assert_eq!(config.hash_energy, 0.5);
+        assert_eq!(config.key_pool_size, 1000);
+        assert_eq!(config.radio, RadioKind::Flat);
+        // Every key reads back the value it was set to
+        for parameter in schema::section(Section::Simulation) {
+            let value = config.get(parameter.key).unwrap();
+            let mut copy = config.clone();
+            copy.set(parameter.key, &value).unwrap();
+            assert_eq!(copy, config, "{}", parameter.key);
+        }
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let config = |values| SimulationConfig::from_lookup(lookup(values));
+        assert!(matches!(
+            config(&[("HASH_ENERGY", "cheap")]),
+            Err(ConfigError::Invalid { .. })
+        ));
+        assert!(matches!(
+            config(&[("KEY_POOL_SIZE", "0")]),
+            Err(ConfigError::OutOfRange { .. })
+        ));
+        // Counts must be whole numbers
+        assert!(matches!(
+            config(&[("KEY_RING_SIZE", "2.5")]),
+            Err(ConfigError::OutOfRange { .. })
+        ));
+        assert!(matches!(
+            config(&[("KEY_RING_SIZE", "20"), ("KEY_POOL_SIZE", "10")]),
+            Err(ConfigError::OutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn network_keys_without_a_default_are_required() {
+        let network = NetworkConfig::from_lookup(lookup(NETWORK)).unwrap();
+        assert_eq!(network.number_of_nodes, 50);
+        assert_eq!(network.topology, TopologyKind::Random);
+        assert_eq!(
+            NetworkConfig::from_lookup(lookup(&NETWORK[1..])),
+            Err(ConfigError::Missing("NUMBER_OF_NODES".to_string()))
+        );
+        let mut too_many_gateways = NETWORK.to_vec();
+        too_many_gateways[1] = ("NUMBER_OF_GATEWAYS", "60");
+        assert!(NetworkConfig::from_lookup(lookup(&too_many_gateways)).is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use std::fmt;
+
+use super::{out_of_range, ConfigError};
+
+// Which configuration a parameter belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Simulation,
+    Network,
+    // Handed by the experiment runner to the schemes
+    Experiment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueRange {
+    // Finite number greater than or equal to 0
+    NonNegative,
+    // Finite number greater than 0
+    Positive,
+    // Integer greater than or equal to 0
+    Count,
+    // Integer greater than 0
+    PositiveCount,
+    // Number between 0 and 1
+    Probability,
+    OneOf(&'static [&'static str]),
+}
+
+impl fmt::Display for ValueRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueRange::NonNegative => write!(f, ">= 0"),
+            ValueRange::Positive => write!(f, "> 0"),
+            ValueRange::Count => write!(f, "integer >= 0"),
+            ValueRange::PositiveCount => write!(f, "integer > 0"),
+            ValueRange::Probability => write!(f, "between 0 and 1"),
+            ValueRange::OneOf(values) => write!(f, "one of {}", values.join(", ")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parameter {
+    pub key: &'static str,
+    pub section: Section,
+    pub unit: &'static str,
+    // Required when there is no default
+    pub default: Option<&'static str>,
+    pub range: ValueRange,
+    pub description: &'static str,
+}
+
+impl Parameter {
+    pub fn is_integer(&self) -> bool {
+        matches!(self.range, ValueRange::Count | ValueRange::PositiveCount)
+    }
+
+    // Text of a numeric value as the parameter parses it, counts are written without a fraction
+    pub fn format_number(&self, value: f64) -> String {
+        if self.is_integer() && (value - value.round()).abs() < 1e-9 {
+            format!("{}", value.round() as i64)
+        } else {
+            value.to_string()
+        }
+    }
+
+    // Reject values outside of the valid range, the type is checked when the value is parsed
+    pub fn check(&self, value: &str) -> Result<(), ConfigError> {
+        let value = value.trim();
+        match self.range {
+            ValueRange::OneOf(values) => {
+                if !values.contains(&value.to_lowercase().as_str()) {
+                    return Err(out_of_range(self.key, &format!("must be {}", self.range)));
+                }
+            }
+            ValueRange::NonNegative
+            | ValueRange::Positive
+            | ValueRange::Count
+            | ValueRange::PositiveCount
+            | ValueRange::Probability => {
+                let number: f64 = value.parse().map_err(|_| ConfigError::Invalid {
+                    key: self.key.to_string(),
+                    value: value.to_string(),
+                })?;
+                let valid = number.is_finite()
+                    && match self.range {
+                        ValueRange::Positive => number > 0.0,
+                        ValueRange::PositiveCount => number > 0.0 && number.fract() == 0.0,
+                        ValueRange::Count => number >= 0.0 && number.fract() == 0.0,
+                        ValueRange::Probability => (0.0..=1.0).contains(&number),
+                        _ => number >= 0.0,
+                    };
+                if !valid {
+                    let reason = if self.is_integer() {
+                        format!("must be an {}", self.range)
+                    } else {
+                        format!("must be a finite number {}", self.range)
+                    };
+                    return Err(out_of_range(self.key, &reason));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+const fn parameter(
+    key: &'static str,
+    section: Section,
+    unit: &'static str,
+    default: Option<&'static str>,
+    range: ValueRange,
+    description: &'static str,
+) -> Parameter {
+    Parameter {
+        key,
+        section,
+        unit,
+        default,
+        range,
+        description,
+    }
+}
+
+use Section::{Experiment, Network, Simulation};
+use ValueRange::{Count, NonNegative, OneOf, Positive, PositiveCount, Probability};
+
+// Every parameter read from the environment, an experiment spec or the command line
+#[rustfmt::skip]
+pub const PARAMETERS: &[Parameter] = &[
+    parameter("EPSB", Simulation, "J/byte", Some("0.0001"), NonNegative, "energy to send a byte with the flat radio model"),
+    parameter("EPRB", Simulation, "J/byte", Some("0.0001"), NonNegative, "energy to receive a byte with the flat radio model"),
+    parameter("HASH_ENERGY", Simulation, "J", Some("0.0001"), NonNegative, "energy of a hash"),
+    parameter("ENCRYPTION_ENERGY", Simulation, "J", Some("0.0001"), NonNegative, "energy of an encryption"),
+    parameter("DECRYPTION_ENERGY", Simulation, "J", Some("0.0001"), NonNegative, "energy of a decryption"),
+    parameter("MULTIPLICATION_ENERGY", Simulation, "J", Some("0.0001"), NonNegative, "energy of a modular multiplication of 64-bit field elements, larger fields cost more"),
+    parameter("KEY_RING_SIZE", Simulation, "keys", Some("58"), Count, "keys predistributed to every node"),
+    parameter("KEY_POOL_SIZE", Simulation, "keys", Some("1000"), PositiveCount, "keys of the pool the rings are drawn from"),
+    parameter("AES_BLOCK_SIZE", Simulation, "bytes", Some("16"), PositiveCount, "block size encrypted messages are padded to"),
+    parameter("MATRIX_LAMBDA", Simulation, "nodes", Some("10"), Count, "collusion threshold of the matrix scheme"),
+    parameter("MATRIX_FIELD_BITS", Simulation, "bits", Some("64"), PositiveCount, "size of the field elements of the matrix scheme"),
+    parameter("PATH_KEY_MAX_HOPS", Simulation, "hops", Some("3"), Count, "longest secured path a path key may travel"),
+    parameter("RADIO_MODEL", Simulation, "", Some("flat"), OneOf(&["flat", "first_order"]), "radio energy model"),
+    parameter("RADIO_ELECTRONICS_ENERGY", Simulation, "J/bit", Some("0.00000005"), NonNegative, "first-order model electronics energy"),
+    parameter("RADIO_FREE_SPACE_AMPLIFIER", Simulation, "J/bit/m²", Some("0.00000000001"), NonNegative, "first-order model free space amplifier energy"),
+    parameter("RADIO_MULTIPATH_AMPLIFIER", Simulation, "J/bit/m⁴", Some("0.0000000000000013"), Positive, "first-order model multipath amplifier energy"),
+    parameter("DEFAULT_LINK_DISTANCE", Simulation, "m", Some("20"), NonNegative, "link length assumed for nodes without coordinates"),
+    parameter("LINK_DATA_RATE", Simulation, "bit/s", Some("250000"), Positive, "radio bit rate of the protocol simulation"),
+    parameter("PROCESSING_DELAY", Simulation, "s", Some("0.001"), NonNegative, "time a node takes to handle one received message"),
+    parameter("MAC_BACKOFF", Simulation, "s", Some("0.005"), NonNegative, "longest random wait before a transmission"),
+    parameter("LOSS_MODEL", Simulation, "", Some("none"), OneOf(&["none", "constant", "distance"]), "frame loss model of the protocol simulation, closed-form traffic metrics require none and the protocol_* metrics replace them"),
+    parameter("LINK_LOSS_PROBABILITY", Simulation, "", Some("0"), Probability, "probability that a frame is lost, at any distance"),
+    parameter("LOSS_HALF_DISTANCE", Simulation, "m", Some("30"), Positive, "distance at which the distance model loses half of the frames"),
+    parameter("MAX_RETRANSMISSIONS", Simulation, "frames", Some("3"), Count, "retransmissions of an unacknowledged frame before giving up"),
+    parameter("ACK_TIMEOUT", Simulation, "s", Some("0.01"), Positive, "wait for the acknowledgement of a frame before retransmitting it"),
+    parameter("LINK_ACK_SIZE", Simulation, "bytes", Some("5"), Count, "size of a link-layer acknowledgement"),
+    parameter("REKEY_GROUP_INTERVAL", Simulation, "rounds", Some("10"), Count, "rounds between scheduled group key refreshes, 0 for never"),
+    parameter("REKEY_PAIRWISE_INTERVAL", Simulation, "rounds", Some("0"), Count, "rounds between scheduled pairwise key refreshes, 0 for never"),
+    parameter("MEMBERSHIP_CHANGE_RATE", Simulation, "", Some("0"), Probability, "probability that a cluster changes membership in a round"),
+    parameter("COMPROMISE_RATE", Simulation, "", Some("0"), Probability, "probability that a constrained node is found compromised in a round"),
+    parameter("SENT_MESSAGE_SIZE", Simulation, "bytes", Some("16"), Count, "group key acknowledgement size"),
+    parameter("RECEIVED_MESSAGE_SIZE", Simulation, "bytes", Some("16"), Count, "group key message size"),
+    parameter("NODE_ID_SIZE", Simulation, "bytes", Some("4"), Count, "size of a node id"),
+    parameter("MESSAGE_TYPE_SIZE", Simulation, "bytes", Some("1"), Count, "size of a message type"),
+    parameter("NONCE_SIZE", Simulation, "bytes", Some("4"), Count, "size of a nonce"),
+    parameter("NUMBER_OF_NODES", Network, "nodes", None, PositiveCount, "nodes in the network including gateways"),
+    parameter("NUMBER_OF_GATEWAYS", Network, "nodes", None, Count, "gateways in the network"),
+    parameter("NUMBER_OF_MIN_POSSIBLE_NEIGHBORS", Network, "nodes", None, Count, "smallest degree target of the random topology"),
+    parameter("NUMBER_OF_MAX_POSSIBLE_NEIGHBORS", Network, "nodes", None, Count, "largest degree target of the random topology"),
+    parameter("NUMBER_OF_GATEWAY_MEMBERS", Network, "nodes", None, Count, "constrained nodes a gateway cluster can hold"),
+    parameter("TOPOLOGY", Network, "", Some("random"), OneOf(&["random", "geometric"]), "topology generator"),
+    parameter("AREA_WIDTH", Network, "m", Some("100"), NonNegative, "width of the geometric deployment area"),
+    parameter("AREA_HEIGHT", Network, "m", Some("100"), NonNegative, "height of the geometric deployment area"),
+    parameter("AREA_DEPTH", Network, "m", Some("0"), NonNegative, "depth of the geometric deployment area (0 for planar)"),
+    parameter("PLACEMENT", Network, "", Some("uniform"), OneOf(&["uniform", "grid", "clustered"]), "placement of the geometric nodes"),
+    parameter("RADIO_RANGE", Network, "m", Some("20"), NonNegative, "distance under which geometric nodes are neighbors"),
+    parameter("NUMBER_OF_CLUSTERS", Network, "clusters", Some("5"), Count, "centers of the clustered placement"),
+    parameter("CLUSTER_SPREAD", Network, "m", Some("10"), NonNegative, "standard deviation around a cluster center"),
+    parameter("MAC_SIZE", Experiment, "bytes", Some("16"), Count, "MAC size (AES block size for pool_hash)"),
+    parameter("CAPTURED_NODES", Experiment, "nodes", Some("0"), Count, "nodes captured by the adversary of the resilience metrics"),
+    parameter("CAPTURE_TARGET", Experiment, "", Some("nodes"), OneOf(&["nodes", "gateways"]), "kind of node the adversary captures"),
+];
+
+pub fn find(key: &str) -> Option<&'static Parameter> {
+    PARAMETERS.iter().find(|parameter| parameter.key == key)
+}
+
+pub fn require(key: &str) -> Result<&'static Parameter, ConfigError> {
+    find(key).ok_or_else(|| ConfigError::UnknownKey(key.to_string()))
+}
+
+pub fn section(section: Section) -> impl Iterator<Item = &'static Parameter> {
+    PARAMETERS
+        .iter()
+        .filter(move |parameter| parameter.section == section)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// Known key a misspelled one most likely stands for
+pub fn closest(key: &str) -> Option<&'static str> {
+    let key = key.to_uppercase();
+    PARAMETERS
+        .iter()
+        .map(|parameter| (edit_distance(&key, parameter.key), parameter.key))
+        .filter(|&(distance, candidate)| {
+            distance <= 3 || candidate.ends_with(key.as_str()) || key.ends_with(candidate)
+        })
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_out_of_range(key: &str, value: &str) -> bool {
+        matches!(
+            require(key).unwrap().check(value),
+            Err(ConfigError::OutOfRange { .. })
+        )
+    }
+
+    #[test]
+    fn numeric_ranges_bound_the_values() {
+        // NonNegative
+        assert!(require("EPSB").unwrap().check("0").is_ok());
+        assert!(is_out_of_range("EPSB", "-0.1"));
+        assert!(is_out_of_range("EPSB", "inf"));
+        // Positive
+        assert!(require("LINK_DATA_RATE").unwrap().check("0.5").is_ok());
+        assert!(is_out_of_range("LINK_DATA_RATE", "0"));
+        // Count
+        assert!(require("KEY_RING_SIZE").unwrap().check(" 0 ").is_ok());
+        assert!(is_out_of_range("KEY_RING_SIZE", "-1"));
+        assert!(is_out_of_range("KEY_RING_SIZE", "2.5"));
+        // PositiveCount
+        assert!(require("KEY_POOL_SIZE").unwrap().check("1").is_ok());
+        assert!(is_out_of_range("KEY_POOL_SIZE", "0"));
+        assert!(is_out_of_range("KEY_POOL_SIZE", "2.5"));
+        // Probability
+        assert!(require("COMPROMISE_RATE").unwrap().check("0").is_ok());
+        assert!(require("COMPROMISE_RATE").unwrap().check("1").is_ok());
+        assert!(is_out_of_range("COMPROMISE_RATE", "1.01"));
+        assert!(is_out_of_range("COMPROMISE_RATE", "-0.01"));
+    }
+
+    #[test]
+    fn text_that_is_not_a_number_is_invalid() {
+        assert_eq!(
+            require("MAC_SIZE").unwrap().check("eight"),
+            Err(ConfigError::Invalid {
+                key: "MAC_SIZE".to_string(),
+                value: "eight".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn choices_ignore_case() {
+        let radio = require("RADIO_MODEL").unwrap();
+        assert!(radio.check("first_order").is_ok());
+        assert!(radio.check("FLAT").is_ok());
+        assert!(is_out_of_range("RADIO_MODEL", "free_space"));
+    }
+
+    #[test]
+    fn every_default_is_in_range() {
+        for parameter in PARAMETERS.iter() {
+            if let Some(default) = parameter.default {
+                assert!(parameter.check(default).is_ok(), "{}", parameter.key);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(find("EPSB").is_some());
+        assert!(find("epsb").is_none());
+        assert_eq!(
+            require("KEY_RINGS"),
+            Err(ConfigError::UnknownKey("KEY_RINGS".to_string()))
+        );
+    }
+
+    #[test]
+    fn misspelled_keys_get_a_suggestion() {
+        assert_eq!(closest("KEY_RING_SIZ"), Some("KEY_RING_SIZE"));
+        assert_eq!(closest("mac_size"), Some("MAC_SIZE"));
+        assert_eq!(closest("MATRIX_LAMDA"), Some("MATRIX_LAMBDA"));
+        // Suffix of a key, too far from it to be a typo
+        assert_eq!(closest("LOSS_PROBABILITY"), Some("LINK_LOSS_PROBABILITY"));
+        assert_eq!(closest("BATTERY_CAPACITY"), None);
+    }
+
+    #[test]
+    fn counts_are_written_without_a_fraction() {
+        assert_eq!(require("MAC_SIZE").unwrap().format_number(16.0), "16");
+        assert_eq!(require("EPSB").unwrap().format_number(16.0), "16");
+        assert_eq!(require("EPSB").unwrap().format_number(0.5), "0.5");
+    }
+}
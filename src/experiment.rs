@@ -0,0 +1,900 @@
+use std::{collections::BTreeMap, error::Error, fmt, fs, path::Path};
+
+use rand::seq::SliceRandom;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{
+        effective_configuration,
+        schema::{self, Section},
+        ConfigError, ExperimentParameters, NetworkConfig, SimulationConfig,
+    },
+    membership::{draw_membership_events, join_cost, leave_cost, MembershipCost},
+    methods::{all_schemes, scheme_by_name, KeyDiscovery, KeyManagementScheme, KeyMaterial},
+    resilience::{capture_nodes, group_exposure, Exposure},
+    results::{ExperimentResults, Measurement, RunMetadata},
+    revocation::{revoke, RevocationReport},
+    rng::{derive_seed, random_seed, rng_from_seed},
+    simulation::{simulate, LossModel, RunReport},
+    stats::Summary,
+    NodeType, NodesVec,
+};
+
+// Streams of the iteration seed the key material of the schemes, the captured nodes, the
+// protocol runs, the joining and leaving nodes and the revocations are drawn from
+pub const KEY_MATERIAL_STREAM: u64 = 1;
+const CAPTURE_STREAM: u64 = 2;
+const PROTOCOL_STREAM: u64 = 3;
+const MEMBERSHIP_STREAM: u64 = 4;
+const REVOCATION_STREAM: u64 = 5;
+
+#[derive(Debug)]
+pub enum ExperimentError {
+    Io(std::io::Error),
+    Parse(String),
+    Config(ConfigError),
+    UnknownScheme(String),
+    InvalidSweep(String),
+    // The metric is computed in closed form and cannot account for frame losses
+    LossNotModeled(&'static str),
+}
+
+impl fmt::Display for ExperimentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExperimentError::Io(error) => write!(f, "could not read experiment: {}", error),
+            ExperimentError::Parse(error) => write!(f, "could not parse experiment: {}", error),
+            ExperimentError::Config(error) => write!(f, "{}", error),
+            ExperimentError::UnknownScheme(name) => write!(f, "unknown scheme: {}", name),
+            ExperimentError::InvalidSweep(reason) => write!(f, "invalid sweep: {}", reason),
+            ExperimentError::LossNotModeled(metric) => write!(
+                f,
+                "{} assumes every frame arrives, set LOSS_MODEL=none or measure the protocol metrics",
+                metric
+            ),
+        }
+    }
+}
+
+impl Error for ExperimentError {}
+
+impl From<ConfigError> for ExperimentError {
+    fn from(error: ConfigError) -> Self {
+        ExperimentError::Config(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    NumberOfMultiplications,
+    NumberPairwiseHashes,
+    NumberOfPairwiseEncryptions,
+    PairwiseCommunicationEnergy,
+    PairwiseCommunicationSent,
+    PairwiseCommunicationReceived,
+    GroupwiseCommunicationEnergy,
+    GroupwiseEncryptionsEnergy,
+    GroupwiseHashesEnergy,
+    GroupwiseComputationEnergy,
+    GroupwiseTotalEnergy,
+    MaxNodeKeyingEnergy,
+    DirectLinkFraction,
+    PathKeyLinkFraction,
+    UnsecuredLinkFraction,
+    DiscoveryBytesSent,
+    PathKeyBytesSent,
+    PathKeyEnergy,
+    ExposedLinkFraction,
+    ExposedGroupFraction,
+    PairwiseMultiplicationsEnergy,
+    KeyStorageBytes,
+    CollusionThreshold,
+    CompletionTime,
+    MeanMessageLatency,
+    CompletedNodeFraction,
+    ProtocolMessages,
+    ProtocolBytesSent,
+    ProtocolEnergy,
+    ProtocolBytesReceived,
+    Retransmissions,
+    LostFrames,
+    UnsettledLinks,
+    JoinEnergy,
+    JoinBytesSent,
+    JoinMessages,
+    JoinHashes,
+    JoinEncryptions,
+    LeaveEnergy,
+    LeaveBytesSent,
+    LeaveMessages,
+    LeaveHashes,
+    LeaveEncryptions,
+    RevocationEnergy,
+    RevocationBytesSent,
+    RevocationMessages,
+    RevokedNodeIsolated,
+}
+
+impl Metric {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::NumberOfMultiplications => "number_of_multiplications",
+            Metric::NumberPairwiseHashes => "number_pairwise_hashes",
+            Metric::NumberOfPairwiseEncryptions => "number_of_pairwise_encryptions",
+            Metric::PairwiseCommunicationEnergy => "pairwise_communication_energy",
+            Metric::PairwiseCommunicationSent => "pairwise_communication_sent",
+            Metric::PairwiseCommunicationReceived => "pairwise_communication_received",
+            Metric::GroupwiseCommunicationEnergy => "groupwise_communication_energy",
+            Metric::GroupwiseEncryptionsEnergy => "groupwise_encryptions_energy",
+            Metric::GroupwiseHashesEnergy => "groupwise_hashes_energy",
+            Metric::GroupwiseComputationEnergy => "groupwise_computation_energy",
+            Metric::GroupwiseTotalEnergy => "groupwise_total_energy",
+            Metric::MaxNodeKeyingEnergy => "max_node_keying_energy",
+            Metric::DirectLinkFraction => "direct_link_fraction",
+            Metric::PathKeyLinkFraction => "path_key_link_fraction",
+            Metric::UnsecuredLinkFraction => "unsecured_link_fraction",
+            Metric::DiscoveryBytesSent => "discovery_bytes_sent",
+            Metric::PathKeyBytesSent => "path_key_bytes_sent",
+            Metric::PathKeyEnergy => "path_key_energy",
+            Metric::ExposedLinkFraction => "exposed_link_fraction",
+            Metric::ExposedGroupFraction => "exposed_group_fraction",
+            Metric::PairwiseMultiplicationsEnergy => "pairwise_multiplications_energy",
+            Metric::KeyStorageBytes => "key_storage_bytes",
+            Metric::CollusionThreshold => "collusion_threshold",
+            Metric::CompletionTime => "completion_time",
+            Metric::MeanMessageLatency => "mean_message_latency",
+            Metric::CompletedNodeFraction => "completed_node_fraction",
+            Metric::ProtocolMessages => "protocol_messages",
+            Metric::ProtocolBytesSent => "protocol_bytes_sent",
+            Metric::ProtocolEnergy => "protocol_energy",
+            Metric::ProtocolBytesReceived => "protocol_bytes_received",
+            Metric::Retransmissions => "retransmissions",
+            Metric::LostFrames => "lost_frames",
+            Metric::UnsettledLinks => "unsettled_links",
+            Metric::JoinEnergy => "join_energy",
+            Metric::JoinBytesSent => "join_bytes_sent",
+            Metric::JoinMessages => "join_messages",
+            Metric::JoinHashes => "join_hashes",
+            Metric::JoinEncryptions => "join_encryptions",
+            Metric::LeaveEnergy => "leave_energy",
+            Metric::LeaveBytesSent => "leave_bytes_sent",
+            Metric::LeaveMessages => "leave_messages",
+            Metric::LeaveHashes => "leave_hashes",
+            Metric::LeaveEncryptions => "leave_encryptions",
+            Metric::RevocationEnergy => "revocation_energy",
+            Metric::RevocationBytesSent => "revocation_bytes_sent",
+            Metric::RevocationMessages => "revocation_messages",
+            Metric::RevokedNodeIsolated => "revoked_node_isolated",
+        }
+    }
+
+    // Metrics read from the simulated shared-key discovery
+    pub fn needs_key_discovery(&self) -> bool {
+        matches!(
+            self,
+            Metric::DirectLinkFraction
+                | Metric::PathKeyLinkFraction
+                | Metric::UnsecuredLinkFraction
+                | Metric::DiscoveryBytesSent
+                | Metric::PathKeyBytesSent
+                | Metric::PathKeyEnergy
+        )
+    }
+
+    // Metrics read from the keys exposed by captured nodes
+    pub fn needs_capture(&self) -> bool {
+        matches!(
+            self,
+            Metric::ExposedLinkFraction | Metric::ExposedGroupFraction
+        )
+    }
+
+    // Metrics read from a message-level run of the keying round
+    pub fn needs_protocol(&self) -> bool {
+        matches!(
+            self,
+            Metric::CompletionTime
+                | Metric::MeanMessageLatency
+                | Metric::CompletedNodeFraction
+                | Metric::ProtocolMessages
+                | Metric::ProtocolBytesSent
+                | Metric::ProtocolEnergy
+                | Metric::ProtocolBytesReceived
+                | Metric::Retransmissions
+                | Metric::LostFrames
+                | Metric::UnsettledLinks
+        )
+    }
+
+    // Traffic computed in closed form, which assumes every frame arrives and counts no
+    // retransmission. Under frame losses the protocol_* metrics, which do count them, replace
+    // these.
+    pub fn counts_lossless_traffic(&self) -> bool {
+        matches!(
+            self,
+            Metric::PairwiseCommunicationEnergy
+                | Metric::PairwiseCommunicationSent
+                | Metric::PairwiseCommunicationReceived
+                | Metric::GroupwiseCommunicationEnergy
+                | Metric::GroupwiseTotalEnergy
+                | Metric::MaxNodeKeyingEnergy
+                | Metric::DiscoveryBytesSent
+                | Metric::PathKeyBytesSent
+                | Metric::PathKeyEnergy
+                | Metric::JoinEnergy
+                | Metric::JoinBytesSent
+                | Metric::JoinMessages
+                | Metric::LeaveEnergy
+                | Metric::LeaveBytesSent
+                | Metric::LeaveMessages
+                | Metric::RevocationEnergy
+                | Metric::RevocationBytesSent
+                | Metric::RevocationMessages
+        )
+    }
+
+    // Metrics read from a node joining and a node leaving the network
+    pub fn needs_membership(&self) -> bool {
+        matches!(
+            self,
+            Metric::JoinEnergy
+                | Metric::JoinBytesSent
+                | Metric::JoinMessages
+                | Metric::JoinHashes
+                | Metric::JoinEncryptions
+                | Metric::LeaveEnergy
+                | Metric::LeaveBytesSent
+                | Metric::LeaveMessages
+                | Metric::LeaveHashes
+                | Metric::LeaveEncryptions
+        )
+    }
+
+    // Metrics read from the revocation of a constrained node
+    pub fn needs_revocation(&self) -> bool {
+        matches!(
+            self,
+            Metric::RevocationEnergy
+                | Metric::RevocationBytesSent
+                | Metric::RevocationMessages
+                | Metric::RevokedNodeIsolated
+        )
+    }
+
+    // `None` when the metric does not apply to the scheme
+    pub fn evaluate(
+        &self,
+        scheme: &dyn KeyManagementScheme,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        material: &KeyMaterial,
+        simulated: &Simulated,
+    ) -> Option<f64> {
+        let discovery = simulated.discovery.as_ref();
+        let protocol = simulated.protocol.as_ref();
+        let join = simulated.join.as_ref();
+        let leave = simulated.leave.as_ref();
+        let revocation = simulated.revocation.as_ref();
+        let value = match self {
+            Metric::NumberOfMultiplications => {
+                scheme.number_of_multiplications(nodes, config) as f64
+            }
+            Metric::NumberPairwiseHashes => {
+                scheme.number_pairwise_hashes(nodes, config, material) as f64
+            }
+            Metric::NumberOfPairwiseEncryptions => {
+                scheme.number_of_pairwise_encryptions(nodes, config, material) as f64
+            }
+            Metric::PairwiseCommunicationEnergy => {
+                scheme.pairwise_communication_energy(nodes, config, mac_size, material) as f64
+            }
+            Metric::PairwiseCommunicationSent => {
+                scheme.pairwise_communication_sent(nodes, config, mac_size, material) as f64
+            }
+            Metric::PairwiseCommunicationReceived => {
+                scheme.pairwise_communication_received(nodes, config, mac_size, material) as f64
+            }
+            Metric::GroupwiseCommunicationEnergy => {
+                scheme.groupwise_communication_energy(nodes, config) as f64
+            }
+            Metric::GroupwiseEncryptionsEnergy => {
+                scheme.groupwise_encryptions_energy(nodes, config) as f64
+            }
+            Metric::GroupwiseHashesEnergy => scheme.groupwise_hashes_energy(nodes, config) as f64,
+            Metric::GroupwiseComputationEnergy => {
+                scheme.groupwise_computation_energy(nodes, config) as f64
+            }
+            Metric::GroupwiseTotalEnergy => scheme.groupwise_total_energy(nodes, config) as f64,
+            // Energy of the node drained the most by a pairwise then groupwise keying round
+            Metric::MaxNodeKeyingEnergy => scheme
+                .keying_round_ledger(nodes, config, mac_size, material)
+                .most_drained()
+                .map_or(0.0, |(_, energy)| energy.total() as f64),
+            Metric::DirectLinkFraction => discovery?.direct_fraction(),
+            Metric::PathKeyLinkFraction => discovery?.path_key_fraction(),
+            Metric::UnsecuredLinkFraction => discovery?.unsecured_fraction(),
+            Metric::DiscoveryBytesSent => discovery?.discovery_bytes_sent as f64,
+            Metric::PathKeyBytesSent => discovery?.path_key_bytes_sent as f64,
+            Metric::PathKeyEnergy => discovery?.path_key_energy as f64,
+            Metric::ExposedLinkFraction => simulated.link_exposure?.fraction(),
+            Metric::ExposedGroupFraction => simulated.group_exposure?.fraction(),
+            Metric::PairwiseMultiplicationsEnergy => {
+                scheme.pairwise_multiplications_energy(nodes, config) as f64
+            }
+            Metric::KeyStorageBytes => scheme.key_storage_bytes(config)? as f64,
+            Metric::CollusionThreshold => scheme.collusion_threshold(config)? as f64,
+            // Only defined when every node completed the round
+            Metric::CompletionTime => protocol?.completion_time?,
+            Metric::MeanMessageLatency => protocol?.mean_latency,
+            Metric::CompletedNodeFraction => protocol?.completed_fraction(),
+            Metric::ProtocolMessages => protocol?.messages_sent as f64,
+            Metric::ProtocolBytesSent => protocol?.bytes_sent as f64,
+            Metric::ProtocolEnergy => protocol?.ledger.total().total() as f64,
+            Metric::ProtocolBytesReceived => protocol?.bytes_received as f64,
+            Metric::Retransmissions => protocol?.retransmissions as f64,
+            Metric::LostFrames => protocol?.lost_frames as f64,
+            // Links left without a key once the retry budget is spent
+            Metric::UnsettledLinks => protocol?.unsettled_links as f64,
+            Metric::JoinEnergy => join?.energy,
+            Metric::JoinBytesSent => join?.bytes_sent,
+            Metric::JoinMessages => join?.operations.messages as f64,
+            Metric::JoinHashes => join?.operations.hashes as f64,
+            Metric::JoinEncryptions => join?.operations.encryptions as f64,
+            // Only defined when some constrained node belongs to a cluster
+            Metric::LeaveEnergy => leave?.energy,
+            Metric::LeaveBytesSent => leave?.bytes_sent,
+            Metric::LeaveMessages => leave?.operations.messages as f64,
+            Metric::LeaveHashes => leave?.operations.hashes as f64,
+            Metric::LeaveEncryptions => leave?.operations.encryptions as f64,
+            // Flood and key replacement together
+            Metric::RevocationEnergy => revocation?.energy(),
+            Metric::RevocationBytesSent => revocation?.bytes_sent(),
+            Metric::RevocationMessages => {
+                (revocation?.flood.operations.messages + revocation?.rekey.operations.messages)
+                    as f64
+            }
+            // 1 when no active node can talk to the revoked node any more, 0 otherwise
+            Metric::RevokedNodeIsolated => {
+                if revocation?.is_isolated() {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        };
+        Some(value)
+    }
+}
+
+// Outcomes of the randomized simulations of one scheme on one topology, shared by its metrics
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Simulated {
+    pub discovery: Option<KeyDiscovery>,
+    pub link_exposure: Option<Exposure>,
+    pub group_exposure: Option<Exposure>,
+    pub protocol: Option<RunReport>,
+    pub join: Option<MembershipCost>,
+    pub leave: Option<MembershipCost>,
+    pub revocation: Option<RevocationReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParameterValue {
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl fmt::Display for ParameterValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParameterValue::Integer(value) => write!(f, "{}", value),
+            ParameterValue::Float(value) => write!(f, "{}", value),
+            ParameterValue::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sweep {
+    pub parameter: String,
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+}
+
+impl Sweep {
+    pub fn values(&self) -> Result<Vec<f64>, ExperimentError> {
+        if !self.step.is_finite()
+            || self.step <= 0.0
+            || !self.start.is_finite()
+            || !self.end.is_finite()
+        {
+            return Err(ExperimentError::InvalidSweep(
+                "step must be positive and bounds must be finite".to_string(),
+            ));
+        }
+        if self.end < self.start {
+            return Err(ExperimentError::InvalidSweep(
+                "end must not be smaller than start".to_string(),
+            ));
+        }
+        let count = ((self.end - self.start) / self.step + 1e-9).floor() as usize + 1;
+        Ok((0..count)
+            .map(|k| self.start + k as f64 * self.step)
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExperimentSpec {
+    pub name: String,
+    // Schemes to evaluate, every scheme when empty
+    #[serde(default)]
+    pub schemes: Vec<String>,
+    pub sweep: Sweep,
+    // Parameters overriding the environment for the whole experiment
+    #[serde(default)]
+    pub fixed: BTreeMap<String, ParameterValue>,
+    // Number of gateways as a fraction of the number of nodes, NUMBER_OF_GATEWAYS is used otherwise
+    #[serde(default)]
+    pub gateway_ratio: Option<f64>,
+    pub iterations: u32,
+    pub metrics: Vec<Metric>,
+    // Keep the value of every iteration in the results, not only their summary
+    #[serde(default)]
+    pub keep_samples: bool,
+    // Master seed of the run, a random one is drawn and recorded in the results when absent
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+impl ExperimentSpec {
+    // Load a spec from a .toml or .json file
+    pub fn from_path(path: &Path) -> Result<Self, ExperimentError> {
+        let contents = fs::read_to_string(path).map_err(ExperimentError::Io)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Self::from_json(&contents),
+            _ => Self::from_toml(&contents),
+        }
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, ExperimentError> {
+        toml::from_str(contents).map_err(|error| ExperimentError::Parse(error.to_string()))
+    }
+
+    pub fn from_json(contents: &str) -> Result<Self, ExperimentError> {
+        serde_json::from_str(contents).map_err(|error| ExperimentError::Parse(error.to_string()))
+    }
+
+    pub fn schemes(&self) -> Result<Vec<Box<dyn KeyManagementScheme>>, ExperimentError> {
+        if self.schemes.is_empty() {
+            return Ok(all_schemes());
+        }
+        self.schemes
+            .iter()
+            .map(|name| {
+                scheme_by_name(name).ok_or_else(|| ExperimentError::UnknownScheme(name.clone()))
+            })
+            .collect()
+    }
+}
+
+// Apply a named parameter to whichever configuration owns it
+fn apply_parameter(
+    key: &str,
+    value: &str,
+    config: &mut SimulationConfig,
+    network: &mut NetworkConfig,
+    parameters: &mut ExperimentParameters,
+) -> Result<(), ConfigError> {
+    match schema::require(key)?.section {
+        Section::Simulation => config.set(key, value),
+        Section::Network => network.set(key, value),
+        Section::Experiment => parameters.set(key, value),
+    }
+}
+
+pub fn run_experiment(
+    spec: &ExperimentSpec,
+    config: &SimulationConfig,
+    network: &NetworkConfig,
+) -> Result<ExperimentResults, ExperimentError> {
+    let schemes = spec.schemes()?;
+    let sweep_values = spec.sweep.values()?;
+    // Misspelled keys fail before anything runs
+    let swept = schema::require(&spec.sweep.parameter)?;
+    for key in spec.fixed.keys() {
+        schema::require(key)?;
+    }
+
+    let mut config = config.clone();
+    let mut network = network.clone();
+    let mut parameters = ExperimentParameters::default();
+    for (key, value) in spec.fixed.iter() {
+        apply_parameter(
+            key,
+            &value.to_string(),
+            &mut config,
+            &mut network,
+            &mut parameters,
+        )?;
+    }
+
+    eprintln!("Effective configuration:");
+    for line in effective_configuration(&config, &network, &parameters) {
+        eprintln!("  {}", line);
+    }
+    eprintln!(
+        "  {} is swept from {} to {} by {}",
+        spec.sweep.parameter, spec.sweep.start, spec.sweep.end, spec.sweep.step
+    );
+
+    // Every iteration gets its own seed derived from the master seed so a sweep is reproducible
+    let master_seed = spec.seed.unwrap_or_else(random_seed);
+
+    let mut measurements: Vec<Measurement> = Vec::new();
+    for (point, &sweep_value) in sweep_values.iter().enumerate() {
+        let mut point_config = config.clone();
+        let mut point_network = network.clone();
+        let mut point_parameters = parameters.clone();
+        apply_parameter(
+            &spec.sweep.parameter,
+            &swept.format_number(sweep_value),
+            &mut point_config,
+            &mut point_network,
+            &mut point_parameters,
+        )?;
+        let point_mac_size = point_parameters.mac_size;
+        if let Some(ratio) = spec.gateway_ratio {
+            point_network.number_of_gateways =
+                (point_network.number_of_nodes as f64 * ratio).round() as i32;
+        }
+        point_config.validate()?;
+        point_network.validate()?;
+        // Only the protocol run draws frame losses
+        if point_config.loss_model != LossModel::None {
+            if let Some(metric) = spec
+                .metrics
+                .iter()
+                .find(|metric| metric.counts_lossless_traffic())
+            {
+                return Err(ExperimentError::LossNotModeled(metric.name()));
+            }
+        }
+
+        eprintln!(
+            "Simulation {}: {} = {}, {} iterations",
+            spec.name, spec.sweep.parameter, sweep_value, spec.iterations
+        );
+        let needs_key_discovery = spec
+            .metrics
+            .iter()
+            .any(|metric| metric.needs_key_discovery());
+        let needs_capture = spec.metrics.iter().any(|metric| metric.needs_capture());
+        let needs_protocol = spec.metrics.iter().any(|metric| metric.needs_protocol());
+        let needs_membership = spec.metrics.iter().any(|metric| metric.needs_membership());
+        let needs_revocation = spec.metrics.iter().any(|metric| metric.needs_revocation());
+        // Iterations run in parallel, each one evaluates every scheme and metric on its own topology
+        let iterations: Vec<Vec<Option<f64>>> = (0..spec.iterations)
+            .into_par_iter()
+            .map(|iteration| {
+                let seed = derive_seed(master_seed, point as u64, iteration as u64);
+                let nodes: NodesVec = point_network.build_network(&mut rng_from_seed(seed));
+                // Every scheme faces the same captured nodes
+                let captured = needs_capture.then(|| {
+                    capture_nodes(
+                        &nodes,
+                        point_parameters.capture_target,
+                        point_parameters.captured_nodes as usize,
+                        &mut rng_from_seed(derive_seed(seed, CAPTURE_STREAM, 0)),
+                    )
+                });
+                // Every scheme faces the same joining and leaving nodes
+                let membership = needs_membership.then(|| {
+                    draw_membership_events(
+                        &nodes,
+                        &point_network,
+                        &mut rng_from_seed(derive_seed(seed, MEMBERSHIP_STREAM, 0)),
+                    )
+                });
+                // Every scheme revokes the same node
+                let revoked = if needs_revocation {
+                    let constrained: Vec<usize> = nodes
+                        .iter()
+                        .filter(|node| node.kind == NodeType::Constrained)
+                        .map(|node| node.id)
+                        .collect();
+                    constrained
+                        .choose(&mut rng_from_seed(derive_seed(seed, REVOCATION_STREAM, 0)))
+                        .copied()
+                } else {
+                    None
+                };
+                let mut values: Vec<Option<f64>> =
+                    Vec::with_capacity(schemes.len() * spec.metrics.len());
+                for scheme in schemes.iter() {
+                    // Key material comes from a stream of its own so the topology does not depend
+                    // on it, each scheme drawing from a fresh copy so its keys do not depend on
+                    // the other schemes. Every metric of the scheme reads the same keys.
+                    let material = scheme.predistribute(
+                        &nodes,
+                        &point_config,
+                        &mut rng_from_seed(derive_seed(seed, KEY_MATERIAL_STREAM, 0)),
+                    );
+                    let mut simulated = Simulated::default();
+                    if needs_key_discovery {
+                        simulated.discovery =
+                            scheme.key_discovery(&nodes, &point_config, point_mac_size, &material);
+                    }
+                    if let Some(captured) = &captured {
+                        simulated.link_exposure =
+                            Some(scheme.link_exposure(&nodes, &point_config, captured, &material));
+                        simulated.group_exposure = Some(group_exposure(&nodes, captured));
+                    }
+                    if needs_protocol {
+                        // Each scheme draws its backoffs and losses from a fresh copy of the stream
+                        let mut protocol_rng = rng_from_seed(derive_seed(seed, PROTOCOL_STREAM, 0));
+                        let mut protocol =
+                            scheme.protocol(&nodes, &point_config, point_mac_size, &material);
+                        simulated.protocol = Some(simulate(
+                            &nodes,
+                            &point_config,
+                            protocol.as_mut(),
+                            &mut protocol_rng,
+                        ));
+                    }
+                    if let Some(events) = &membership {
+                        // The joiner is loaded with keys before deployment too, drawn from the
+                        // same stream over the network it joined
+                        let joined_material = scheme.predistribute(
+                            &events.joined,
+                            &point_config,
+                            &mut rng_from_seed(derive_seed(seed, KEY_MATERIAL_STREAM, 0)),
+                        );
+                        simulated.join = Some(join_cost(
+                            scheme.as_ref(),
+                            events,
+                            &point_config,
+                            point_mac_size,
+                            &joined_material,
+                        ));
+                        simulated.leave = leave_cost(scheme.as_ref(), events, &point_config);
+                    }
+                    if let Some(node) = revoked {
+                        let (_, report) = revoke(
+                            scheme.as_ref(),
+                            &nodes,
+                            &point_config,
+                            point_mac_size,
+                            node,
+                            &material,
+                        );
+                        simulated.revocation = Some(report);
+                    }
+                    for metric in spec.metrics.iter() {
+                        values.push(metric.evaluate(
+                            scheme.as_ref(),
+                            &nodes,
+                            &point_config,
+                            point_mac_size,
+                            &material,
+                            &simulated,
+                        ));
+                    }
+                }
+                values
+            })
+            .collect();
+        // Results are collected in iteration order so the samples do not depend on scheduling
+        let mut samples: Vec<Vec<Vec<Option<f64>>>> =
+            vec![
+                vec![Vec::with_capacity(spec.iterations as usize); spec.metrics.len()];
+                schemes.len()
+            ];
+        for values in iterations.iter() {
+            for (s, scheme_samples) in samples.iter_mut().enumerate() {
+                for (m, metric_samples) in scheme_samples.iter_mut().enumerate() {
+                    metric_samples.push(values[s * spec.metrics.len() + m]);
+                }
+            }
+        }
+        for (s, scheme) in schemes.iter().enumerate() {
+            for (m, metric) in spec.metrics.iter().enumerate() {
+                let values: Vec<f64> = samples[s][m].iter().flatten().copied().collect();
+                // The metric does not apply to this scheme
+                if values.is_empty() && spec.iterations > 0 {
+                    continue;
+                }
+                measurements.push(Measurement {
+                    scheme: scheme.name(),
+                    sweep_parameter: spec.sweep.parameter.clone(),
+                    sweep_value,
+                    metric: *metric,
+                    summary: Summary::from_samples(&values),
+                    missing: samples[s][m].len() - values.len(),
+                    samples: if spec.keep_samples {
+                        std::mem::take(&mut samples[s][m])
+                    } else {
+                        Vec::new()
+                    },
+                });
+            }
+        }
+    }
+    Ok(ExperimentResults {
+        metadata: RunMetadata {
+            spec: ExperimentSpec {
+                seed: Some(master_seed),
+                ..spec.clone()
+            },
+            config,
+            network,
+        },
+        rows: measurements,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = r#"
+        name = "spec"
+        iterations = 4
+        metrics = ["number_of_multiplications", "protocol_energy"]
+
+        [fixed]
+        NUMBER_OF_NODES = 40
+        LINK_LOSS_PROBABILITY = 0.25
+        LOSS_MODEL = "none"
+
+        [sweep]
+        parameter = "MAC_SIZE"
+        start = 4
+        end = 16
+        step = 4
+    "#;
+
+    #[test]
+    fn specs_parse_with_their_defaults() {
+        let spec = ExperimentSpec::from_toml(SPEC).unwrap();
+        assert_eq!(spec.name, "spec");
+        assert!(spec.schemes.is_empty());
+        assert_eq!(spec.schemes().unwrap().len(), all_schemes().len());
+        assert_eq!(
+            spec.metrics,
+            vec![Metric::NumberOfMultiplications, Metric::ProtocolEnergy]
+        );
+        assert_eq!(spec.fixed["NUMBER_OF_NODES"], ParameterValue::Integer(40));
+        assert_eq!(
+            spec.fixed["LINK_LOSS_PROBABILITY"],
+            ParameterValue::Float(0.25)
+        );
+        assert_eq!(
+            spec.fixed["LOSS_MODEL"],
+            ParameterValue::Text("none".to_string())
+        );
+        assert!(!spec.keep_samples);
+        assert_eq!(spec.seed, None);
+        assert_eq!(spec.sweep.values().unwrap(), vec![4.0, 8.0, 12.0, 16.0]);
+
+        // The same spec written as JSON
+        let json = serde_json::to_string(&spec).unwrap();
+        assert_eq!(ExperimentSpec::from_json(&json).unwrap(), spec);
+    }
+
+    #[test]
+    fn malformed_specs_are_rejected() {
+        let unknown_metric = SPEC.replace("protocol_energy", "protocol_joules");
+        assert!(matches!(
+            ExperimentSpec::from_toml(&unknown_metric),
+            Err(ExperimentError::Parse(_))
+        ));
+        let unknown_scheme = format!("schemes = [\"blom\"]\n{}", SPEC);
+        assert!(matches!(
+            ExperimentSpec::from_toml(&unknown_scheme).unwrap().schemes(),
+            Err(ExperimentError::UnknownScheme(name)) if name == "blom"
+        ));
+    }
+
+    fn sweep(start: f64, end: f64, step: f64) -> Sweep {
+        Sweep {
+            parameter: "LINK_LOSS_PROBABILITY".to_string(),
+            start,
+            end,
+            step,
+        }
+    }
+
+    #[test]
+    fn sweeps_include_both_bounds() {
+        let values = sweep(0.0, 0.5, 0.1).values().unwrap();
+        assert_eq!(values.len(), 6);
+        assert_eq!(values[3], 3.0 * 0.1);
+        assert_eq!(sweep(2.0, 2.0, 1.0).values().unwrap(), vec![2.0]);
+        assert!(matches!(
+            sweep(1.0, 0.0, 0.1).values(),
+            Err(ExperimentError::InvalidSweep(_))
+        ));
+        assert!(matches!(
+            sweep(0.0, 1.0, 0.0).values(),
+            Err(ExperimentError::InvalidSweep(_))
+        ));
+        assert!(sweep(0.0, f64::INFINITY, 1.0).values().is_err());
+    }
+
+    // A value carrying rounding noise from a fractional step still sets an integer key
+    #[test]
+    fn swept_values_are_formatted_for_their_parameter() {
+        let nodes = schema::require("NUMBER_OF_NODES").unwrap();
+        let noisy = (0.1 + 0.2) * 100.0;
+        assert_ne!(noisy, 30.0);
+        assert_eq!(nodes.format_number(noisy), "30");
+        let mut network = NetworkConfig::from_lookup(|key| match key {
+            "NUMBER_OF_NODES" | "NUMBER_OF_GATEWAY_MEMBERS" => Some("10".to_string()),
+            "NUMBER_OF_GATEWAYS" | "NUMBER_OF_MIN_POSSIBLE_NEIGHBORS" => Some("1".to_string()),
+            "NUMBER_OF_MAX_POSSIBLE_NEIGHBORS" => Some("3".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        network
+            .set("NUMBER_OF_NODES", &nodes.format_number(noisy))
+            .unwrap();
+        assert_eq!(network.number_of_nodes, 30);
+        // A real fraction of an integer key is still an error
+        assert!(network
+            .set("NUMBER_OF_NODES", &nodes.format_number(30.5))
+            .is_err());
+        let probability = schema::require("LINK_LOSS_PROBABILITY").unwrap();
+        assert_eq!(probability.format_number(0.25), "0.25");
+    }
+
+    fn run_on_threads(threads: usize) -> ExperimentResults {
+        let spec = ExperimentSpec::from_toml(
+            r#"
+            name = "threads"
+            iterations = 8
+            gateway_ratio = 0.1
+            keep_samples = true
+            seed = 11
+            metrics = ["exposed_link_fraction", "protocol_bytes_sent", "join_energy", "revocation_energy"]
+
+            [sweep]
+            parameter = "NUMBER_OF_NODES"
+            start = 30
+            end = 50
+            step = 20
+            "#,
+        )
+        .unwrap();
+        let network = NetworkConfig::from_lookup(|key| {
+            let value = match key {
+                "NUMBER_OF_NODES" => "30",
+                "NUMBER_OF_GATEWAYS" => "3",
+                "NUMBER_OF_MIN_POSSIBLE_NEIGHBORS" => "3",
+                "NUMBER_OF_MAX_POSSIBLE_NEIGHBORS" => "6",
+                "NUMBER_OF_GATEWAY_MEMBERS" => "10",
+                _ => return None,
+            };
+            Some(value.to_string())
+        })
+        .unwrap();
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(|| run_experiment(&spec, &SimulationConfig::default(), &network).unwrap())
+    }
+
+    // Every iteration draws from streams derived from the master seed, so the scheduling of
+    // the iterations on the worker threads cannot change the results
+    #[test]
+    fn results_do_not_depend_on_the_thread_count() {
+        assert_eq!(run_on_threads(1).rows, run_on_threads(4).rows);
+    }
+}
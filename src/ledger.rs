@@ -0,0 +1,222 @@
+use std::ops::AddAssign;
+
+use serde::Serialize;
+
+use crate::{config::SimulationConfig, NodesVec};
+
+// Energy spent by a single node, split by activity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct NodeEnergy {
+    pub transmit: f32,
+    pub receive: f32,
+    pub hash: f32,
+    pub encrypt: f32,
+    pub decrypt: f32,
+    pub multiply: f32,
+}
+
+impl NodeEnergy {
+    pub fn communication(&self) -> f32 {
+        self.transmit + self.receive
+    }
+
+    pub fn computation(&self) -> f32 {
+        self.hash + self.encrypt + self.decrypt + self.multiply
+    }
+
+    pub fn total(&self) -> f32 {
+        self.communication() + self.computation()
+    }
+}
+
+impl AddAssign for NodeEnergy {
+    fn add_assign(&mut self, other: Self) {
+        self.transmit += other.transmit;
+        self.receive += other.receive;
+        self.hash += other.hash;
+        self.encrypt += other.encrypt;
+        self.decrypt += other.decrypt;
+        self.multiply += other.multiply;
+    }
+}
+
+// Messages and cryptographic operations of the whole network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct OperationCounts {
+    // Transmissions, a relayed message counted at every hop
+    pub messages: u32,
+    pub hashes: u32,
+    pub encryptions: u32,
+    pub decryptions: u32,
+    pub multiplications: u32,
+}
+
+// Energy spent by every node of a network, indexed by node id
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EnergyLedger {
+    nodes: Vec<NodeEnergy>,
+    // Traffic of the whole network, relayed messages counted at every hop
+    bytes_sent: f32,
+    bytes_received: f32,
+    operations: OperationCounts,
+}
+
+impl EnergyLedger {
+    pub fn new(number_of_nodes: usize) -> Self {
+        Self {
+            nodes: vec![NodeEnergy::default(); number_of_nodes],
+            bytes_sent: 0.0,
+            bytes_received: 0.0,
+            operations: OperationCounts::default(),
+        }
+    }
+
+    pub fn for_network(nodes: &NodesVec) -> Self {
+        Self::new(nodes.len())
+    }
+
+    // Transmission of `bytes` over a link of `distance` meters
+    pub fn transmit(&mut self, node: usize, bytes: f32, distance: f32, config: &SimulationConfig) {
+        self.nodes[node].transmit += config.transmit_energy(bytes, distance);
+        self.bytes_sent += bytes;
+        self.operations.messages += 1;
+    }
+
+    pub fn receive(&mut self, node: usize, bytes: f32, config: &SimulationConfig) {
+        self.nodes[node].receive += config.receive_energy(bytes);
+        self.bytes_received += bytes;
+    }
+
+    // A relay receives the message and transmits it again to the next hop
+    pub fn forward(&mut self, node: usize, bytes: f32, distance: f32, config: &SimulationConfig) {
+        self.receive(node, bytes, config);
+        self.transmit(node, bytes, distance, config);
+    }
+
+    pub fn hashes(&mut self, node: usize, count: u32, config: &SimulationConfig) {
+        self.nodes[node].hash += count as f32 * config.hash_energy;
+        self.operations.hashes += count;
+    }
+
+    pub fn encryptions(&mut self, node: usize, count: u32, config: &SimulationConfig) {
+        self.nodes[node].encrypt += count as f32 * config.encryption_energy;
+        self.operations.encryptions += count;
+    }
+
+    pub fn decryptions(&mut self, node: usize, count: u32, config: &SimulationConfig) {
+        self.nodes[node].decrypt += count as f32 * config.decryption_energy;
+        self.operations.decryptions += count;
+    }
+
+    pub fn multiplications(&mut self, node: usize, count: u32, config: &SimulationConfig) {
+        self.nodes[node].multiply += count as f32 * config.multiplication_energy;
+        self.operations.multiplications += count;
+    }
+
+    pub fn node(&self, node: usize) -> &NodeEnergy {
+        &self.nodes[node]
+    }
+
+    pub fn bytes_sent(&self) -> f32 {
+        self.bytes_sent
+    }
+
+    pub fn bytes_received(&self) -> f32 {
+        self.bytes_received
+    }
+
+    pub fn operations(&self) -> OperationCounts {
+        self.operations
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &NodeEnergy> {
+        self.nodes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // Sum over every node of the network
+    pub fn total(&self) -> NodeEnergy {
+        let mut total = NodeEnergy::default();
+        for energy in self.nodes.iter() {
+            total += *energy;
+        }
+        total
+    }
+
+    // Id and energy of the node that spent the most
+    pub fn most_drained(&self) -> Option<(usize, &NodeEnergy)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total().total_cmp(&b.total()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totals_add_up_every_node_and_activity() {
+        let config = SimulationConfig::default();
+        let mut ledger = EnergyLedger::new(3);
+        ledger.transmit(0, 10.0, 5.0, &config);
+        ledger.receive(1, 10.0, &config);
+        ledger.forward(2, 4.0, 5.0, &config);
+        ledger.hashes(0, 3, &config);
+        ledger.encryptions(1, 2, &config);
+        ledger.decryptions(1, 1, &config);
+        ledger.multiplications(2, 4, &config);
+
+        let total = ledger.total();
+        assert_eq!(
+            total.transmit,
+            config.transmit_energy(10.0, 5.0) + config.transmit_energy(4.0, 5.0)
+        );
+        assert_eq!(
+            total.receive,
+            config.receive_energy(10.0) + config.receive_energy(4.0)
+        );
+        assert_eq!(total.hash, 3.0 * config.hash_energy);
+        assert_eq!(total.encrypt, 2.0 * config.encryption_energy);
+        assert_eq!(total.decrypt, config.decryption_energy);
+        assert_eq!(total.multiply, 4.0 * config.multiplication_energy);
+        let sum: f32 = ledger.iter().map(|energy| energy.total()).sum();
+        assert!((total.total() - sum).abs() < 1e-6 * sum);
+
+        // A forwarded message is received and sent again
+        assert_eq!(ledger.bytes_sent(), 14.0);
+        assert_eq!(ledger.bytes_received(), 14.0);
+        assert_eq!(
+            ledger.operations(),
+            OperationCounts {
+                messages: 2,
+                hashes: 3,
+                encryptions: 2,
+                decryptions: 1,
+                multiplications: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn most_drained_is_the_node_that_spent_the_most() {
+        let config = SimulationConfig::default();
+        let mut ledger = EnergyLedger::new(3);
+        ledger.hashes(0, 1, &config);
+        ledger.hashes(1, 5, &config);
+        ledger.hashes(2, 2, &config);
+        let (node, energy) = ledger.most_drained().unwrap();
+        assert_eq!(node, 1);
+        assert_eq!(energy.hash, 5.0 * config.hash_energy);
+        assert!(ledger.iter().all(|other| other.total() <= energy.total()));
+        assert_eq!(EnergyLedger::new(0).most_drained(), None);
+    }
+}
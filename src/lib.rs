@@ -1,8 +1,25 @@
 use std::ops::{Deref, DerefMut};
 
-use rand::{rngs::ThreadRng, seq::SliceRandom, thread_rng, Rng};
-
+#[cfg(feature = "crypto")]
+pub mod calibration;
+pub mod config;
+pub mod experiment;
+pub mod ledger;
+pub mod lifetime;
+pub mod membership;
 pub mod methods;
+pub mod radio;
+pub mod rekeying;
+pub mod resilience;
+pub mod results;
+pub mod revocation;
+pub mod rng;
+pub mod routing;
+pub mod simulation;
+pub mod stats;
+pub mod topology;
+
+pub use topology::{initialize_network, initialize_network_from_seed};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum NodeType {
@@ -10,12 +27,49 @@ pub enum NodeType {
     Constrained,
 }
 
+// Coordinates of a node, z is 0 for planar deployments
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Position {
+    pub fn distance(&self, other: &Position) -> f64 {
+        ((self.x - other.x).powi(2) + (self.y - other.y).powi(2) + (self.z - other.z).powi(2))
+            .sqrt()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NodeState {
+    Active,
+    // Left the network, or ran out of energy
+    Departed,
+    // Evicted by the network, its key material is no longer trusted
+    Revoked,
+}
+
 #[derive(Debug, Clone)]
 pub struct Node {
     pub id: usize,
     pub kind: NodeType,
     pub neighbors: Vec<usize>,
     pub max_possible_neighbors: usize,
+    // Only set by spatial topologies
+    pub position: Option<Position>,
+    // Gateway whose cluster this constrained node belongs to
+    pub gateway: Option<usize>,
+    // Departed and revoked nodes keep their id but have no neighbors and no cluster
+    pub state: NodeState,
+}
+
+// A gateway and the constrained nodes sharing its group key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cluster {
+    pub gateway: usize,
+    pub members: Vec<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -25,6 +79,41 @@ impl NodesVec {
     fn new() -> Self {
         Self(Vec::new())
     }
+
+    // Every gateway with the constrained nodes assigned to it, ordered by gateway id.
+    // Nodes without a gateway are not covered by any group key.
+    pub fn clusters(&self) -> Vec<Cluster> {
+        let mut clusters: Vec<Cluster> = Vec::new();
+        let mut cluster_of_gateway: Vec<usize> = vec![usize::MAX; self.len()];
+        for node in self.iter() {
+            if node.kind == NodeType::Gateway && node.is_active() {
+                cluster_of_gateway[node.id] = clusters.len();
+                clusters.push(Cluster {
+                    gateway: node.id,
+                    members: Vec::new(),
+                });
+            }
+        }
+        for node in self.iter() {
+            if let Some(gateway) = node.gateway {
+                if node.kind == NodeType::Constrained && cluster_of_gateway[gateway] != usize::MAX {
+                    clusters[cluster_of_gateway[gateway]].members.push(node.id);
+                }
+            }
+        }
+        clusters
+    }
+
+    // Cluster of a gateway or of the gateway of a constrained node
+    pub fn cluster_of(&self, node: usize) -> Option<Cluster> {
+        let gateway = match self[node].kind {
+            NodeType::Gateway => node,
+            NodeType::Constrained => self[node].gateway?,
+        };
+        self.clusters()
+            .into_iter()
+            .find(|cluster| cluster.gateway == gateway)
+    }
 }
 
 impl Deref for NodesVec {
@@ -53,85 +142,13 @@ impl Node {
             kind,
             neighbors,
             max_possible_neighbors,
+            position: None,
+            gateway: None,
+            state: NodeState::Active,
         }
     }
-}
-
-pub fn initialize_network(
-    number_of_nodes: i32,
-    number_of_gateways: i32,
-    number_of_min_possible_neighbors: i32,
-    number_of_max_possible_neighbors: i32,
-) -> NodesVec {
-    let mut nodes: NodesVec = NodesVec::new();
-
-    let mut rng: ThreadRng = thread_rng();
-
-    // Push gateway nodes
-    for _ in 0..number_of_gateways {
-        let number_of_neighbors: i32 =
-            rng.gen_range(number_of_min_possible_neighbors..=number_of_max_possible_neighbors);
-        let node = Node::new(
-            nodes.len(),
-            NodeType::Gateway,
-            vec![],
-            number_of_neighbors as usize,
-        );
-        nodes.push(node);
-    }
 
-    // Push constrained nodes
-    for _ in 0..(number_of_nodes - number_of_gateways) {
-        let number_of_neighbors: i32 =
-            rng.gen_range(number_of_min_possible_neighbors..=number_of_max_possible_neighbors);
-        let node = Node::new(
-            nodes.len(),
-            NodeType::Constrained,
-            vec![],
-            number_of_neighbors as usize,
-        );
-        nodes.push(node);
+    pub fn is_active(&self) -> bool {
+        self.state == NodeState::Active
     }
-
-    // Sort nodes randomly
-    nodes.shuffle(&mut rng);
-
-    // Update id of each node to match its index in the nodes array
-    for i in 0..nodes.len() {
-        nodes[i].id = i;
-    }
-
-    // Start adding neighbors to each node
-    for i in 0..nodes.len() {
-        let current_node_id: usize = nodes[i].id;
-        let number_of_current_neighbors: usize = nodes[i].neighbors.len();
-        let number_of_current_max_possible_neighbors: usize = nodes[i].max_possible_neighbors;
-        let number_of_current_remaining_possible_neighbors: usize =
-            number_of_current_max_possible_neighbors - number_of_current_neighbors;
-        // For each remaining possible neighbor, add it to the current node if it is not already a neighbor
-        for _j in 0..number_of_current_remaining_possible_neighbors {
-            // Get the list of nodes that are not already neighbors of the current node and that are not the current node itself and that do not have the maximum number of neighbors
-            let possible_neighbors: Vec<usize> = (0..nodes.len())
-                .filter(|&k| k != i)
-                .filter(|&k| !nodes[i].neighbors.contains(&k))
-                .filter(|&k| nodes[k].neighbors.len() < nodes[k].max_possible_neighbors)
-                .collect();
-
-            // If there are no possible neighbors, break the loop
-            if possible_neighbors.len() == 0 {
-                break;
-            }
-
-            // Pick a random node from the list of possible neighbors
-            let neighbor_index: usize = rng.gen_range(0..possible_neighbors.len());
-            let neighbor: usize = possible_neighbors[neighbor_index];
-            let neighbor_id: usize = nodes[neighbor].id;
-            // Add the neighbor id to the current node's list of neighbors
-            nodes[i].neighbors.push(neighbor_id);
-            // Add the current node id to the neighbor's list of neighbors
-            nodes[neighbor].neighbors.push(current_node_id);
-        }
-    }
-
-    nodes
 }
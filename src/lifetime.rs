@@ -0,0 +1,215 @@
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::{
+    config::SimulationConfig,
+    methods::{KeyManagementScheme, KeyMaterial},
+    results::write_csv_row,
+    NodeType, NodesVec,
+};
+
+// Battery lifetime of the constrained nodes of a network keyed again at every round.
+// Gateways are assumed to be mains powered and never run out of energy.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Lifetime {
+    pub scheme: &'static str,
+    pub battery_capacity: f64,
+    // First round at the end of which a constrained node has no energy left
+    pub first_node_death: Option<u32>,
+    // Percentage of constrained nodes still alive at the end of each round
+    pub alive_percent: Vec<f64>,
+}
+
+// Run `rounds` keying rounds of the scheme, each made of a pairwise and a groupwise key
+// establishment with the keys of `material`, starting with `battery_capacity` in every
+// constrained node. The capacity is in the unit of the configured energies.
+pub fn estimate_lifetime(
+    scheme: &dyn KeyManagementScheme,
+    nodes: &NodesVec,
+    config: &SimulationConfig,
+    mac_size: u32,
+    material: &KeyMaterial,
+    battery_capacity: f64,
+    rounds: u32,
+) -> Lifetime {
+    let mut network = nodes.clone();
+    let mut residual: Vec<f64> = vec![battery_capacity; network.len()];
+    let mut alive: Vec<bool> = vec![true; network.len()];
+    let constrained_count = network
+        .iter()
+        .filter(|node| node.kind == NodeType::Constrained)
+        .count();
+    let mut alive_count = constrained_count;
+    let mut first_node_death = None;
+    let mut alive_percent = Vec::with_capacity(rounds as usize);
+
+    // The cost of a round only changes when a node dies
+    let mut ledger = scheme.keying_round_ledger(&network, config, mac_size, material);
+    for round in 1..=rounds {
+        let mut died: Vec<usize> = Vec::new();
+        for node in network.iter() {
+            if node.kind == NodeType::Gateway || !alive[node.id] {
+                continue;
+            }
+            residual[node.id] -= ledger.node(node.id).total() as f64;
+            if residual[node.id] <= 0.0 {
+                died.push(node.id);
+            }
+        }
+        if !died.is_empty() {
+            if first_node_death.is_none() {
+                first_node_death = Some(round);
+            }
+            for &dead in died.iter() {
+                alive[dead] = false;
+                // The following rounds are keyed without it
+                network.remove_node(dead);
+            }
+            alive_count -= died.len();
+            ledger = scheme.keying_round_ledger(&network, config, mac_size, material);
+        }
+        alive_percent.push(if constrained_count > 0 {
+            100.0 * alive_count as f64 / constrained_count as f64
+        } else {
+            0.0
+        });
+    }
+
+    Lifetime {
+        scheme: scheme.name(),
+        battery_capacity,
+        first_node_death,
+        alive_percent,
+    }
+}
+
+// One row per scheme and round, the first node death of each scheme as `#` comments
+pub fn write_lifetimes_csv<W: Write>(writer: &mut W, lifetimes: &[Lifetime]) -> io::Result<()> {
+    for lifetime in lifetimes.iter() {
+        match lifetime.first_node_death {
+            Some(round) => writeln!(writer, "# {}.first_node_death={}", lifetime.scheme, round)?,
+            None => writeln!(writer, "# {}.first_node_death=none", lifetime.scheme)?,
+        }
+    }
+    writeln!(writer, "scheme,round,alive_percent")?;
+    for lifetime in lifetimes.iter() {
+        for (index, percent) in lifetime.alive_percent.iter().enumerate() {
+            write_csv_row(writer, &[&lifetime.scheme, &(index + 1), percent])?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        methods::{ifkms::Ifkms, matrix::Matrix},
+        rng::rng_from_seed,
+        topology::{assign_gateway_clusters, initialize_network_from_seed},
+        Node,
+    };
+
+    // Gateway 0 and its single member 1
+    fn pair() -> NodesVec {
+        let mut nodes = NodesVec::new();
+        nodes.push(Node::new(0, NodeType::Gateway, vec![1], 1));
+        let mut member = Node::new(1, NodeType::Constrained, vec![0], 1);
+        member.gateway = Some(0);
+        nodes.push(member);
+        nodes
+    }
+
+    #[test]
+    fn round_cost_is_drawn_from_the_battery() {
+        let config = SimulationConfig::default();
+        let mac_size = 8;
+        let hello = config.hello_message_size(mac_size);
+        let reply = mac_size as f32 / 2.0;
+        let distance = config.default_link_distance;
+        // Hello and half the reply each way, the group key from the gateway, one
+        // multiplication and two hashes for the pairwise key, two encryptions and a hash for
+        // the group key
+        let round = config.transmit_energy(hello, distance)
+            + config.receive_energy(hello)
+            + config.transmit_energy(reply, distance)
+            + config.receive_energy(reply)
+            + config.receive_energy(config.received_message_size as f32)
+            + config.multiplication_energy
+            + 3.0 * config.hash_energy
+            + 2.0 * config.encryption_energy;
+        let lifetime = estimate_lifetime(
+            &Ifkms,
+            &pair(),
+            &config,
+            mac_size,
+            &KeyMaterial::default(),
+            2.5 * round as f64,
+            4,
+        );
+        assert_eq!(lifetime.first_node_death, Some(3));
+        assert_eq!(lifetime.alive_percent, vec![100.0, 100.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn gateways_never_run_out_of_energy() {
+        let config = SimulationConfig::default();
+        // Gateway 0 keyed with and sending the group key to eight members
+        let mut nodes = NodesVec::new();
+        nodes.push(Node::new(0, NodeType::Gateway, (1..=8).collect(), 8));
+        for id in 1..=8 {
+            let mut member = Node::new(id, NodeType::Constrained, vec![0], 1);
+            member.gateway = Some(0);
+            nodes.push(member);
+        }
+        let ledger = Ifkms.keying_round_ledger(&nodes, &config, 8, &KeyMaterial::default());
+        let gateway = ledger.node(0).total() as f64;
+        let member = ledger.node(1).total() as f64;
+        // Enough for the members to last five rounds, not for the gateway were it on batteries
+        let battery = 5.5 * member;
+        assert!(5.0 * gateway > battery);
+        let lifetime = estimate_lifetime(
+            &Ifkms,
+            &nodes,
+            &config,
+            8,
+            &KeyMaterial::default(),
+            battery,
+            5,
+        );
+        assert_eq!(lifetime.first_node_death, None);
+        assert_eq!(lifetime.alive_percent, vec![100.0; 5]);
+    }
+
+    #[test]
+    fn first_death_is_the_first_round_below_full_strength() {
+        let config = SimulationConfig::default();
+        let mut nodes = initialize_network_from_seed(60, 6, 3, 8, 4);
+        assign_gateway_clusters(&mut nodes, 10, &mut rng_from_seed(4));
+        let battery = 20.0
+            * Matrix
+                .keying_round_ledger(&nodes, &config, 8, &KeyMaterial::default())
+                .total()
+                .total() as f64
+            / 60.0;
+        let lifetime = estimate_lifetime(
+            &Matrix,
+            &nodes,
+            &config,
+            8,
+            &KeyMaterial::default(),
+            battery,
+            200,
+        );
+        let first = lifetime.first_node_death.expect("some node runs out");
+        assert!(lifetime.alive_percent[..first as usize - 1]
+            .iter()
+            .all(|&percent| percent == 100.0));
+        assert!(lifetime.alive_percent[first as usize - 1] < 100.0);
+        assert!(lifetime
+            .alive_percent
+            .windows(2)
+            .all(|pair| pair[1] <= pair[0]));
+    }
+}
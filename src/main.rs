@@ -1,236 +1,666 @@
-#![allow(unused_imports)]
-use std::env;
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process,
+};
 
-use dotenv::dotenv;
-// use NodesVec from lib.rs
+use clap::{Args, Parser, Subcommand, ValueEnum};
+#[cfg(feature = "crypto")]
+use evkms_metrics_simulation::calibration::{Calibration, CpuProfile};
 use evkms_metrics_simulation::{
-    initialize_network,
-    methods::{evkms, matrix, ifkms, pool_hash},
-    NodesVec,
+    config::{
+        effective_configuration, schema, ExperimentParameters, NetworkConfig, SimulationConfig,
+    },
+    experiment::{run_experiment, ExperimentSpec, ParameterValue, KEY_MATERIAL_STREAM},
+    lifetime::{estimate_lifetime, write_lifetimes_csv, Lifetime},
+    methods::{all_schemes, scheme_by_name, KeyManagementScheme},
+    rekeying::{draw_events, simulate_rekeying, write_rekeying_csv, RekeyReport},
+    results::{write_csv_row, OutputFormat},
+    rng::{derive_seed, random_seed, rng_from_seed},
+    simulation::LossModel,
 };
+use rayon::prelude::*;
+
+const GROUPWISE_EXPERIMENT: &str = include_str!("../experiments/groupwise.toml");
+const PAIRWISE_ENERGY_EXPERIMENT: &str = include_str!("../experiments/pairwise_energy.toml");
+const PAIRWISE_BYTES_EXPERIMENT: &str = include_str!("../experiments/pairwise_communication.toml");
+
+#[derive(Parser)]
+#[command(
+    name = "evkms_metrics_simulation",
+    version,
+    about = "Compare the cost of key management schemes on simulated IoT networks"
+)]
+struct Cli {
+    // Runs the pairwise sent/received bytes experiment when omitted
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run an experiment described by a TOML or JSON spec file
+    Run {
+        spec: PathBuf,
+        #[command(flatten)]
+        options: RunOptions,
+    },
+    /// Multiplications and groupwise energy while the number of nodes grows
+    Groupwise {
+        #[command(flatten)]
+        options: RunOptions,
+    },
+    /// Pairwise communication energy for increasing MAC sizes
+    PairwiseEnergy {
+        #[command(flatten)]
+        options: RunOptions,
+    },
+    /// Pairwise sent and received bytes for increasing MAC sizes
+    PairwiseBytes {
+        #[command(flatten)]
+        options: RunOptions,
+    },
+    /// Battery lifetime of the nodes over repeated keying rounds
+    Lifetime {
+        #[command(flatten)]
+        options: LifetimeOptions,
+    },
+    /// Cumulative energy and traffic of scheduled and event-driven rekeying
+    Rekey {
+        #[command(flatten)]
+        options: RekeyOptions,
+    },
+    /// List every configuration parameter with its unit, default and valid range
+    Config,
+    /// Benchmark the cryptographic primitives and write the matching operation energies
+    #[cfg(feature = "crypto")]
+    Calibrate {
+        #[command(flatten)]
+        options: CalibrateOptions,
+    },
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum Format {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl From<Format> for OutputFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Csv => OutputFormat::Csv,
+            Format::Json => OutputFormat::Json,
+        }
+    }
+}
+
+#[derive(Args, Default)]
+struct ConfigOptions {
+    /// Override a configuration parameter, takes precedence over the environment
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    overrides: Vec<(String, String)>,
+    /// Read the environment from this file instead of .env, repeat it to layer several files,
+    /// the later ones taking precedence
+    #[arg(long, value_name = "PATH")]
+    env_file: Vec<PathBuf>,
+}
+
+#[derive(Args, Default)]
+struct RunOptions {
+    #[command(flatten)]
+    config: ConfigOptions,
+    /// Number of random topologies evaluated per sweep value
+    #[arg(long)]
+    iterations: Option<u32>,
+    /// Master seed of the run, every iteration derives its own seed from it
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Comma separated list of schemes to include
+    #[arg(long, value_delimiter = ',')]
+    schemes: Vec<String>,
+    /// First value of the swept parameter
+    #[arg(long)]
+    from: Option<f64>,
+    /// Last value of the swept parameter
+    #[arg(long)]
+    to: Option<f64>,
+    /// Increment of the swept parameter
+    #[arg(long)]
+    step: Option<f64>,
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+    /// Write the results to this file instead of stdout
+    #[arg(long, short, value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// Number of worker threads, defaults to the number of CPU cores
+    #[arg(long)]
+    threads: Option<usize>,
+    /// Also write the value of every iteration to this CSV file
+    #[arg(long, value_name = "PATH")]
+    samples: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct LifetimeOptions {
+    #[command(flatten)]
+    config: ConfigOptions,
+    /// Initial energy of every constrained node, in the unit of the configured energies
+    #[arg(long)]
+    battery: f64,
+    /// Number of keying rounds to simulate
+    #[arg(long, default_value_t = 1000)]
+    rounds: u32,
+    /// MAC size handed to the pairwise key establishment
+    #[arg(long, default_value_t = ExperimentParameters::default().mac_size)]
+    mac_size: u32,
+    /// Seed of the generated topology
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Comma separated list of schemes to include
+    #[arg(long, value_delimiter = ',')]
+    schemes: Vec<String>,
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+    /// Write the results to this file instead of stdout
+    #[arg(long, short, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct RekeyOptions {
+    #[command(flatten)]
+    config: ConfigOptions,
+    /// Number of rounds after the initial keying
+    #[arg(long, default_value_t = 100)]
+    rounds: u32,
+    /// MAC size handed to the pairwise key establishment
+    #[arg(long, default_value_t = ExperimentParameters::default().mac_size)]
+    mac_size: u32,
+    /// Seed of the generated topology and of the membership and compromise events
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Comma separated list of schemes to include
+    #[arg(long, value_delimiter = ',')]
+    schemes: Vec<String>,
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+    /// Write the results to this file instead of stdout
+    #[arg(long, short, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+#[cfg(feature = "crypto")]
+#[derive(Args)]
+struct CalibrateOptions {
+    /// CPU the energies are computed for: msp430, cortex_m0, host or the path of a TOML profile
+    #[arg(long, default_value = "host")]
+    profile: String,
+    /// Override the clock frequency of the profile, in Hz
+    #[arg(long)]
+    clock_hz: Option<f64>,
+    /// Override the active power of the profile, in W
+    #[arg(long)]
+    power: Option<f64>,
+    /// Number of timed runs of each primitive
+    #[arg(long, default_value_t = 100_000)]
+    iterations: u32,
+    /// Write the environment fragment to this file instead of stdout
+    #[arg(long, short, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+fn parse_key_value(argument: &str) -> Result<(String, String), String> {
+    match argument.split_once('=') {
+        Some((key, value)) => Ok((key.trim().to_string(), value.trim().to_string())),
+        None => Err(format!("expected KEY=VALUE, got {:?}", argument)),
+    }
+}
 
 fn main() {
-    dotenv().ok();
-    simulate_pairwise_communication();
-}
-
-fn simulate() {
-    let number_of_nodes = env::var("NUMBER_OF_NODES")
-        .expect("NUMBER_OF_NODES must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_NODES must be a number");
-    let number_of_gateways = env::var("NUMBER_OF_GATEWAYS")
-        .expect("NUMBER_OF_GATEWAYS must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_GATEWAYS must be a number");
-    let number_of_min_possible_neighbors = env::var("NUMBER_OF_MIN_POSSIBLE_NEIGHBORS")
-        .expect("NUMBER_OF_MIN_POSSIBLE_NEIGHBORS must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_MIN_POSSIBLE_NEIGHBORS must be a number");
-    let number_of_max_possible_neighbors = env::var("NUMBER_OF_MAX_POSSIBLE_NEIGHBORS")
-        .expect("NUMBER_OF_MAX_POSSIBLE_NEIGHBORS must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_MAX_POSSIBLE_NEIGHBORS must be a number");
-    let number_of_gateway_members = env::var("NUMBER_OF_GATEWAY_MEMBERS")
-        .expect("NUMBER_OF_GATEWAY_MEMBERS must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_GATEWAY_MEMBERS must be a number");
-
-    let mut evkms_multiplications: Vec<(u32, u32)> = Vec::new();
-    let mut matrix_multiplications: Vec<(u32, u32)> = Vec::new();
-    let mut evkms_groupwise_computation_energy: Vec<(u32, f32)> = Vec::new();
-    let mut matrix_groupwise_computation_energy: Vec<(u32, f32)> = Vec::new();
-    let mut evkms_groupwise_total_energy: Vec<(u32, f32)> = Vec::new();
-    let mut matrix_groupwise_total_energy: Vec<(u32, f32)> = Vec::new();
-
-    for i in (10..=number_of_nodes).step_by(10) {
-        let mut evkms_sum: u32 = 0;
-        let mut matrix_sum: u32 = 0;
-        let mut evkms_groupwise_computation_energy_sum: f32 = 0.0;
-        let mut matrix_groupwise_computation_energy_sum: f32 = 0.0;
-        let mut evkms_groupwise_total_energy_sum: f32 = 0.0;
-        let mut matrix_groupwise_total_energy_sum: f32 = 0.0;
-        // Simulate 1000 times
-        for iteration in 0..1000 {
-            println!(
-                "Simulation: Number of nodes: {}, iteration: {}",
-                i, iteration
-            );
-            let nodes: NodesVec = initialize_network(
-                i,
-                i / 10,
-                number_of_min_possible_neighbors,
-                number_of_max_possible_neighbors,
+    let cli = Cli::parse();
+    let (spec, options) = match cli.command {
+        Some(Command::Run { spec, options }) => (ExperimentSpec::from_path(&spec), options),
+        Some(Command::Groupwise { options }) => {
+            (ExperimentSpec::from_toml(GROUPWISE_EXPERIMENT), options)
+        }
+        Some(Command::PairwiseEnergy { options }) => (
+            ExperimentSpec::from_toml(PAIRWISE_ENERGY_EXPERIMENT),
+            options,
+        ),
+        Some(Command::PairwiseBytes { options }) => (
+            ExperimentSpec::from_toml(PAIRWISE_BYTES_EXPERIMENT),
+            options,
+        ),
+        Some(Command::Lifetime { options }) => return lifetime(&options),
+        Some(Command::Rekey { options }) => return rekey(&options),
+        Some(Command::Config) => return print_schema(),
+        #[cfg(feature = "crypto")]
+        Some(Command::Calibrate { options }) => return calibrate(&options),
+        None => (
+            ExperimentSpec::from_toml(PAIRWISE_BYTES_EXPERIMENT),
+            RunOptions::default(),
+        ),
+    };
+    let mut spec = spec.unwrap_or_else(|error| {
+        eprintln!("Invalid experiment: {}", error);
+        process::exit(1);
+    });
+
+    let (config, network) = load_configs(&options.config);
+
+    apply_options(&mut spec, &options);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(0))
+        .build()
+        .unwrap_or_else(|error| {
+            eprintln!("Could not start the worker threads: {}", error);
+            process::exit(1);
+        });
+    let results = pool
+        .install(|| run_experiment(&spec, &config, &network))
+        .unwrap_or_else(|error| {
+            eprintln!("Experiment {} failed: {}", spec.name, error);
+            process::exit(1);
+        });
+
+    let mut writer = output_writer(&options.output);
+    if let Err(error) = results
+        .write(&mut writer, options.format.into())
+        .and_then(|_| writer.flush())
+    {
+        eprintln!("Could not write the results: {}", error);
+        process::exit(1);
+    }
+
+    if let Some(path) = &options.samples {
+        let written = File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            results.write_samples_csv(&mut writer)?;
+            writer.flush()
+        });
+        if let Err(error) = written {
+            eprintln!(
+                "Could not write the samples to {}: {}",
+                path.display(),
+                error
             );
-            evkms_sum += evkms::number_of_multiplications(nodes.clone());
-            matrix_sum += matrix::number_of_multiplications(nodes.clone());
+            process::exit(1);
+        }
+    }
+}
 
-            evkms_groupwise_computation_energy_sum +=
-                evkms::groupwise_computation_energy(nodes.clone());
-            matrix_groupwise_computation_energy_sum +=
-                matrix::groupwise_computation_energy(nodes.clone());
+// Read the environment file then build both configurations, `--set` taking precedence
+fn load_configs(options: &ConfigOptions) -> (SimulationConfig, NetworkConfig) {
+    // dotenv never overwrites a variable, so the files are loaded from the last one
+    let env_files: Vec<PathBuf> = if options.env_file.is_empty() {
+        dotenv::dotenv().into_iter().collect()
+    } else {
+        for path in options.env_file.iter().rev() {
+            if let Err(error) = dotenv::from_path(path) {
+                eprintln!("Could not read {}: {}", path.display(), error);
+                process::exit(1);
+            }
+        }
+        options.env_file.clone()
+    };
+    // Every key of the environment files and of --set must be a known parameter
+    for path in env_files.iter() {
+        let keys = env_file_keys(path).unwrap_or_else(|error| {
+            eprintln!("Could not read {}: {}", path.display(), error);
+            process::exit(1);
+        });
+        for key in keys {
+            if let Err(error) = schema::require(&key) {
+                eprintln!("Invalid {}: {}", path.display(), error);
+                process::exit(1);
+            }
+        }
+    }
+    for (key, _) in options.overrides.iter() {
+        if let Err(error) = schema::require(key) {
+            eprintln!("Invalid --set: {}", error);
+            process::exit(1);
+        }
+    }
+    let overrides: HashMap<String, String> = options.overrides.iter().cloned().collect();
+    let lookup = |key: &str| overrides.get(key).cloned().or_else(|| env::var(key).ok());
+    let config = SimulationConfig::from_lookup(lookup).unwrap_or_else(|error| {
+        eprintln!("Invalid configuration: {}", error);
+        process::exit(1);
+    });
+    let network = NetworkConfig::from_lookup(lookup).unwrap_or_else(|error| {
+        eprintln!("Invalid network configuration: {}", error);
+        process::exit(1);
+    });
+    (config, network)
+}
 
-            evkms_groupwise_total_energy_sum += evkms::groupwise_total_energy(nodes.clone());
-            matrix_groupwise_total_energy_sum += matrix::groupwise_total_energy(nodes.clone());
+// Keys assigned by an environment file, skipping blank lines and comments
+fn env_file_keys(path: &Path) -> io::Result<Vec<String>> {
+    let mut keys: Vec<String> = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
         }
-        evkms_multiplications.push((i as u32, evkms_sum / 1000));
-        matrix_multiplications.push((i as u32, matrix_sum / 1000));
-        evkms_groupwise_computation_energy
-            .push((i as u32, evkms_groupwise_computation_energy_sum / 1000.0));
-        matrix_groupwise_computation_energy
-            .push((i as u32, matrix_groupwise_computation_energy_sum / 1000.0));
-        evkms_groupwise_total_energy.push((i as u32, evkms_groupwise_total_energy_sum / 1000.0));
-        matrix_groupwise_total_energy.push((i as u32, matrix_groupwise_total_energy_sum / 1000.0));
-    }
-
-    println!("evkms_multiplications: {:?}", evkms_multiplications);
-    println!("matrix_multiplications: {:?}", matrix_multiplications);
-    println!(
-        "evkms_groupwise_computation_energy: {:?}",
-        evkms_groupwise_computation_energy
-    );
-    println!(
-        "matrix_groupwise_computation_energy: {:?}",
-        matrix_groupwise_computation_energy
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        if let Some((key, _)) = line.split_once('=') {
+            keys.push(key.trim().to_string());
+        }
+    }
+    Ok(keys)
+}
+
+fn output_writer(output: &Option<PathBuf>) -> Box<dyn Write> {
+    match output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(error) => {
+                eprintln!("Could not create {}: {}", path.display(), error);
+                process::exit(1);
+            }
+        },
+        None => Box::new(io::stdout().lock()),
+    }
+}
+
+// Schemes named on the command line, every scheme when none is
+fn selected_schemes(names: &[String]) -> Vec<Box<dyn KeyManagementScheme>> {
+    if names.is_empty() {
+        return all_schemes();
+    }
+    names
+        .iter()
+        .map(|name| {
+            scheme_by_name(name).unwrap_or_else(|| {
+                eprintln!("Unknown scheme: {}", name);
+                process::exit(1);
+            })
+        })
+        .collect()
+}
+
+// Lifetime and rekeying costs are computed in closed form, which assumes every frame arrives
+fn require_lossless(config: &SimulationConfig) {
+    if config.loss_model != LossModel::None {
+        eprintln!("Frame losses are only simulated by the protocol metrics of the run command, set LOSS_MODEL=none");
+        process::exit(1);
+    }
+}
+
+// Lifetime of every selected scheme on one topology
+fn lifetime(options: &LifetimeOptions) {
+    let (config, network) = load_configs(&options.config);
+    require_lossless(&config);
+    let schemes = selected_schemes(&options.schemes);
+    eprintln!("Effective configuration:");
+    let parameters = ExperimentParameters {
+        mac_size: options.mac_size,
+        ..ExperimentParameters::default()
+    };
+    for line in effective_configuration(&config, &network, &parameters) {
+        eprintln!("  {}", line);
+    }
+    let seed = options.seed.unwrap_or_else(random_seed);
+    let nodes = network.build_network(&mut rng_from_seed(seed));
+    eprintln!(
+        "Lifetime: {} nodes, seed {}, {} rounds",
+        nodes.len(),
+        seed,
+        options.rounds
     );
-    println!(
-        "evkms_groupwise_total_energy: {:?}",
-        evkms_groupwise_total_energy
+    let lifetimes: Vec<Lifetime> = schemes
+        .par_iter()
+        .map(|scheme| {
+            let material = scheme.predistribute(
+                &nodes,
+                &config,
+                &mut rng_from_seed(derive_seed(seed, KEY_MATERIAL_STREAM, 0)),
+            );
+            estimate_lifetime(
+                scheme.as_ref(),
+                &nodes,
+                &config,
+                options.mac_size,
+                &material,
+                options.battery,
+                options.rounds,
+            )
+        })
+        .collect();
+
+    let mut writer = output_writer(&options.output);
+    let written = match options.format {
+        Format::Csv => write_lifetimes_csv(&mut writer, &lifetimes),
+        Format::Json => serde_json::to_writer_pretty(&mut writer, &lifetimes)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(writer)),
+    };
+    if let Err(error) = written.and_then(|_| writer.flush()) {
+        eprintln!("Could not write the results: {}", error);
+        process::exit(1);
+    }
+}
+
+// Rekeying cost of every selected scheme on one topology, facing the same events
+fn rekey(options: &RekeyOptions) {
+    let (config, network) = load_configs(&options.config);
+    require_lossless(&config);
+    let schemes = selected_schemes(&options.schemes);
+    eprintln!("Effective configuration:");
+    let parameters = ExperimentParameters {
+        mac_size: options.mac_size,
+        ..ExperimentParameters::default()
+    };
+    for line in effective_configuration(&config, &network, &parameters) {
+        eprintln!("  {}", line);
+    }
+    let seed = options.seed.unwrap_or_else(random_seed);
+    let mut rng = rng_from_seed(seed);
+    let nodes = network.build_network(&mut rng);
+    let events = draw_events(&nodes, &config, options.rounds, &mut rng);
+    eprintln!(
+        "Rekeying: {} nodes, seed {}, {} rounds, {} events",
+        nodes.len(),
+        seed,
+        options.rounds,
+        events.len()
     );
-    println!(
-        "matrix_groupwise_total_energy: {:?}",
-        matrix_groupwise_total_energy
+    let schedules = schemes
+        .par_iter()
+        .map(|scheme| {
+            let material = scheme.predistribute(
+                &nodes,
+                &config,
+                &mut rng_from_seed(derive_seed(seed, KEY_MATERIAL_STREAM, 0)),
+            );
+            simulate_rekeying(
+                scheme.as_ref(),
+                &nodes,
+                &config,
+                options.mac_size,
+                &material,
+                &events,
+                options.rounds,
+            )
+        })
+        .collect();
+    let report = RekeyReport { events, schedules };
+
+    let mut writer = output_writer(&options.output);
+    let written = match options.format {
+        Format::Csv => write_rekeying_csv(&mut writer, &report),
+        Format::Json => serde_json::to_writer_pretty(&mut writer, &report)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(writer)),
+    };
+    if let Err(error) = written.and_then(|_| writer.flush()) {
+        eprintln!("Could not write the results: {}", error);
+        process::exit(1);
+    }
+}
+
+#[cfg(feature = "crypto")]
+fn calibrate(options: &CalibrateOptions) {
+    let mut profile = CpuProfile::find(&options.profile).unwrap_or_else(|error| {
+        eprintln!("Invalid profile: {}", error);
+        process::exit(1);
+    });
+    if let Some(clock_hz) = options.clock_hz {
+        profile.clock_hz = clock_hz;
+    }
+    if let Some(power) = options.power {
+        profile.active_power = power;
+    }
+    eprintln!(
+        "Calibrating for {}: {} runs of each primitive",
+        profile.name, options.iterations
     );
+    let calibration = Calibration::new(profile, options.iterations);
+
+    let mut writer = output_writer(&options.output);
+    if let Err(error) = calibration
+        .write_env(&mut writer)
+        .and_then(|_| writer.flush())
+    {
+        eprintln!("Could not write the calibration: {}", error);
+        process::exit(1);
+    }
 }
 
-fn simulate_pairwise_communication_energy_consumption() {
-    let number_of_nodes = env::var("NUMBER_OF_NODES")
-        .expect("NUMBER_OF_NODES must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_NODES must be a number");
-    let number_of_gateways = env::var("NUMBER_OF_GATEWAYS")
-        .expect("NUMBER_OF_GATEWAYS must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_GATEWAYS must be a number");
-    let number_of_min_possible_neighbors = env::var("NUMBER_OF_MIN_POSSIBLE_NEIGHBORS")
-        .expect("NUMBER_OF_MIN_POSSIBLE_NEIGHBORS must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_MIN_POSSIBLE_NEIGHBORS must be a number");
-    let number_of_max_possible_neighbors = env::var("NUMBER_OF_MAX_POSSIBLE_NEIGHBORS")
-        .expect("NUMBER_OF_MAX_POSSIBLE_NEIGHBORS must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_MAX_POSSIBLE_NEIGHBORS must be a number");
-    let number_of_gateway_members = env::var("NUMBER_OF_GATEWAY_MEMBERS")
-        .expect("NUMBER_OF_GATEWAY_MEMBERS must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_GATEWAY_MEMBERS must be a number");
-    let min_mac_size = 16;
-    let max_mac_size = 64;
-    let mut evkms_energy: Vec<(u32, f32)> = Vec::new();
-    let mut matrix_energy: Vec<(u32, f32)> = Vec::new();
-    let mut ifkms_energy: Vec<(u32, f32)> = Vec::new();
-    let mut pool_hash_energy: Vec<(u32, f32)> = Vec::new();
-    for i in (min_mac_size..=max_mac_size).step_by(4) {
-        let mut evkms_sum: f32 = 0.0;
-        let mut matrix_sum: f32 = 0.0;
-        let mut ifkms_sum: f32 = 0.0;
-        let mut pool_hash_sum: f32 = 0.0;
-        for iteration in 0..1000 {
-            println!("Simulation: Mac size: {}, iteration: {}", i, iteration);
-            let nodes: NodesVec = initialize_network(
-                number_of_nodes,
-                number_of_nodes / 10,
-                number_of_min_possible_neighbors,
-                number_of_max_possible_neighbors,
-            );
-            evkms_sum += evkms::pairwise_communication_energy(nodes.clone(), i as u32);
-            matrix_sum += matrix::pairwise_communication_energy(nodes.clone(), i as u32);
-            ifkms_sum += ifkms::pairwise_communication_energy(nodes.clone(), i as u32);
-            pool_hash_sum += pool_hash::pairwise_communication_energy(nodes.clone(), i as u32);
-        }
-        evkms_energy.push((i as u32, evkms_sum / 1000.0));
-        matrix_energy.push((i as u32, matrix_sum / 1000.0));
-        ifkms_energy.push((i as u32, ifkms_sum / 1000.0));
-        pool_hash_energy.push((i as u32, pool_hash_sum / 1000.0));
-    }
-    println!("evkms_energy: {:?}", evkms_energy);
-    println!("matrix_energy: {:?}", matrix_energy);
-    println!("ifkms_energy: {:?}", ifkms_energy);
-    println!("pool_hash_energy: {:?}", pool_hash_energy);
-}
-
-fn simulate_pairwise_communication() {
-    let number_of_nodes = env::var("NUMBER_OF_NODES")
-        .expect("NUMBER_OF_NODES must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_NODES must be a number");
-    let number_of_gateways = env::var("NUMBER_OF_GATEWAYS")
-        .expect("NUMBER_OF_GATEWAYS must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_GATEWAYS must be a number");
-    let number_of_min_possible_neighbors = env::var("NUMBER_OF_MIN_POSSIBLE_NEIGHBORS")
-        .expect("NUMBER_OF_MIN_POSSIBLE_NEIGHBORS must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_MIN_POSSIBLE_NEIGHBORS must be a number");
-    let number_of_max_possible_neighbors = env::var("NUMBER_OF_MAX_POSSIBLE_NEIGHBORS")
-        .expect("NUMBER_OF_MAX_POSSIBLE_NEIGHBORS must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_MAX_POSSIBLE_NEIGHBORS must be a number");
-    let number_of_gateway_members = env::var("NUMBER_OF_GATEWAY_MEMBERS")
-        .expect("NUMBER_OF_GATEWAY_MEMBERS must be set")
-        .parse::<i32>()
-        .expect("NUMBER_OF_GATEWAY_MEMBERS must be a number");
-    let min_mac_size = 16;
-    let max_mac_size = 64;
-    let mut evkms_sent: Vec<(u32, f32)> = Vec::new();
-    let mut evkms_received: Vec<(u32, f32)> = Vec::new();
-    let mut matrix_sent: Vec<(u32, f32)> = Vec::new();
-    let mut matrix_received: Vec<(u32, f32)> = Vec::new();
-    let mut ifkms_sent: Vec<(u32, f32)> = Vec::new();
-    let mut ifkms_received: Vec<(u32, f32)> = Vec::new();
-    let mut pool_hash_sent: Vec<(u32, f32)> = Vec::new();
-    let mut pool_hash_received: Vec<(u32, f32)> = Vec::new();
-    for i in (min_mac_size..=max_mac_size).step_by(4) {
-        let mut evkms_sent_sum: f32 = 0.0;
-        let mut evkms_received_sum: f32 = 0.0;
-        let mut matrix_sent_sum: f32 = 0.0;
-        let mut matrix_received_sum: f32 = 0.0;
-        let mut ifkms_sent_sum: f32 = 0.0;
-        let mut ifkms_received_sum: f32 = 0.0;
-        let mut pool_hash_sent_sum: f32 = 0.0;
-        let mut pool_hash_received_sum: f32 = 0.0;
-        for iteration in 0..1000 {
-            println!("Simulation: Mac size: {}, iteration: {}", i, iteration);
-            let nodes: NodesVec = initialize_network(
-                number_of_nodes,
-                number_of_nodes / 10,
-                number_of_min_possible_neighbors,
-                number_of_max_possible_neighbors,
-            );
-            evkms_sent_sum += evkms::pairwise_communication_sent(nodes.clone(), i as u32);
-            evkms_received_sum += evkms::pairwise_communication_received(nodes.clone(), i as u32);
-            matrix_sent_sum += matrix::pairwise_communication_sent(nodes.clone(), i as u32);
-            matrix_received_sum += matrix::pairwise_communication_received(nodes.clone(), i as u32);
-            ifkms_sent_sum += ifkms::pairwise_communication_sent(nodes.clone(), i as u32);
-            ifkms_received_sum += ifkms::pairwise_communication_received(nodes.clone(), i as u32);
-            pool_hash_sent_sum += pool_hash::pairwise_communication_sent(nodes.clone(), i as u32);
-            pool_hash_received_sum += pool_hash::pairwise_communication_received(nodes.clone(), i as u32);
+fn print_schema() {
+    let mut writer = io::stdout().lock();
+    let mut written = writeln!(writer, "key,section,unit,default,range,description");
+    for parameter in schema::PARAMETERS.iter() {
+        written = written.and_then(|_| {
+            write_csv_row(
+                &mut writer,
+                &[
+                    &parameter.key,
+                    &format!("{:?}", parameter.section),
+                    &parameter.unit,
+                    &parameter.default.unwrap_or("required"),
+                    &parameter.range,
+                    &parameter.description,
+                ],
+            )
+        });
+    }
+    if let Err(error) = written {
+        eprintln!("Could not write the schema: {}", error);
+        process::exit(1);
+    }
+}
+
+// Command line flags take precedence over the values of the spec
+fn apply_options(spec: &mut ExperimentSpec, options: &RunOptions) {
+    for (key, value) in options.config.overrides.iter() {
+        spec.fixed
+            .insert(key.clone(), ParameterValue::Text(value.clone()));
+    }
+    if let Some(iterations) = options.iterations {
+        spec.iterations = iterations;
+    }
+    if options.seed.is_some() {
+        spec.seed = options.seed;
+    }
+    if !options.schemes.is_empty() {
+        spec.schemes = options.schemes.clone();
+    }
+    if let Some(from) = options.from {
+        spec.sweep.start = from;
+    }
+    if let Some(to) = options.to {
+        spec.sweep.end = to;
+    }
+    if let Some(step) = options.step {
+        spec.sweep.step = step;
+    }
+    if options.samples.is_some() {
+        spec.keep_samples = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_options(arguments: &[&str]) -> RunOptions {
+        let mut command = vec!["evkms_metrics_simulation", "groupwise"];
+        command.extend_from_slice(arguments);
+        match Cli::try_parse_from(command).unwrap().command {
+            Some(Command::Groupwise { options }) => options,
+            _ => unreachable!(),
         }
-        evkms_sent.push((i as u32, evkms_sent_sum / 1000.0));
-        evkms_received.push((i as u32, evkms_received_sum / 1000.0));
-        matrix_sent.push((i as u32, matrix_sent_sum / 1000.0));
-        matrix_received.push((i as u32, matrix_received_sum / 1000.0));
-        ifkms_sent.push((i as u32, ifkms_sent_sum / 1000.0));
-        ifkms_received.push((i as u32, ifkms_received_sum / 1000.0));
-        pool_hash_sent.push((i as u32, pool_hash_sent_sum / 1000.0));
-        pool_hash_received.push((i as u32, pool_hash_received_sum / 1000.0));
-    }
-    println!("evkms_sent: {:?}", evkms_sent);
-    println!("evkms_received: {:?}", evkms_received);
-    println!("matrix_sent: {:?}", matrix_sent);
-    println!("matrix_received: {:?}", matrix_received);
-    println!("ifkms_sent: {:?}", ifkms_sent);
-    println!("ifkms_received: {:?}", ifkms_received);
-    println!("pool_hash_sent: {:?}", pool_hash_sent);
-    println!("pool_hash_received: {:?}", pool_hash_received);
+    }
+
+    #[test]
+    fn command_line_overrides_the_spec() {
+        let options = run_options(&[
+            "--set",
+            "MAC_SIZE=8",
+            "--set",
+            " NUMBER_OF_NODES = 40 ",
+            "--iterations",
+            "3",
+            "--seed",
+            "9",
+            "--schemes",
+            "evkms,matrix",
+            "--from",
+            "10",
+            "--to",
+            "20",
+            "--step",
+            "5",
+            "--samples",
+            "samples.csv",
+        ]);
+        let mut spec = ExperimentSpec::from_toml(GROUPWISE_EXPERIMENT).unwrap();
+        apply_options(&mut spec, &options);
+        assert_eq!(
+            spec.fixed["MAC_SIZE"],
+            ParameterValue::Text("8".to_string())
+        );
+        assert_eq!(
+            spec.fixed["NUMBER_OF_NODES"],
+            ParameterValue::Text("40".to_string())
+        );
+        assert_eq!(spec.iterations, 3);
+        assert_eq!(spec.seed, Some(9));
+        assert_eq!(spec.schemes, vec!["evkms", "matrix"]);
+        assert_eq!(spec.sweep.values().unwrap(), vec![10.0, 15.0, 20.0]);
+        assert!(spec.keep_samples);
+    }
+
+    #[test]
+    fn options_left_out_keep_the_spec() {
+        let spec = ExperimentSpec::from_toml(GROUPWISE_EXPERIMENT).unwrap();
+        let mut applied = spec.clone();
+        apply_options(&mut applied, &run_options(&[]));
+        assert_eq!(applied, spec);
+    }
+
+    #[test]
+    fn overrides_must_be_key_value_pairs() {
+        assert!(Cli::try_parse_from([
+            "evkms_metrics_simulation",
+            "groupwise",
+            "--set",
+            "MAC_SIZE"
+        ])
+        .is_err());
+        assert_eq!(
+            parse_key_value("LOSS_MODEL=constant"),
+            Ok(("LOSS_MODEL".to_string(), "constant".to_string()))
+        );
+    }
 }
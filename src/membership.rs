@@ -0,0 +1,247 @@
+use rand::{seq::SliceRandom, Rng};
+use serde::Serialize;
+
+use crate::{
+    config::{NetworkConfig, SimulationConfig},
+    ledger::{EnergyLedger, OperationCounts},
+    methods::{KeyManagementScheme, KeyMaterial},
+    topology::Departure,
+    NodeType, NodesVec,
+};
+
+// What admitting or losing one node costs the whole network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct MembershipCost {
+    pub energy: f64,
+    pub bytes_sent: f64,
+    pub operations: OperationCounts,
+}
+
+impl MembershipCost {
+    pub fn from_ledger(ledger: &EnergyLedger) -> Self {
+        Self {
+            energy: ledger.total().total() as f64,
+            bytes_sent: ledger.bytes_sent() as f64,
+            operations: ledger.operations(),
+        }
+    }
+}
+
+// A constrained node joining the network and, separately, one leaving it
+#[derive(Debug, Clone)]
+pub struct MembershipEvents {
+    // The network once the node joined
+    pub joined: NodesVec,
+    pub joiner: usize,
+    // The network once the node left, `None` when no constrained node belongs to a cluster
+    pub departed: Option<(NodesVec, Departure)>,
+}
+
+// Draw the events every scheme is charged for. The leaving node is drawn among the members of
+// a cluster, so its departure has a group key to refresh.
+pub fn draw_membership_events<R: Rng + ?Sized>(
+    nodes: &NodesVec,
+    network: &NetworkConfig,
+    rng: &mut R,
+) -> MembershipEvents {
+    let mut joined = nodes.clone();
+    let joiner = network.add_node(&mut joined, NodeType::Constrained, rng);
+
+    let members: Vec<usize> = nodes
+        .iter()
+        .filter(|node| {
+            node.kind == NodeType::Constrained && node.is_active() && node.gateway.is_some()
+        })
+        .map(|node| node.id)
+        .collect();
+    let departed = members.choose(rng).map(|&leaving| {
+        let mut departed = nodes.clone();
+        let departure = departed.remove_node(leaving);
+        (departed, departure)
+    });
+
+    MembershipEvents {
+        joined,
+        joiner,
+        departed,
+    }
+}
+
+pub fn join_cost(
+    scheme: &dyn KeyManagementScheme,
+    events: &MembershipEvents,
+    config: &SimulationConfig,
+    mac_size: u32,
+    material: &KeyMaterial,
+) -> MembershipCost {
+    let mut ledger = EnergyLedger::for_network(&events.joined);
+    scheme.record_join(
+        &events.joined,
+        config,
+        mac_size,
+        material,
+        events.joiner,
+        &mut ledger,
+    );
+    MembershipCost::from_ledger(&ledger)
+}
+
+pub fn leave_cost(
+    scheme: &dyn KeyManagementScheme,
+    events: &MembershipEvents,
+    config: &SimulationConfig,
+) -> Option<MembershipCost> {
+    let (departed, departure) = events.departed.as_ref()?;
+    let mut ledger = EnergyLedger::for_network(departed);
+    scheme.record_leave(departed, config, departure, &mut ledger);
+    Some(MembershipCost::from_ledger(&ledger))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        methods::{evkms::Evkms, matrix::Matrix, RekeyTrigger},
+        rng::rng_from_seed,
+        topology::{assign_gateway_clusters, initialize_network_from_seed},
+    };
+
+    fn network_config() -> NetworkConfig {
+        NetworkConfig::from_lookup(|key| match key {
+            "NUMBER_OF_NODES" => Some("60".to_string()),
+            "NUMBER_OF_GATEWAYS" => Some("6".to_string()),
+            "NUMBER_OF_MIN_POSSIBLE_NEIGHBORS" => Some("3".to_string()),
+            "NUMBER_OF_MAX_POSSIBLE_NEIGHBORS" => Some("8".to_string()),
+            "NUMBER_OF_GATEWAY_MEMBERS" => Some("12".to_string()),
+            _ => None,
+        })
+        .unwrap()
+    }
+
+    fn events(seed: u64) -> (NodesVec, MembershipEvents) {
+        let mut nodes = initialize_network_from_seed(60, 6, 3, 8, seed);
+        assign_gateway_clusters(&mut nodes, 12, &mut rng_from_seed(seed));
+        let events = draw_membership_events(&nodes, &network_config(), &mut rng_from_seed(seed));
+        (nodes, events)
+    }
+
+    // Cryptographic operations of `after` not in `before`, messages are left out: the neighbors
+    // of a joiner say hello again
+    fn added(after: &EnergyLedger, before: &EnergyLedger) -> [u32; 4] {
+        let (after, before) = (after.operations(), before.operations());
+        [
+            after.hashes - before.hashes,
+            after.encryptions - before.encryptions,
+            after.decryptions - before.decryptions,
+            after.multiplications - before.multiplications,
+        ]
+    }
+
+    fn computation(operations: OperationCounts) -> [u32; 4] {
+        [
+            operations.hashes,
+            operations.encryptions,
+            operations.decryptions,
+            operations.multiplications,
+        ]
+    }
+
+    fn cluster_rekey(
+        scheme: &dyn KeyManagementScheme,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        gateway: Option<usize>,
+    ) -> EnergyLedger {
+        let mut ledger = EnergyLedger::for_network(nodes);
+        for cluster in nodes.clusters() {
+            if Some(cluster.gateway) == gateway {
+                scheme.record_group_rekey(
+                    nodes,
+                    config,
+                    &cluster,
+                    RekeyTrigger::Exclusion,
+                    &mut ledger,
+                );
+            }
+        }
+        ledger
+    }
+
+    #[test]
+    fn a_join_costs_what_keying_the_grown_network_adds() {
+        let config = SimulationConfig::default();
+        let material = KeyMaterial::default();
+        let schemes: [&dyn KeyManagementScheme; 2] = [&Matrix, &Evkms];
+        let mut clustered = 0;
+        for seed in 0..3 {
+            let (nodes, events) = events(seed);
+            // `None` when no cluster next to the joiner has room
+            let gateway = events.joined[events.joiner].gateway;
+            clustered += gateway.iter().count();
+            for scheme in schemes {
+                let mut before = EnergyLedger::for_network(&nodes);
+                scheme.record_pairwise(&nodes, &config, 8, &material, &mut before);
+                let mut after = EnergyLedger::for_network(&events.joined);
+                scheme.record_pairwise(&events.joined, &config, 8, &material, &mut after);
+                let cluster = cluster_rekey(scheme, &events.joined, &config, gateway);
+
+                let mut expected = added(&after, &before);
+                for (operations, rekey) in
+                    expected.iter_mut().zip(computation(cluster.operations()))
+                {
+                    *operations += rekey;
+                }
+                let cost = join_cost(scheme, &events, &config, 8, &material);
+                assert_eq!(
+                    computation(cost.operations),
+                    expected,
+                    "{} {}",
+                    scheme.name(),
+                    seed
+                );
+            }
+        }
+        assert!(clustered > 0);
+    }
+
+    #[test]
+    fn a_leave_costs_the_key_of_the_former_cluster() {
+        let config = SimulationConfig::default();
+        for seed in 0..3 {
+            let (_, events) = events(seed);
+            let (departed, departure) = events.departed.as_ref().unwrap();
+            let gateway = departure.gateway.unwrap();
+            // Every group key sent again, less those of the other clusters
+            let mut all = EnergyLedger::for_network(departed);
+            Matrix.record_groupwise(departed, &config, &mut all);
+            let mut others = EnergyLedger::for_network(departed);
+            for cluster in departed.clusters() {
+                if cluster.gateway != gateway {
+                    Matrix.record_group_rekey(
+                        departed,
+                        &config,
+                        &cluster,
+                        RekeyTrigger::Exclusion,
+                        &mut others,
+                    );
+                }
+            }
+            let cost = leave_cost(&Matrix, &events, &config).unwrap();
+            assert_eq!(computation(cost.operations), added(&all, &others));
+            assert_eq!(
+                cost.bytes_sent as f32,
+                all.bytes_sent() - others.bytes_sent()
+            );
+            assert_eq!(cost.operations.multiplications, 0);
+            assert_eq!(
+                cost,
+                MembershipCost::from_ledger(&cluster_rekey(
+                    &Matrix,
+                    departed,
+                    &config,
+                    Some(gateway)
+                ))
+            );
+        }
+    }
+}
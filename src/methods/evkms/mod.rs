@@ -0,0 +1,413 @@
+use std::ops::AddAssign;
+
+use crate::{
+    config::SimulationConfig,
+    ledger::EnergyLedger,
+    radio::broadcast_distance,
+    routing::RoutingTable,
+    simulation::{Engine, Message, MessageKind, PairwiseProgress, Protocol},
+    Cluster, NodeType, NodesVec,
+};
+
+use super::{
+    pairwise_participants, record_cluster_key_transport, KeyManagementScheme, KeyMaterial,
+    RekeyTrigger,
+};
+
+#[cfg(feature = "crypto")]
+pub mod protocol;
+
+// Cryptographic operations performed by one node
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Operations {
+    pub hashes: u32,
+    pub multiplications: u32,
+}
+
+impl AddAssign for Operations {
+    fn add_assign(&mut self, other: Self) {
+        self.hashes += other.hashes;
+        self.multiplications += other.multiplications;
+    }
+}
+
+// Neighbors of every node of a measured topology and the operations of each node
+#[cfg(feature = "crypto")]
+type Measurement = (Vec<Vec<usize>>, Vec<Operations>);
+
+// Operations of each node during the pairwise round, measured on the reference implementation.
+// The counts do not depend on the key material, so a fixed seed is enough.
+#[cfg(feature = "crypto")]
+pub fn pairwise_operations(nodes: &NodesVec) -> Vec<Operations> {
+    // Every metric of an iteration measures the same topology on the same thread, so each thread
+    // keeps the counts of the last topology it measured
+    thread_local! {
+        static LAST_MEASURED: std::cell::RefCell<Option<Measurement>> =
+            const { std::cell::RefCell::new(None) };
+    }
+    let topology: Vec<Vec<usize>> = nodes.iter().map(|node| node.neighbors.clone()).collect();
+    LAST_MEASURED.with(|last| {
+        let mut last = last.borrow_mut();
+        if let Some((measured, operations)) = last.as_ref() {
+            if *measured == topology {
+                return operations.clone();
+            }
+        }
+        let mut rng = crate::rng::rng_from_seed(0);
+        let mut deployment = protocol::Deployment::predistribute(nodes, &mut rng);
+        deployment.establish_pairwise(nodes);
+        let operations = deployment.operations().to_vec();
+        *last = Some((topology, operations.clone()));
+        operations
+    })
+}
+
+// Operations of each node during the pairwise round: one share evaluation, one key derivation
+// and one confirmation tag per neighbor
+#[cfg(not(feature = "crypto"))]
+pub fn pairwise_operations(nodes: &NodesVec) -> Vec<Operations> {
+    modeled_pairwise_operations(nodes)
+}
+
+#[cfg(any(test, not(feature = "crypto")))]
+fn modeled_pairwise_operations(nodes: &NodesVec) -> Vec<Operations> {
+    let mut operations = vec![Operations::default(); nodes.len()];
+    for node in nodes.iter() {
+        operations[node.id] = Operations {
+            hashes: 2 * node.neighbors.len() as u32,
+            multiplications: node.neighbors.len() as u32,
+        };
+    }
+    operations
+}
+
+// Operations of each node during the group round, measured on the reference implementation
+#[cfg(feature = "crypto")]
+pub fn group_operations(nodes: &NodesVec) -> Vec<Operations> {
+    let mut rng = crate::rng::rng_from_seed(0);
+    let mut deployment = protocol::Deployment::predistribute(nodes, &mut rng);
+    deployment.establish_group(nodes, 0);
+    deployment.operations().to_vec()
+}
+
+// Operations of each node during the group round: each member derives the key of its cluster
+// with one hash
+#[cfg(not(feature = "crypto"))]
+pub fn group_operations(nodes: &NodesVec) -> Vec<Operations> {
+    modeled_group_operations(nodes)
+}
+
+#[cfg(any(test, not(feature = "crypto")))]
+fn modeled_group_operations(nodes: &NodesVec) -> Vec<Operations> {
+    let mut operations = vec![Operations::default(); nodes.len()];
+    for cluster in nodes.clusters() {
+        for &member in cluster.members.iter() {
+            operations[member].hashes += 1;
+        }
+    }
+    operations
+}
+
+fn total(operations: &[Operations]) -> Operations {
+    let mut total = Operations::default();
+    for node in operations.iter() {
+        total += *node;
+    }
+    total
+}
+
+pub struct Evkms;
+
+// Every node broadcasts a hello and derives the key shared with a neighbor when it handles the
+// hello of that neighbor. Unlike the closed form, gateways say hello too so their neighbors can
+// key with them. Members then hash the seed of their cluster.
+pub struct EvkmsProtocol {
+    progress: PairwiseProgress,
+    hello_size: f32,
+}
+
+impl EvkmsProtocol {
+    fn finish(node: usize, engine: &mut Engine) {
+        let config = engine.config();
+        let member = &engine.nodes()[node];
+        if member.kind == NodeType::Constrained && member.gateway.is_some() {
+            engine.ledger().hashes(node, 1, config);
+        }
+        engine.complete(node);
+    }
+}
+
+impl Protocol for EvkmsProtocol {
+    fn start(&mut self, node: usize, engine: &mut Engine) {
+        engine.send(Message::broadcast(
+            MessageKind::Hello,
+            node,
+            self.hello_size,
+        ));
+        if self.progress.is_done(node) {
+            Self::finish(node, engine);
+        }
+    }
+
+    fn receive(&mut self, node: usize, message: &Message, engine: &mut Engine) {
+        if message.kind != MessageKind::Hello {
+            return;
+        }
+        let config = engine.config();
+        engine.ledger().multiplications(node, 1, config);
+        engine.ledger().hashes(node, 2, config);
+        if self.progress.resolve(node, message.from) {
+            Self::finish(node, engine);
+        }
+    }
+
+    fn unsettled_links(&self) -> usize {
+        self.progress.unsettled_links()
+    }
+}
+
+impl KeyManagementScheme for Evkms {
+    fn name(&self) -> &'static str {
+        "evkms"
+    }
+
+    fn number_of_multiplications(&self, nodes: &NodesVec, _config: &SimulationConfig) -> u32 {
+        total(&pairwise_operations(nodes)).multiplications
+    }
+
+    fn number_pairwise_hashes(
+        &self,
+        nodes: &NodesVec,
+        _config: &SimulationConfig,
+        _material: &KeyMaterial,
+    ) -> u32 {
+        total(&pairwise_operations(nodes)).hashes
+    }
+
+    fn number_of_pairwise_encryptions(
+        &self,
+        _nodes: &NodesVec,
+        _config: &SimulationConfig,
+        _material: &KeyMaterial,
+    ) -> u32 {
+        0
+    }
+
+    fn pairwise_communication_energy(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+    ) -> f32 {
+        let mut energy = 0.0;
+        for node in pairwise_participants(nodes) {
+            if node.kind == NodeType::Gateway {
+                continue;
+            }
+            energy += config.transmit_energy(
+                config.hello_message_size(mac_size),
+                broadcast_distance(nodes, node.id, config),
+            );
+            energy += config
+                .receive_energy(node.neighbors.len() as f32 * config.hello_message_size(mac_size));
+        }
+        energy
+    }
+
+    fn pairwise_communication_sent(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+    ) -> f32 {
+        let mut sent = 0.0;
+        for node in pairwise_participants(nodes) {
+            if node.kind == NodeType::Gateway {
+                continue;
+            }
+            sent += config.hello_message_size(mac_size);
+        }
+        sent
+    }
+
+    fn pairwise_communication_received(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+    ) -> f32 {
+        let mut received = 0.0;
+        for node in pairwise_participants(nodes) {
+            if node.kind == NodeType::Gateway {
+                continue;
+            }
+            received += node.neighbors.len() as f32 * config.hello_message_size(mac_size);
+        }
+        received
+    }
+
+    fn groupwise_communication_energy(&self, _nodes: &NodesVec, _config: &SimulationConfig) -> f32 {
+        0.0
+    }
+
+    fn groupwise_encryptions_energy(&self, _nodes: &NodesVec, _config: &SimulationConfig) -> f32 {
+        0.0
+    }
+
+    fn groupwise_hashes_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32 {
+        total(&group_operations(nodes)).hashes as f32 * config.hash_energy
+    }
+
+    fn record_pairwise(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+        ledger: &mut EnergyLedger,
+    ) {
+        let operations = pairwise_operations(nodes);
+        for node in pairwise_participants(nodes) {
+            let neighbors_count = node.neighbors.len() as u32;
+            ledger.multiplications(node.id, operations[node.id].multiplications, config);
+            ledger.hashes(node.id, operations[node.id].hashes, config);
+            if node.kind == NodeType::Gateway {
+                continue;
+            }
+            ledger.transmit(
+                node.id,
+                config.hello_message_size(mac_size),
+                broadcast_distance(nodes, node.id, config),
+                config,
+            );
+            ledger.receive(
+                node.id,
+                neighbors_count as f32 * config.hello_message_size(mac_size),
+                config,
+            );
+        }
+    }
+
+    fn record_groupwise(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        ledger: &mut EnergyLedger,
+    ) {
+        for (node, operations) in group_operations(nodes).iter().enumerate() {
+            ledger.hashes(node, operations.hashes, config);
+        }
+    }
+
+    // Scheduled refreshes ratchet every pairwise key with one hash, K' = H(K). A compromised
+    // node could ratchet its own keys too, so the keys are established again with fresh nonces.
+    fn record_pairwise_rekey(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        material: &KeyMaterial,
+        trigger: RekeyTrigger,
+        ledger: &mut EnergyLedger,
+    ) {
+        match trigger {
+            RekeyTrigger::Periodic => {
+                for node in pairwise_participants(nodes) {
+                    ledger.hashes(node.id, node.neighbors.len() as u32, config);
+                }
+            }
+            RekeyTrigger::Exclusion => {
+                self.record_pairwise(nodes, config, mac_size, material, ledger)
+            }
+        }
+    }
+
+    // Scheduled refreshes move every member to the next epoch of the cluster seed with one
+    // hash. A departed or compromised node holds the seed, so the gateway sends a new one to
+    // the remaining members.
+    fn record_group_rekey(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        cluster: &Cluster,
+        trigger: RekeyTrigger,
+        ledger: &mut EnergyLedger,
+    ) {
+        match trigger {
+            RekeyTrigger::Periodic => {
+                for &member in cluster.members.iter() {
+                    ledger.hashes(member, 1, config);
+                }
+            }
+            RekeyTrigger::Exclusion => {
+                let routes = RoutingTable::to_gateways(nodes);
+                record_cluster_key_transport(nodes, config, &routes, cluster, ledger);
+            }
+        }
+    }
+
+    fn protocol(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+    ) -> Box<dyn Protocol> {
+        Box::new(EvkmsProtocol {
+            progress: PairwiseProgress::new(nodes),
+            hello_size: config.hello_message_size(mac_size),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rng::rng_from_seed,
+        topology::{assign_gateway_clusters, initialize_network_from_seed},
+    };
+
+    fn network(seed: u64) -> NodesVec {
+        let mut nodes = initialize_network_from_seed(60, 6, 3, 8, seed);
+        assign_gateway_clusters(&mut nodes, 10, &mut rng_from_seed(seed));
+        nodes
+    }
+
+    // Without the crypto feature both sides are the model, with it the reference implementation
+    // must count what the model charges
+    #[test]
+    fn reference_and_model_count_the_same_operations() {
+        for seed in 0..5 {
+            let nodes = network(seed);
+            assert_eq!(
+                pairwise_operations(&nodes),
+                modeled_pairwise_operations(&nodes)
+            );
+            assert_eq!(group_operations(&nodes), modeled_group_operations(&nodes));
+        }
+    }
+
+    // The counts of the last topology are kept, they must not outlive a change of its links
+    #[test]
+    fn counts_follow_a_change_of_topology() {
+        let mut nodes = network(0);
+        assert_eq!(
+            pairwise_operations(&nodes),
+            modeled_pairwise_operations(&nodes)
+        );
+        let node = nodes
+            .iter()
+            .find(|node| !node.neighbors.is_empty())
+            .expect("the network has links")
+            .id;
+        nodes.remove_node(node);
+        assert_eq!(
+            pairwise_operations(&nodes),
+            modeled_pairwise_operations(&nodes)
+        );
+    }
+}
@@ -0,0 +1,414 @@
+use std::{collections::HashMap, error::Error, fmt};
+
+use hmac::{Hmac, Mac};
+use rand::{Rng, RngCore};
+use sha2::{Digest, Sha256};
+
+use crate::{NodeType, NodesVec};
+
+use super::Operations;
+
+// Degree in each variable of the symmetric polynomial the shares are drawn from. A single
+// polynomial of such a low degree falls to two captured shares, so the reference only
+// exercises the operation counts and the scheme model reports no collusion threshold.
+pub const POLYNOMIAL_DEGREE: u32 = 1;
+
+// Shares are evaluated in the Mersenne prime field of 2^61 - 1
+pub const FIELD_PRIME: u64 = (1 << 61) - 1;
+
+pub type Key = [u8; 32];
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn field_reduce(value: u128) -> u64 {
+    // 2^61 ≡ 1, so the high bits fold back onto the low bits
+    let folded = (value & FIELD_PRIME as u128) + (value >> 61);
+    let folded = ((folded & FIELD_PRIME as u128) + (folded >> 61)) as u64;
+    if folded >= FIELD_PRIME {
+        folded - FIELD_PRIME
+    } else {
+        folded
+    }
+}
+
+fn field_add(a: u64, b: u64) -> u64 {
+    field_reduce(a as u128 + b as u128)
+}
+
+pub fn field_mul(a: u64, b: u64) -> u64 {
+    field_reduce(a as u128 * b as u128)
+}
+
+// Node ids are shifted by one so no node evaluates the polynomial at zero
+fn field_point(node: usize) -> u64 {
+    field_reduce(node as u128 + 1)
+}
+
+// Material loaded on a node before deployment
+#[derive(Debug, Clone)]
+pub struct NodeMaterial {
+    // f(i, y) = share[0] + share[1]·y for the symmetric polynomial
+    // f(x, y) = a + b·(x + y) + c·x·y
+    pub share: [u64; POLYNOMIAL_DEGREE as usize + 1],
+    // Fresh value broadcast in the hello message
+    pub nonce: u64,
+    // Seed of the cluster key, shared by the gateway and its members
+    pub cluster_seed: Option<Key>,
+}
+
+// Pairwise key of both ends of a link and the confirmation tag each end computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairwiseKey {
+    pub key: Key,
+    pub tag: Key,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyMismatch {
+    // The two ends of a link derived different pairwise keys
+    Pairwise { node: usize, neighbor: usize },
+    // A member derived a cluster key different from the one of its gateway
+    Group { gateway: usize, member: usize },
+    // Two clusters derived the same key, the members of each could read the other
+    SharedGroupKey { gateway: usize, other: usize },
+    // A node outside of every cluster holds a cluster key
+    Outsider { node: usize },
+}
+
+impl fmt::Display for KeyMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyMismatch::Pairwise { node, neighbor } => write!(
+                f,
+                "nodes {} and {} derived different pairwise keys",
+                node, neighbor
+            ),
+            KeyMismatch::Group { gateway, member } => write!(
+                f,
+                "member {} derived a group key different from gateway {}",
+                member, gateway
+            ),
+            KeyMismatch::SharedGroupKey { gateway, other } => write!(
+                f,
+                "the clusters of gateways {} and {} derived the same group key",
+                gateway, other
+            ),
+            KeyMismatch::Outsider { node } => {
+                write!(f, "node {} holds a group key outside of any cluster", node)
+            }
+        }
+    }
+}
+
+impl Error for KeyMismatch {}
+
+// EVKMS run on a whole network, every operation is counted on the node performing it
+#[derive(Debug, Clone)]
+pub struct Deployment {
+    material: Vec<NodeMaterial>,
+    operations: Vec<Operations>,
+    // Keys derived by each end of each link, indexed by (node, neighbor)
+    pairwise: HashMap<(usize, usize), PairwiseKey>,
+    // Cluster key derived by each node, `None` until the group round ran
+    group: Vec<Option<Key>>,
+    // The key each gateway derived when it generated the seed, before deployment
+    gateway_keys: HashMap<usize, Key>,
+}
+
+impl Deployment {
+    // Draw the secret polynomial and load every node with its share, a nonce and the seed of
+    // the cluster it was assigned to
+    pub fn predistribute(nodes: &NodesVec, rng: &mut dyn RngCore) -> Self {
+        let a = rng.gen_range(0..FIELD_PRIME);
+        let b = rng.gen_range(0..FIELD_PRIME);
+        let c = rng.gen_range(0..FIELD_PRIME);
+
+        let mut seeds: HashMap<usize, Key> = HashMap::new();
+        for cluster in nodes.clusters() {
+            let mut seed = [0u8; 32];
+            rng.fill_bytes(&mut seed);
+            seeds.insert(cluster.gateway, seed);
+        }
+
+        let mut material = Vec::with_capacity(nodes.len());
+        for node in nodes.iter() {
+            let x = field_point(node.id);
+            let cluster = if node.kind == NodeType::Gateway {
+                Some(node.id)
+            } else {
+                node.gateway
+            };
+            material.push(NodeMaterial {
+                share: [field_add(a, field_mul(b, x)), field_add(b, field_mul(c, x))],
+                nonce: rng.next_u64(),
+                cluster_seed: cluster.and_then(|gateway| seeds.get(&gateway).copied()),
+            });
+        }
+
+        Self {
+            operations: vec![Operations::default(); material.len()],
+            material,
+            pairwise: HashMap::new(),
+            group: vec![None; nodes.len()],
+            gateway_keys: HashMap::new(),
+        }
+    }
+
+    pub fn material(&self, node: usize) -> &NodeMaterial {
+        &self.material[node]
+    }
+
+    pub fn operations(&self) -> &[Operations] {
+        &self.operations
+    }
+
+    // f(node, neighbor) from the share of the node, one multiplication
+    fn evaluate_share(&mut self, node: usize, neighbor: usize) -> u64 {
+        let share = self.material[node].share;
+        self.operations[node].multiplications += 1;
+        field_add(share[0], field_mul(share[1], field_point(neighbor)))
+    }
+
+    // K = H(f(i, j) || n_low || n_high), the nonces ordered by node id so both ends agree
+    fn derive_pairwise_key(&mut self, node: usize, neighbor: usize) -> Key {
+        let secret = self.evaluate_share(node, neighbor);
+        let (low, high) = if node < neighbor {
+            (node, neighbor)
+        } else {
+            (neighbor, node)
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(secret.to_be_bytes());
+        hasher.update(self.material[low].nonce.to_be_bytes());
+        hasher.update(self.material[high].nonce.to_be_bytes());
+        self.operations[node].hashes += 1;
+        hasher.finalize().into()
+    }
+
+    // HMAC of both ids under the pairwise key, compared with the tag of the neighbor
+    fn confirmation_tag(&mut self, node: usize, neighbor: usize, key: &Key) -> Key {
+        let (low, high) = if node < neighbor {
+            (node, neighbor)
+        } else {
+            (neighbor, node)
+        };
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(&(low as u64).to_be_bytes());
+        mac.update(&(high as u64).to_be_bytes());
+        self.operations[node].hashes += 1;
+        mac.finalize().into_bytes().into()
+    }
+
+    // Every node derives a key with each of its neighbors from their hello messages
+    pub fn establish_pairwise(&mut self, nodes: &NodesVec) {
+        for node in nodes.iter() {
+            for &neighbor in node.neighbors.iter() {
+                let key = self.derive_pairwise_key(node.id, neighbor);
+                let tag = self.confirmation_tag(node.id, neighbor, &key);
+                self.pairwise
+                    .insert((node.id, neighbor), PairwiseKey { key, tag });
+            }
+        }
+    }
+
+    fn cluster_key(seed: &Key, epoch: u64) -> Key {
+        let mut hasher = Sha256::new();
+        hasher.update(seed);
+        hasher.update(epoch.to_be_bytes());
+        hasher.finalize().into()
+    }
+
+    // Members derive the key of their cluster for the epoch with one hash of the seed
+    pub fn establish_group(&mut self, nodes: &NodesVec, epoch: u64) {
+        for cluster in nodes.clusters() {
+            if let Some(seed) = self.material[cluster.gateway].cluster_seed {
+                let key = Self::cluster_key(&seed, epoch);
+                self.gateway_keys.insert(cluster.gateway, key);
+                self.group[cluster.gateway] = Some(key);
+            }
+            for &member in cluster.members.iter() {
+                if let Some(seed) = self.material[member].cluster_seed {
+                    self.operations[member].hashes += 1;
+                    self.group[member] = Some(Self::cluster_key(&seed, epoch));
+                }
+            }
+        }
+    }
+
+    pub fn pairwise_key(&self, node: usize, neighbor: usize) -> Option<&PairwiseKey> {
+        self.pairwise.get(&(node, neighbor))
+    }
+
+    pub fn group_key(&self, node: usize) -> Option<&Key> {
+        self.group[node].as_ref()
+    }
+
+    // Both ends of every link hold the same key and the same confirmation tag
+    pub fn verify_pairwise(&self, nodes: &NodesVec) -> Result<(), KeyMismatch> {
+        for node in nodes.iter() {
+            for &neighbor in node.neighbors.iter() {
+                let mismatch = KeyMismatch::Pairwise {
+                    node: node.id,
+                    neighbor,
+                };
+                let (Some(ours), Some(theirs)) = (
+                    self.pairwise_key(node.id, neighbor),
+                    self.pairwise_key(neighbor, node.id),
+                ) else {
+                    return Err(mismatch);
+                };
+                if ours != theirs {
+                    return Err(mismatch);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Every member holds the key of its gateway, no two clusters share a key and no node outside
+    // of the clusters holds one
+    pub fn verify_group(&self, nodes: &NodesVec) -> Result<(), KeyMismatch> {
+        let mut in_cluster: Vec<bool> = vec![false; nodes.len()];
+        let mut owners: HashMap<Key, usize> = HashMap::new();
+        for cluster in nodes.clusters() {
+            in_cluster[cluster.gateway] = true;
+            let expected = self.gateway_keys.get(&cluster.gateway);
+            if let Some(key) = expected {
+                if let Some(&other) = owners.get(key) {
+                    return Err(KeyMismatch::SharedGroupKey {
+                        gateway: cluster.gateway,
+                        other,
+                    });
+                }
+                owners.insert(*key, cluster.gateway);
+            }
+            for &member in cluster.members.iter() {
+                in_cluster[member] = true;
+                if expected.is_none() || self.group_key(member) != expected {
+                    return Err(KeyMismatch::Group {
+                        gateway: cluster.gateway,
+                        member,
+                    });
+                }
+            }
+        }
+        for node in nodes.iter() {
+            if !in_cluster[node.id] && self.group_key(node.id).is_some() {
+                return Err(KeyMismatch::Outsider { node: node.id });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rng::rng_from_seed,
+        topology::{assign_gateway_clusters, initialize_network_from_seed},
+    };
+
+    fn network(seed: u64) -> NodesVec {
+        let mut nodes = initialize_network_from_seed(60, 6, 3, 8, seed);
+        assign_gateway_clusters(&mut nodes, 10, &mut rng_from_seed(seed));
+        nodes
+    }
+
+    #[test]
+    fn both_ends_derive_the_same_pairwise_key() {
+        for seed in 0..5 {
+            let nodes = network(seed);
+            let mut deployment = Deployment::predistribute(&nodes, &mut rng_from_seed(seed));
+            deployment.establish_pairwise(&nodes);
+            assert_eq!(deployment.verify_pairwise(&nodes), Ok(()));
+        }
+    }
+
+    #[test]
+    fn members_derive_the_key_of_their_gateway() {
+        for seed in 0..5 {
+            let nodes = network(seed);
+            let mut deployment = Deployment::predistribute(&nodes, &mut rng_from_seed(seed));
+            deployment.establish_group(&nodes, 3);
+            assert_eq!(deployment.verify_group(&nodes), Ok(()));
+        }
+    }
+
+    #[test]
+    fn a_member_with_the_seed_of_another_cluster_is_detected() {
+        let nodes = network(0);
+        let clusters = nodes.clusters();
+        let mut deployment = Deployment::predistribute(&nodes, &mut rng_from_seed(0));
+        let member = clusters[0].members[0];
+        deployment.material[member].cluster_seed =
+            deployment.material[clusters[1].gateway].cluster_seed;
+        deployment.establish_group(&nodes, 0);
+        assert_eq!(
+            deployment.verify_group(&nodes),
+            Err(KeyMismatch::Group {
+                gateway: clusters[0].gateway,
+                member,
+            })
+        );
+    }
+
+    #[test]
+    fn clusters_sharing_a_seed_are_detected() {
+        let nodes = network(0);
+        let clusters = nodes.clusters();
+        let mut deployment = Deployment::predistribute(&nodes, &mut rng_from_seed(0));
+        let seed = deployment.material[clusters[0].gateway].cluster_seed;
+        for &node in [clusters[1].gateway]
+            .iter()
+            .chain(clusters[1].members.iter())
+        {
+            deployment.material[node].cluster_seed = seed;
+        }
+        deployment.establish_group(&nodes, 0);
+        assert_eq!(
+            deployment.verify_group(&nodes),
+            Err(KeyMismatch::SharedGroupKey {
+                gateway: clusters[1].gateway,
+                other: clusters[0].gateway,
+            })
+        );
+    }
+
+    #[test]
+    fn a_key_outside_of_the_clusters_is_detected() {
+        // Clusters of two members leave most of the nodes out
+        let mut nodes = initialize_network_from_seed(30, 3, 3, 8, 0);
+        assign_gateway_clusters(&mut nodes, 2, &mut rng_from_seed(0));
+        let mut deployment = Deployment::predistribute(&nodes, &mut rng_from_seed(0));
+        deployment.establish_group(&nodes, 0);
+        assert_eq!(deployment.verify_group(&nodes), Ok(()));
+        let outsider = nodes
+            .iter()
+            .find(|node| node.kind == NodeType::Constrained && node.gateway.is_none())
+            .expect("the clusters cannot hold every node")
+            .id;
+        deployment.group[outsider] = deployment.group[nodes.clusters()[0].gateway];
+        assert_eq!(
+            deployment.verify_group(&nodes),
+            Err(KeyMismatch::Outsider { node: outsider })
+        );
+    }
+
+    #[test]
+    fn a_wrong_share_is_detected() {
+        let nodes = network(0);
+        let mut deployment = Deployment::predistribute(&nodes, &mut rng_from_seed(0));
+        let node = nodes
+            .iter()
+            .find(|node| !node.neighbors.is_empty())
+            .expect("the network has links")
+            .id;
+        deployment.material[node].share[0] = field_add(deployment.material[node].share[0], 1);
+        deployment.establish_pairwise(&nodes);
+        assert!(matches!(
+            deployment.verify_pairwise(&nodes),
+            Err(KeyMismatch::Pairwise { .. })
+        ));
+    }
+}
@@ -1,172 +1,447 @@
-use std::env;
-
-use lazy_static::lazy_static;
-
-use crate::{NodeType, NodesVec};
-
-lazy_static! {
-    static ref EPSB: f32 = env::var("EPSB")
-        .unwrap_or(0.0001.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref EPRB: f32 = env::var("EPRB")
-        .unwrap_or(0.0001.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref HASH_ENERGY: f32 = env::var("HASH_ENERGY")
-        .unwrap_or(0.0001.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref ENCRYPTION_ENERGY: f32 = env::var("ENCRYPTION_ENERGY")
-        .unwrap_or(0.0001.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref SENT_MESSAGE_SIZE: u32 = env::var("SENT_MESSAGE_SIZE")
-        .unwrap_or(16.to_string())
-        .parse::<u32>()
-        .unwrap();
-    static ref RECEIVED_MESSAGE_SIZE: u32 = env::var("RECEIVED_MESSAGE_SIZE")
-        .unwrap_or(16.to_string())
-        .parse::<u32>()
-        .unwrap();
-    static ref NODE_ID_SIZE: f32 = env::var("NODE_ID_SIZE")
-        .unwrap_or(4.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref MESSAGE_TYPE_SIZE: f32 = env::var("MESSAGE_TYPE_SIZE")
-        .unwrap_or(1.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref NONCE_SIZE: f32 = env::var("NONCE_SIZE")
-        .unwrap_or(4.to_string())
-        .parse::<f32>()
-        .unwrap();
+use crate::{
+    config::SimulationConfig,
+    ledger::EnergyLedger,
+    radio::{broadcast_distance, link_distance},
+    routing::RoutingTable,
+    simulation::{Engine, KeyTransport, Message, MessageKind, PairwiseProgress, Protocol},
+    Cluster, NodeType, NodesVec,
+};
+
+use super::{pairwise_participants, KeyManagementScheme, KeyMaterial, RekeyTrigger};
+
+pub struct Ifkms;
+
+// Neighbors of a member that belong to the cluster of `gateway`, the gateway included
+fn cluster_neighbors(nodes: &NodesVec, member: usize, gateway: usize) -> usize {
+    nodes[member]
+        .neighbors
+        .iter()
+        .filter(|&&neighbor| neighbor == gateway || nodes[neighbor].gateway == Some(gateway))
+        .count()
 }
 
-pub fn number_of_multiplications(nodes: NodesVec) -> u32 {
-    let mut number_of_multiplications = 0;
-    for node in nodes.iter() {
-        number_of_multiplications += node.neighbors.len() as u32;
+// The group key is carried from the gateway to every member it can reach along the route of
+// the member, each relay forwarding it. Members out of reach get nothing.
+fn record_group_key_delivery(
+    nodes: &NodesVec,
+    config: &SimulationConfig,
+    routes: &RoutingTable,
+    cluster: &Cluster,
+    ledger: &mut EnergyLedger,
+) {
+    let key_size = config.received_message_size as f32;
+    for &member in cluster.members.iter() {
+        let Some(relays) = routes.relays(member) else {
+            continue;
+        };
+        // Gateway, relays then member
+        let mut path: Vec<usize> = Vec::with_capacity(relays.len() + 2);
+        path.push(cluster.gateway);
+        path.extend_from_slice(relays);
+        path.push(member);
+        let last = path.len() - 1;
+        ledger.transmit(
+            cluster.gateway,
+            key_size,
+            link_distance(nodes, cluster.gateway, path[1], config),
+            config,
+        );
+        for i in 1..last {
+            let distance = link_distance(nodes, path[i], path[i + 1], config);
+            ledger.forward(path[i], key_size, distance, config);
+        }
+        ledger.receive(member, key_size, config);
     }
-    number_of_multiplications
 }
 
-pub fn number_pairwise_hashes(nodes: NodesVec) -> u32 {
-    let mut number_of_hashes = 0;
-    for node in nodes.iter() {
-        number_of_hashes += 2 * node.neighbors.len() as u32;
+// Group key agreement of a cluster: the members encrypt and hash for the neighbors of their
+// cluster and the key is delivered along the routes
+fn record_cluster_group_key(
+    nodes: &NodesVec,
+    config: &SimulationConfig,
+    routes: &RoutingTable,
+    cluster: &Cluster,
+    ledger: &mut EnergyLedger,
+) {
+    for &member in cluster.members.iter() {
+        let neighbors_count = cluster_neighbors(nodes, member, cluster.gateway) as u32;
+        ledger.encryptions(member, 2 * neighbors_count, config);
+        ledger.hashes(member, neighbors_count, config);
     }
-    number_of_hashes
+    record_group_key_delivery(nodes, config, routes, cluster, ledger);
 }
 
-pub fn number_of_pairwise_encryptions(_nodes: NodesVec) -> u32 {
-    0
+// Every node broadcasts a hello. On the hello of a neighbor both ends compute the key and the
+// end with the lower id sends the encrypted reply, which settles the link for the other end.
+// Members then encrypt and hash for the neighbors of their cluster, and once a gateway is keyed
+// with all its neighbors it delivers the group key to its members.
+pub struct IfkmsProtocol {
+    progress: PairwiseProgress,
+    transport: KeyTransport,
+    hello_size: f32,
+    reply_size: f32,
 }
 
-pub fn pairwise_communication_energy(nodes: NodesVec, mac_size: u32) -> f32 {
-    let mut energy = 0.0;
-    for node in nodes.iter() {
-        if node.kind == NodeType::Gateway {
-            continue;
+impl IfkmsProtocol {
+    // Pairwise keys are settled with every neighbor
+    fn keyed(&mut self, node: usize, engine: &mut Engine) {
+        let nodes = engine.nodes();
+        let config = engine.config();
+        match nodes[node].kind {
+            NodeType::Gateway => self.transport.start(node, engine),
+            NodeType::Constrained => {
+                if let Some(gateway) = nodes[node].gateway {
+                    let neighbors_count = cluster_neighbors(nodes, node, gateway) as u32;
+                    engine
+                        .ledger()
+                        .encryptions(node, 2 * neighbors_count, config);
+                    engine.ledger().hashes(node, neighbors_count, config);
+                }
+            }
         }
-        energy += (*MESSAGE_TYPE_SIZE + *NODE_ID_SIZE + *NONCE_SIZE + mac_size as f32)
-            * *EPSB;
-        energy += node.neighbors.len() as f32
-            * (*MESSAGE_TYPE_SIZE + *NODE_ID_SIZE + *NONCE_SIZE + mac_size as f32)
-            * *EPRB;
-        // Only 1/2 of nodes will send reply with an encrypted message and the other half will receive it
-        energy += node.neighbors.len() as f32 * mac_size as f32 * *EPSB / 2 as f32;
-        energy += node.neighbors.len() as f32 * mac_size as f32 * *EPRB / 2 as f32;
     }
-    energy
-}
 
-pub fn pairwise_communication_sent(nodes: NodesVec, mac_size: u32) -> f32 {
-    let mut sent = 0.0;
-    for node in nodes.iter() {
-        if node.kind == NodeType::Gateway {
-            continue;
+    fn try_complete(&self, node: usize, engine: &mut Engine) {
+        if self.progress.is_done(node) && self.transport.is_done(node) {
+            engine.complete(node);
         }
-        sent += *MESSAGE_TYPE_SIZE + *NODE_ID_SIZE + *NONCE_SIZE + mac_size as f32;
-        // Only 1/2 of nodes will send reply with an encrypted message and the other half will receive it
-        sent += node.neighbors.len() as f32 * mac_size as f32 / 2 as f32;
     }
-    sent
 }
 
-pub fn pairwise_communication_received(nodes: NodesVec, mac_size: u32) -> f32 {
-    let mut received = 0.0;
-    for node in nodes.iter() {
-        if node.kind == NodeType::Gateway {
-            continue;
+impl Protocol for IfkmsProtocol {
+    fn start(&mut self, node: usize, engine: &mut Engine) {
+        engine.send(Message::broadcast(
+            MessageKind::Hello,
+            node,
+            self.hello_size,
+        ));
+        if self.progress.is_done(node) {
+            self.keyed(node, engine);
         }
-        received += node.neighbors.len() as f32
-            * (*MESSAGE_TYPE_SIZE + *NODE_ID_SIZE + *NONCE_SIZE + mac_size as f32);
-        // Only 1/2 of nodes will send reply with an encrypted message and the other half will receive it
-        received += node.neighbors.len() as f32 * mac_size as f32 / 2 as f32;
+        self.try_complete(node, engine);
     }
-    received
-}
 
-pub fn groupwise_communication_energy(_nodes: NodesVec) -> f32 {
-    0.0
-}
+    fn receive(&mut self, node: usize, message: &Message, engine: &mut Engine) {
+        let config = engine.config();
+        let settled = match message.kind {
+            MessageKind::Hello => {
+                engine.ledger().multiplications(node, 1, config);
+                engine.ledger().hashes(node, 2, config);
+                if node < message.from {
+                    engine.send(Message::unicast(
+                        MessageKind::Reply,
+                        node,
+                        message.from,
+                        self.reply_size,
+                    ));
+                    true
+                } else {
+                    false
+                }
+            }
+            MessageKind::Reply => true,
+            _ => {
+                self.transport.receive(node, message, engine);
+                false
+            }
+        };
+        if settled && self.progress.resolve(node, message.from) {
+            self.keyed(node, engine);
+        }
+        self.try_complete(node, engine);
+    }
 
-pub fn groupwise_encryptions_energy(nodes: NodesVec) -> f32 {
-    let mut energy = 0.0;
-    let constrained_nodes: NodesVec = NodesVec(
-        nodes
-            .iter()
-            .filter(|node| node.kind == NodeType::Constrained)
-            .map(|node| node.clone())
-            .collect(),
-    );
-    for node in constrained_nodes.iter() {
-        energy += 2 as f32 * node.neighbors.len() as f32 * *ENCRYPTION_ENERGY;
-    }
-    energy
+    fn unsettled_links(&self) -> usize {
+        self.progress.unsettled_links()
+    }
 }
 
-pub fn groupwise_hashes_energy(nodes: NodesVec) -> f32 {
-    let mut energy = 0.0;
-    let constrained_nodes: NodesVec = NodesVec(
-        nodes
-            .iter()
-            .filter(|node| node.kind == NodeType::Constrained)
-            .map(|node| node.clone())
-            .collect(),
-    );
-    for node in constrained_nodes.iter() {
-        energy += node.neighbors.len() as f32 * *HASH_ENERGY;
-    }
-    energy
+impl KeyManagementScheme for Ifkms {
+    fn name(&self) -> &'static str {
+        "ifkms"
+    }
+
+    fn number_of_multiplications(&self, nodes: &NodesVec, _config: &SimulationConfig) -> u32 {
+        let mut number_of_multiplications = 0;
+        for node in nodes.iter() {
+            number_of_multiplications += node.neighbors.len() as u32;
+        }
+        number_of_multiplications
+    }
+
+    fn number_pairwise_hashes(
+        &self,
+        nodes: &NodesVec,
+        _config: &SimulationConfig,
+        _material: &KeyMaterial,
+    ) -> u32 {
+        let mut number_of_hashes = 0;
+        for node in nodes.iter() {
+            number_of_hashes += 2 * node.neighbors.len() as u32;
+        }
+        number_of_hashes
+    }
+
+    fn number_of_pairwise_encryptions(
+        &self,
+        _nodes: &NodesVec,
+        _config: &SimulationConfig,
+        _material: &KeyMaterial,
+    ) -> u32 {
+        0
+    }
+
+    fn pairwise_communication_energy(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+    ) -> f32 {
+        let mut energy = 0.0;
+        for node in pairwise_participants(nodes) {
+            if node.kind == NodeType::Gateway {
+                continue;
+            }
+            energy += config.transmit_energy(
+                config.hello_message_size(mac_size),
+                broadcast_distance(nodes, node.id, config),
+            );
+            energy += config
+                .receive_energy(node.neighbors.len() as f32 * config.hello_message_size(mac_size));
+            // Only 1/2 of nodes will send reply with an encrypted message and the other half will receive it
+            for &neighbor in node.neighbors.iter() {
+                energy += config.transmit_energy(
+                    mac_size as f32 / 2.0,
+                    link_distance(nodes, node.id, neighbor, config),
+                );
+            }
+            energy += config.receive_energy(node.neighbors.len() as f32 * mac_size as f32 / 2.0);
+        }
+        energy
+    }
+
+    fn pairwise_communication_sent(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+    ) -> f32 {
+        let mut sent = 0.0;
+        for node in pairwise_participants(nodes) {
+            if node.kind == NodeType::Gateway {
+                continue;
+            }
+            sent += config.hello_message_size(mac_size);
+            // Only 1/2 of nodes will send reply with an encrypted message and the other half will receive it
+            sent += node.neighbors.len() as f32 * mac_size as f32 / 2.0;
+        }
+        sent
+    }
+
+    fn pairwise_communication_received(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+    ) -> f32 {
+        let mut received = 0.0;
+        for node in pairwise_participants(nodes) {
+            if node.kind == NodeType::Gateway {
+                continue;
+            }
+            received += node.neighbors.len() as f32 * config.hello_message_size(mac_size);
+            // Only 1/2 of nodes will send reply with an encrypted message and the other half will receive it
+            received += node.neighbors.len() as f32 * mac_size as f32 / 2.0;
+        }
+        received
+    }
+
+    fn groupwise_communication_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32 {
+        let routes = RoutingTable::to_gateways(nodes);
+        let mut ledger = EnergyLedger::for_network(nodes);
+        for cluster in nodes.clusters() {
+            record_group_key_delivery(nodes, config, &routes, &cluster, &mut ledger);
+        }
+        ledger.total().communication()
+    }
+
+    fn groupwise_encryptions_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32 {
+        let mut energy = 0.0;
+        // The group key is forwarded hop by hop between neighbors of the same cluster
+        for cluster in nodes.clusters() {
+            for &member in cluster.members.iter() {
+                energy += 2.0
+                    * cluster_neighbors(nodes, member, cluster.gateway) as f32
+                    * config.encryption_energy;
+            }
+        }
+        energy
+    }
+
+    fn groupwise_hashes_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32 {
+        let mut energy = 0.0;
+        for cluster in nodes.clusters() {
+            for &member in cluster.members.iter() {
+                energy +=
+                    cluster_neighbors(nodes, member, cluster.gateway) as f32 * config.hash_energy;
+            }
+        }
+        energy
+    }
+
+    fn record_pairwise(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+        ledger: &mut EnergyLedger,
+    ) {
+        for node in pairwise_participants(nodes) {
+            let neighbors_count = node.neighbors.len() as u32;
+            ledger.multiplications(node.id, neighbors_count, config);
+            ledger.hashes(node.id, 2 * neighbors_count, config);
+            if node.kind == NodeType::Gateway {
+                continue;
+            }
+            ledger.transmit(
+                node.id,
+                config.hello_message_size(mac_size),
+                broadcast_distance(nodes, node.id, config),
+                config,
+            );
+            ledger.receive(
+                node.id,
+                neighbors_count as f32 * config.hello_message_size(mac_size),
+                config,
+            );
+            // Only 1/2 of nodes will send reply with an encrypted message and the other half will receive it
+            for &neighbor in node.neighbors.iter() {
+                ledger.transmit(
+                    node.id,
+                    mac_size as f32 / 2.0,
+                    link_distance(nodes, node.id, neighbor, config),
+                    config,
+                );
+            }
+            ledger.receive(
+                node.id,
+                neighbors_count as f32 * mac_size as f32 / 2.0,
+                config,
+            );
+        }
+    }
+
+    fn record_groupwise(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        ledger: &mut EnergyLedger,
+    ) {
+        let routes = RoutingTable::to_gateways(nodes);
+        for cluster in nodes.clusters() {
+            record_cluster_group_key(nodes, config, &routes, &cluster, ledger);
+        }
+    }
+
+    // The members agree on a new key the same way, whatever the trigger
+    fn record_group_rekey(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        cluster: &Cluster,
+        _trigger: RekeyTrigger,
+        ledger: &mut EnergyLedger,
+    ) {
+        let routes = RoutingTable::to_gateways(nodes);
+        record_cluster_group_key(nodes, config, &routes, cluster, ledger);
+    }
+
+    fn protocol(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+    ) -> Box<dyn Protocol> {
+        Box::new(IfkmsProtocol {
+            progress: PairwiseProgress::new(nodes),
+            transport: KeyTransport::unacknowledged(nodes, config.received_message_size as f32),
+            hello_size: config.hello_message_size(mac_size),
+            reply_size: mac_size as f32,
+        })
+    }
 }
 
-pub fn groupwise_computation_energy(nodes: NodesVec) -> f32 {
-    let mut energy = 0.0;
-    let constrained_nodes: NodesVec = NodesVec(
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rng::rng_from_seed,
+        simulation::simulate,
+        topology::{assign_gateway_clusters, initialize_network_from_seed},
+        Node,
+    };
+
+    // Gateway 0 with members 1, 3 and 4, gateway 5 with members 2 and 6. Node 2 relays for the
+    // cluster of 0 and node 4 has no link at all.
+    fn chain() -> NodesVec {
+        let links: [&[usize]; 7] = [&[1], &[0, 2], &[1, 3, 6], &[2], &[], &[6], &[5, 2]];
+        let gateways = [None, Some(0), Some(5), Some(0), Some(0), None, Some(5)];
+        let mut nodes = NodesVec::new();
+        for (id, neighbors) in links.iter().enumerate() {
+            let kind = if gateways[id].is_none() {
+                NodeType::Gateway
+            } else {
+                NodeType::Constrained
+            };
+            let mut node = Node::new(id, kind, neighbors.to_vec(), neighbors.len());
+            node.gateway = gateways[id];
+            nodes.push(node);
+        }
         nodes
-            .iter()
-            .filter(|node| node.kind == NodeType::Constrained)
-            .map(|node| node.clone())
-            .collect(),
-    );
-    // Calculate the hashes energy
-    let total_hash_energy: f32 = groupwise_hashes_energy(constrained_nodes.clone());
-    // Calculate the encryptions energy
-    let total_encryption_energy: f32 = groupwise_encryptions_energy(constrained_nodes.clone());
-    // Sum all the energies
-    energy += total_hash_energy + total_encryption_energy;
-    energy
-}
+    }
 
-pub fn groupwise_total_energy(nodes: NodesVec) -> f32 {
-    let mut energy = 0.0;
-    // Calculate the pairwise communication energy
-    let groupewise_communication_energy: f32 = groupwise_communication_energy(nodes.clone());
-    // Calculate the groupwise computation energy
-    let groupwise_computation_energy: f32 = groupwise_computation_energy(nodes.clone());
-    // Sum all the energies
-    energy += groupewise_communication_energy + groupwise_computation_energy;
-    energy
+    #[test]
+    fn group_key_is_forwarded_along_the_routes() {
+        let nodes = chain();
+        let config = SimulationConfig::default();
+        let key_size = config.received_message_size as f32;
+        let hop = config.transmit_energy(key_size, config.default_link_distance)
+            + config.receive_energy(key_size);
+        // 1, 3, 2 and 6 are 1, 3, 2 and 1 hops away, 4 cannot be reached
+        let expected = 7.0 * hop;
+        let energy = Ifkms.groupwise_communication_energy(&nodes, &config);
+        assert!((energy - expected).abs() < 1e-6 * expected, "{}", energy);
+
+        let mut ledger = EnergyLedger::for_network(&nodes);
+        Ifkms.record_groupwise(&nodes, &config, &mut ledger);
+        assert!((ledger.total().communication() - expected).abs() < 1e-6 * expected);
+        // Node 2 forwards the key of node 3 and receives its own
+        let relay = hop + config.receive_energy(key_size);
+        assert!((ledger.node(2).communication() - relay).abs() < 1e-6 * relay);
+        assert_eq!(ledger.node(4).communication(), 0.0);
+    }
+
+    #[test]
+    fn every_node_completes_the_protocol() {
+        for seed in 0..3 {
+            let mut nodes = initialize_network_from_seed(60, 6, 3, 8, seed);
+            assign_gateway_clusters(&mut nodes, 10, &mut rng_from_seed(seed));
+            let config = SimulationConfig::default();
+            let material = Ifkms.predistribute(&nodes, &config, &mut rng_from_seed(seed));
+            let mut protocol = Ifkms.protocol(&nodes, &config, 8, &material);
+            let report = simulate(&nodes, &config, protocol.as_mut(), &mut rng_from_seed(seed));
+            assert!(report.completion_time.is_some());
+            assert_eq!(report.unsettled_links, 0);
+            let expected = Ifkms.keying_round_ledger(&nodes, &config, 8, &material);
+            assert_eq!(
+                report.ledger.operations().encryptions,
+                expected.operations().encryptions
+            );
+        }
+    }
 }
@@ -1,168 +1,326 @@
-use std::env;
-
-use lazy_static::lazy_static;
-
-use crate::{NodeType, NodesVec};
-
-lazy_static! {
-    static ref EPSB: f32 = env::var("EPSB")
-        .unwrap_or(0.0001.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref EPRB: f32 = env::var("EPRB")
-        .unwrap_or(0.0001.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref HASH_ENERGY: f32 = env::var("HASH_ENERGY")
-        .unwrap_or(0.0001.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref ENCRYPTION_ENERGY: f32 = env::var("ENCRYPTION_ENERGY")
-        .unwrap_or(0.0001.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref DECRYPTION_ENERGY: f32 = env::var("DECRYPTION_ENERGY")
-        .unwrap_or(0.0001.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref SENT_MESSAGE_SIZE: u32 = env::var("SENT_MESSAGE_SIZE")
-        .unwrap_or(16.to_string())
-        .parse::<u32>()
-        .unwrap();
-    static ref RECEIVED_MESSAGE_SIZE: u32 = env::var("RECEIVED_MESSAGE_SIZE")
-        .unwrap_or(16.to_string())
-        .parse::<u32>()
-        .unwrap();
-    static ref NODE_ID_SIZE: f32 = env::var("NODE_ID_SIZE")
-        .unwrap_or(4.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref MESSAGE_TYPE_SIZE: f32 = env::var("MESSAGE_TYPE_SIZE")
-        .unwrap_or(1.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref NONCE_SIZE: f32 = env::var("NONCE_SIZE")
-        .unwrap_or(4.to_string())
-        .parse::<f32>()
-        .unwrap();
-}
+use crate::{
+    config::SimulationConfig,
+    ledger::EnergyLedger,
+    radio::broadcast_distance,
+    routing::RoutingTable,
+    simulation::{Engine, KeyTransport, Message, MessageKind, PairwiseProgress, Protocol},
+    Cluster, NodeType, NodesVec,
+};
+
+use super::{
+    pairwise_participants, record_cluster_key_transport, record_key_transport, KeyManagementScheme,
+    KeyMaterial, RekeyTrigger,
+};
+
+// Blom's scheme over a prime field of MATRIX_FIELD_BITS bits. The public matrix G is a
+// Vandermonde matrix, so the column of a node is the powers 1, s, ..., s^λ of its public seed.
+// Each node stores its row of (D·G)ᵀ, D being the secret symmetric (λ + 1) × (λ + 1) matrix, and
+// computes the key shared with a neighbor as the product of its row by the neighbor's column.
+pub struct Matrix;
+
+// Size of the field elements MULTIPLICATION_ENERGY is given for, the field of evkms
+pub const MULTIPLICATION_WORD_BITS: u32 = 64;
 
-pub fn number_of_multiplications(nodes: NodesVec) -> u32 {
-    let mut number_of_multiplications = 0;
-    for node in nodes.iter() {
-        number_of_multiplications += 2 * node.neighbors.len() as u32;
+impl Matrix {
+    // λ multiplications to expand the neighbor's column from its seed, λ + 1 for the product,
+    // each one costing as many word multiplications as a schoolbook product of its words
+    pub fn multiplications_per_key(config: &SimulationConfig) -> u32 {
+        let words = config.matrix_field_bits.div_ceil(MULTIPLICATION_WORD_BITS);
+        (2 * config.matrix_lambda + 1) * words * words
     }
-    number_of_multiplications
-}
 
-pub fn number_pairwise_hashes(nodes: NodesVec) -> u32 {
-    let mut number_of_hashes = 0;
-    for node in nodes.iter() {
-        number_of_hashes += 2 * node.neighbors.len() as u32;
+    pub fn field_element_bytes(config: &SimulationConfig) -> u32 {
+        config.matrix_field_bits.div_ceil(8)
     }
-    number_of_hashes
 }
 
-pub fn number_of_pairwise_encryptions(_nodes: NodesVec) -> u32 {
-    0
+// Every node broadcasts a hello carrying its seed and computes the key of a neighbor when it
+// handles the hello of that neighbor. Once a gateway is keyed with all its neighbors it sends the
+// group key to its members.
+pub struct MatrixProtocol {
+    progress: PairwiseProgress,
+    transport: KeyTransport,
+    hello_size: f32,
+    multiplications_per_key: u32,
 }
 
-pub fn pairwise_communication_energy(nodes: NodesVec, mac_size: u32) -> f32 {
-    let mut energy = 0.0;
-    for node in nodes.iter() {
-        if node.kind == NodeType::Gateway {
-            continue;
+impl MatrixProtocol {
+    fn settle(&mut self, node: usize, neighbor: usize, engine: &mut Engine) {
+        if self.progress.resolve(node, neighbor) && engine.nodes()[node].kind == NodeType::Gateway {
+            self.transport.start(node, engine);
         }
-        energy += (*MESSAGE_TYPE_SIZE + *NODE_ID_SIZE + *NONCE_SIZE + mac_size as f32)
-            * *EPSB;
-        energy += node.neighbors.len() as f32
-            * (*MESSAGE_TYPE_SIZE + *NODE_ID_SIZE + *NONCE_SIZE + mac_size as f32)
-            * *EPRB;
     }
-    energy
-}
 
-pub fn pairwise_communication_sent(nodes: NodesVec, mac_size: u32) -> f32 {
-    let mut sent = 0.0;
-    for node in nodes.iter() {
-        if node.kind == NodeType::Gateway {
-            continue;
+    fn try_complete(&self, node: usize, engine: &mut Engine) {
+        if self.progress.is_done(node) && self.transport.is_done(node) {
+            engine.complete(node);
         }
-        sent += *MESSAGE_TYPE_SIZE + *NODE_ID_SIZE + *NONCE_SIZE + mac_size as f32;
     }
-    sent
 }
 
-pub fn pairwise_communication_received(nodes: NodesVec, mac_size: u32) -> f32 {
-    let mut received = 0.0;
-    for node in nodes.iter() {
-        if node.kind == NodeType::Gateway {
-            continue;
+impl Protocol for MatrixProtocol {
+    fn start(&mut self, node: usize, engine: &mut Engine) {
+        engine.send(Message::broadcast(
+            MessageKind::Hello,
+            node,
+            self.hello_size,
+        ));
+        if self.progress.is_done(node) && engine.nodes()[node].kind == NodeType::Gateway {
+            self.transport.start(node, engine);
         }
-        received += node.neighbors.len() as f32
-            * (*MESSAGE_TYPE_SIZE + *NODE_ID_SIZE + *NONCE_SIZE + mac_size as f32);
+        self.try_complete(node, engine);
     }
-    received
-}
 
-pub fn groupwise_communication_energy(nodes: NodesVec) -> f32 {
-    let mut energy = 0.0;
-    let constrained_nodes: NodesVec = NodesVec(
-        nodes
-            .iter()
-            .filter(|node| node.kind == NodeType::Constrained)
-            .map(|node| node.clone())
-            .collect(),
-    );
-    let sent_energy = constrained_nodes.len() as f32 * *SENT_MESSAGE_SIZE as f32 * *EPSB;
-    let received_energy = constrained_nodes.len() as f32 * *RECEIVED_MESSAGE_SIZE as f32 * *EPRB;
-    energy += sent_energy + received_energy;
-    energy
-}
+    fn receive(&mut self, node: usize, message: &Message, engine: &mut Engine) {
+        let config = engine.config();
+        match message.kind {
+            MessageKind::Hello => {
+                engine
+                    .ledger()
+                    .multiplications(node, self.multiplications_per_key, config);
+                engine.ledger().hashes(node, 2, config);
+                self.settle(node, message.from, engine);
+            }
+            _ => self.transport.receive(node, message, engine),
+        }
+        self.try_complete(node, engine);
+    }
 
-pub fn groupwise_encryptions_energy(nodes: NodesVec) -> f32 {
-    let mut energy = 0.0;
-    let constrained_nodes: NodesVec = NodesVec(
-        nodes
-            .iter()
-            .filter(|node| node.kind == NodeType::Constrained)
-            .map(|node| node.clone())
-            .collect(),
-    );
-    energy += constrained_nodes.len() as f32 * *ENCRYPTION_ENERGY;
-    energy += constrained_nodes.len() as f32 * *DECRYPTION_ENERGY;
-    energy
+    fn unsettled_links(&self) -> usize {
+        self.progress.unsettled_links()
+    }
 }
 
-pub fn groupwise_hashes_energy(_nodes: NodesVec) -> f32 {
-    0.0
-}
+impl KeyManagementScheme for Matrix {
+    fn name(&self) -> &'static str {
+        "matrix"
+    }
+
+    fn number_of_multiplications(&self, nodes: &NodesVec, config: &SimulationConfig) -> u32 {
+        let mut number_of_multiplications = 0;
+        for node in nodes.iter() {
+            number_of_multiplications +=
+                node.neighbors.len() as u32 * Matrix::multiplications_per_key(config);
+        }
+        number_of_multiplications
+    }
+
+    fn number_pairwise_hashes(
+        &self,
+        nodes: &NodesVec,
+        _config: &SimulationConfig,
+        _material: &KeyMaterial,
+    ) -> u32 {
+        let mut number_of_hashes = 0;
+        for node in nodes.iter() {
+            number_of_hashes += 2 * node.neighbors.len() as u32;
+        }
+        number_of_hashes
+    }
+
+    fn number_of_pairwise_encryptions(
+        &self,
+        _nodes: &NodesVec,
+        _config: &SimulationConfig,
+        _material: &KeyMaterial,
+    ) -> u32 {
+        0
+    }
+
+    fn pairwise_communication_energy(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+    ) -> f32 {
+        let mut energy = 0.0;
+        for node in pairwise_participants(nodes) {
+            if node.kind == NodeType::Gateway {
+                continue;
+            }
+            energy += config.transmit_energy(
+                config.hello_message_size(mac_size),
+                broadcast_distance(nodes, node.id, config),
+            );
+            energy += config
+                .receive_energy(node.neighbors.len() as f32 * config.hello_message_size(mac_size));
+        }
+        energy
+    }
+
+    fn pairwise_communication_sent(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+    ) -> f32 {
+        let mut sent = 0.0;
+        for node in pairwise_participants(nodes) {
+            if node.kind == NodeType::Gateway {
+                continue;
+            }
+            sent += config.hello_message_size(mac_size);
+        }
+        sent
+    }
 
-pub fn groupwise_computation_energy(nodes: NodesVec) -> f32 {
-    let mut energy = 0.0;
-    let constrained_nodes: NodesVec = NodesVec(
-        nodes
-            .iter()
-            .filter(|node| node.kind == NodeType::Constrained)
-            .map(|node| node.clone())
-            .collect(),
-    );
-    // Calculate the hashes energy
-    let total_hash_energy: f32 = groupwise_hashes_energy(constrained_nodes.clone());
-    // Calculate the encryptions energy
-    let total_encryption_energy: f32 = groupwise_encryptions_energy(constrained_nodes.clone());
-    // Sum all the energies
-    energy += total_hash_energy + total_encryption_energy;
-    energy
+    fn pairwise_communication_received(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+    ) -> f32 {
+        let mut received = 0.0;
+        for node in pairwise_participants(nodes) {
+            if node.kind == NodeType::Gateway {
+                continue;
+            }
+            received += node.neighbors.len() as f32 * config.hello_message_size(mac_size);
+        }
+        received
+    }
+
+    fn groupwise_communication_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32 {
+        let mut energy = 0.0;
+        let mut ledger = EnergyLedger::for_network(nodes);
+        record_key_transport(nodes, config, &mut ledger);
+        energy += ledger.total().communication();
+        energy
+    }
+
+    // The gateway encrypts the group key for every reachable member and decrypts its
+    // acknowledgement, the member decrypts the key and encrypts the acknowledgement
+    fn groupwise_encryptions_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32 {
+        let mut energy = 0.0;
+        let mut ledger = EnergyLedger::for_network(nodes);
+        record_key_transport(nodes, config, &mut ledger);
+        let total = ledger.total();
+        energy += total.encrypt + total.decrypt;
+        energy
+    }
+
+    fn groupwise_hashes_energy(&self, _nodes: &NodesVec, _config: &SimulationConfig) -> f32 {
+        0.0
+    }
+
+    fn record_pairwise(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+        ledger: &mut EnergyLedger,
+    ) {
+        for node in pairwise_participants(nodes) {
+            let neighbors_count = node.neighbors.len() as u32;
+            ledger.multiplications(
+                node.id,
+                neighbors_count * Matrix::multiplications_per_key(config),
+                config,
+            );
+            ledger.hashes(node.id, 2 * neighbors_count, config);
+            if node.kind == NodeType::Gateway {
+                continue;
+            }
+            ledger.transmit(
+                node.id,
+                config.hello_message_size(mac_size),
+                broadcast_distance(nodes, node.id, config),
+                config,
+            );
+            ledger.receive(
+                node.id,
+                neighbors_count as f32 * config.hello_message_size(mac_size),
+                config,
+            );
+        }
+    }
+
+    fn record_groupwise(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        ledger: &mut EnergyLedger,
+    ) {
+        record_key_transport(nodes, config, ledger);
+    }
+
+    // A new key is sent to every member whatever the trigger
+    fn record_group_rekey(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        cluster: &Cluster,
+        _trigger: RekeyTrigger,
+        ledger: &mut EnergyLedger,
+    ) {
+        let routes = RoutingTable::to_gateways(nodes);
+        record_cluster_key_transport(nodes, config, &routes, cluster, ledger);
+    }
+
+    fn protocol(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        _material: &KeyMaterial,
+    ) -> Box<dyn Protocol> {
+        Box::new(MatrixProtocol {
+            progress: PairwiseProgress::new(nodes),
+            transport: KeyTransport::new(
+                nodes,
+                config.received_message_size as f32,
+                config.sent_message_size as f32,
+            ),
+            hello_size: config.hello_message_size(mac_size),
+            multiplications_per_key: Matrix::multiplications_per_key(config),
+        })
+    }
+
+    // Capturing λ + 1 rows of (D·G)ᵀ reveals D and every key with it
+    fn collusion_threshold(&self, config: &SimulationConfig) -> Option<u32> {
+        Some(config.matrix_lambda)
+    }
+
+    // The private row of λ + 1 field elements, the column is regenerated from the public seed
+    fn key_storage_bytes(&self, config: &SimulationConfig) -> Option<u32> {
+        Some((config.matrix_lambda + 1) * Matrix::field_element_bytes(config))
+    }
 }
 
-pub fn groupwise_total_energy(nodes: NodesVec) -> f32 {
-    let mut energy = 0.0;
-    // Calculate the pairwise communication energy
-    let groupewise_communication_energy: f32 = groupwise_communication_energy(nodes.clone());
-    // Calculate the groupwise computation energy
-    let groupwise_computation_energy: f32 = groupwise_computation_energy(nodes.clone());
-    // Sum all the energies
-    energy += groupewise_communication_energy + groupwise_computation_energy;
-    energy
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Node;
+
+    #[test]
+    fn multiplications_grow_with_the_words_of_the_field() {
+        let mut config = SimulationConfig::default();
+        config.matrix_lambda = 10;
+        // (2λ + 1)·words² for fields of one, two and three 64-bit words
+        for (bits, words) in [(64, 1), (128, 2), (160, 3), (192, 3)] {
+            config.matrix_field_bits = bits;
+            assert_eq!(Matrix::multiplications_per_key(&config), 21 * words * words);
+        }
+        config.matrix_field_bits = 32;
+        config.matrix_lambda = 0;
+        assert_eq!(Matrix::multiplications_per_key(&config), 1);
+    }
+
+    #[test]
+    fn both_ends_of_a_link_hash_its_key() {
+        let config = SimulationConfig::default();
+        let mut nodes = NodesVec::new();
+        nodes.push(Node::new(0, NodeType::Constrained, vec![1, 2], 2));
+        nodes.push(Node::new(1, NodeType::Constrained, vec![0], 2));
+        nodes.push(Node::new(2, NodeType::Constrained, vec![0], 2));
+        let material = KeyMaterial::default();
+        assert_eq!(Matrix.number_pairwise_hashes(&nodes, &config, &material), 8);
+        let mut ledger = EnergyLedger::for_network(&nodes);
+        Matrix.record_pairwise(&nodes, &config, 8, &material, &mut ledger);
+        assert_eq!(ledger.total().hash, 8.0 * config.hash_energy);
+    }
 }
@@ -1,4 +1,487 @@
+use rand::RngCore;
+
+use crate::{
+    config::SimulationConfig,
+    ledger::EnergyLedger,
+    radio::link_distance,
+    resilience::{threshold_exposure, Exposure},
+    revocation::Revocation,
+    routing::RoutingTable,
+    simulation::Protocol,
+    topology::Departure,
+    Cluster, Node, NodeState, NodesVec,
+};
+
 pub mod evkms;
 pub mod ifkms;
 pub mod matrix;
-pub mod pool_hash;
\ No newline at end of file
+pub mod pool_hash;
+
+// Outcome of the shared-key discovery between every pair of neighbors
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct KeyDiscovery {
+    pub links: usize,
+    // Neighbors holding a common key
+    pub direct_links: usize,
+    // Neighbors keyed through a path of secured links
+    pub path_key_links: usize,
+    // Neighbors left without a key
+    pub unsecured_links: usize,
+    // Bytes of the key identifier broadcasts
+    pub discovery_bytes_sent: f32,
+    pub discovery_bytes_received: f32,
+    // Hop by hop transport of the path keys
+    pub path_key_messages: usize,
+    pub path_key_bytes_sent: f32,
+    pub path_key_bytes_received: f32,
+    pub path_key_energy: f32,
+}
+
+impl KeyDiscovery {
+    fn fraction(&self, count: usize) -> f64 {
+        if self.links == 0 {
+            0.0
+        } else {
+            count as f64 / self.links as f64
+        }
+    }
+
+    pub fn direct_fraction(&self) -> f64 {
+        self.fraction(self.direct_links)
+    }
+
+    pub fn path_key_fraction(&self) -> f64 {
+        self.fraction(self.path_key_links)
+    }
+
+    pub fn unsecured_fraction(&self) -> f64 {
+        self.fraction(self.unsecured_links)
+    }
+}
+
+// Random key material loaded on the nodes before deployment. It is drawn once per topology so
+// every simulation of a scheme reads the same keys, and is empty for schemes deriving their keys
+// from node ids.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyMaterial {
+    // Pool key identifiers held by each node, indexed by node id
+    pub key_rings: Vec<Vec<u32>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RekeyTrigger {
+    // Scheduled refresh, every node holding the old key may hold the new one
+    Periodic,
+    // A node must be kept out of the key: one that left or was compromised must not learn the
+    // new key, one that joined must not learn the old one
+    Exclusion,
+}
+
+pub trait KeyManagementScheme: Send + Sync {
+    // Short lowercase name used to label the results of the scheme
+    fn name(&self) -> &'static str;
+
+    // Pairwise key establishment
+    fn number_of_multiplications(&self, nodes: &NodesVec, config: &SimulationConfig) -> u32;
+
+    fn number_pairwise_hashes(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        material: &KeyMaterial,
+    ) -> u32;
+
+    fn number_of_pairwise_encryptions(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        material: &KeyMaterial,
+    ) -> u32;
+
+    fn pairwise_multiplications_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32 {
+        self.number_of_multiplications(nodes, config) as f32 * config.multiplication_energy
+    }
+
+    fn pairwise_communication_energy(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        material: &KeyMaterial,
+    ) -> f32;
+
+    fn pairwise_communication_sent(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        material: &KeyMaterial,
+    ) -> f32;
+
+    fn pairwise_communication_received(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        material: &KeyMaterial,
+    ) -> f32;
+
+    // Group key establishment
+    fn groupwise_communication_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32;
+
+    fn groupwise_encryptions_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32;
+
+    fn groupwise_hashes_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32;
+
+    fn groupwise_computation_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32 {
+        let mut energy = 0.0;
+        // Calculate the hashes energy
+        let total_hash_energy: f32 = self.groupwise_hashes_energy(nodes, config);
+        // Calculate the encryptions energy
+        let total_encryption_energy: f32 = self.groupwise_encryptions_energy(nodes, config);
+        // Sum all the energies
+        energy += total_hash_energy + total_encryption_energy;
+        energy
+    }
+
+    fn groupwise_total_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32 {
+        let mut energy = 0.0;
+        // Calculate the groupwise communication energy
+        let groupwise_communication_energy: f32 =
+            self.groupwise_communication_energy(nodes, config);
+        // Calculate the groupwise computation energy
+        let groupwise_computation_energy: f32 = self.groupwise_computation_energy(nodes, config);
+        // Sum all the energies
+        energy += groupwise_communication_energy + groupwise_computation_energy;
+        energy
+    }
+
+    // Charge every node with what it spends during the pairwise key establishment
+    fn record_pairwise(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        material: &KeyMaterial,
+        ledger: &mut EnergyLedger,
+    );
+
+    // Charge every node with what it spends during the group key establishment
+    fn record_groupwise(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        ledger: &mut EnergyLedger,
+    );
+
+    // Charge every node with what it spends refreshing all its pairwise keys, a new
+    // establishment unless the scheme can do better
+    fn record_pairwise_rekey(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        material: &KeyMaterial,
+        _trigger: RekeyTrigger,
+        ledger: &mut EnergyLedger,
+    ) {
+        self.record_pairwise(nodes, config, mac_size, material, ledger);
+    }
+
+    // Charge the gateway and the members of the cluster with a refresh of its group key
+    fn record_group_rekey(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        cluster: &Cluster,
+        trigger: RekeyTrigger,
+        ledger: &mut EnergyLedger,
+    );
+
+    // Charge what admitting the node, already wired into the network, costs: keys with each of
+    // its neighbors, and a new key for its cluster so it cannot read earlier group traffic
+    // (backward secrecy)
+    fn record_join(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        material: &KeyMaterial,
+        node: usize,
+        ledger: &mut EnergyLedger,
+    ) {
+        self.record_pairwise(&nodes.links_of(node), config, mac_size, material, ledger);
+        if let Some(cluster) = nodes.cluster_of(node) {
+            self.record_group_rekey(nodes, config, &cluster, RekeyTrigger::Exclusion, ledger);
+        }
+    }
+
+    // Charge what a departure costs once the node is out of the network. Its neighbors erase the
+    // keys they shared with it for free, its former cluster needs a new key so it cannot read
+    // later group traffic (forward secrecy).
+    fn record_leave(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        departure: &Departure,
+        ledger: &mut EnergyLedger,
+    ) {
+        if let Some(cluster) = departure
+            .gateway
+            .and_then(|gateway| nodes.cluster_of(gateway))
+        {
+            self.record_group_rekey(nodes, config, &cluster, RekeyTrigger::Exclusion, ledger);
+        }
+    }
+
+    // Revocation flooded by a gateway: message type, revoked id and the tag of the gateway
+    fn revocation_message_size(&self, config: &SimulationConfig, mac_size: u32) -> f32 {
+        (config.message_type_size + config.node_id_size + mac_size) as f32
+    }
+
+    // Charge what the network spends to stop trusting a revoked node once the revocation spread,
+    // and return the active nodes the revoked node can still talk to. The cluster of the node
+    // gets a new group key. Pairwise keys derive from node ids, so the neighbors only erase
+    // their key with it, unless more nodes were revoked than the collusion threshold and every
+    // pairwise key is established again. Nodes the revocation missed still accept the revoked
+    // id.
+    fn record_revocation(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        revocation: &Revocation,
+        material: &KeyMaterial,
+        ledger: &mut EnergyLedger,
+    ) -> Vec<usize> {
+        self.record_leave(nodes, config, &revocation.departure, ledger);
+        let revoked_count = nodes
+            .iter()
+            .filter(|node| node.state == NodeState::Revoked)
+            .count();
+        if self
+            .collusion_threshold(config)
+            .is_some_and(|threshold| revoked_count > threshold as usize)
+        {
+            self.record_pairwise_rekey(
+                nodes,
+                config,
+                mac_size,
+                material,
+                RekeyTrigger::Exclusion,
+                ledger,
+            );
+        }
+        nodes
+            .iter()
+            .filter(|node| node.is_active() && !revocation.reached[node.id])
+            .map(|node| node.id)
+            .collect()
+    }
+
+    // Draw the key material loaded on the nodes before deployment. Key material is drawn from
+    // `rng`.
+    fn predistribute(
+        &self,
+        _nodes: &NodesVec,
+        _config: &SimulationConfig,
+        _rng: &mut dyn RngCore,
+    ) -> KeyMaterial {
+        KeyMaterial::default()
+    }
+
+    // Simulate the shared-key discovery, only for schemes where neighbors may not share a key
+    fn key_discovery(
+        &self,
+        _nodes: &NodesVec,
+        _config: &SimulationConfig,
+        _mac_size: u32,
+        _material: &KeyMaterial,
+    ) -> Option<KeyDiscovery> {
+        None
+    }
+
+    // Key material predistributed to each node, `None` when the scheme does not model it
+    fn key_storage_bytes(&self, _config: &SimulationConfig) -> Option<u32> {
+        None
+    }
+
+    // Number of captured nodes past which the adversary can compute every pairwise key, `None`
+    // when capturing nodes only exposes their own keys
+    fn collusion_threshold(&self, _config: &SimulationConfig) -> Option<u32> {
+        None
+    }
+
+    // Links between uncaptured nodes whose key the adversary computes from the key material of
+    // the captured nodes
+    fn link_exposure(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        captured: &[bool],
+        _material: &KeyMaterial,
+    ) -> Exposure {
+        threshold_exposure(nodes, captured, self.collusion_threshold(config))
+    }
+
+    // Message-level state machine of one pairwise then groupwise keying round, run by the
+    // discrete-event engine
+    fn protocol(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        material: &KeyMaterial,
+    ) -> Box<dyn Protocol>;
+
+    // Per-node energy of one pairwise then groupwise keying round
+    fn keying_round_ledger(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        material: &KeyMaterial,
+    ) -> EnergyLedger {
+        let mut ledger = EnergyLedger::for_network(nodes);
+        self.record_pairwise(nodes, config, mac_size, material, &mut ledger);
+        self.record_groupwise(nodes, config, &mut ledger);
+        ledger
+    }
+}
+
+// Nodes that take part in a pairwise establishment. Departed and revoked nodes are out of the
+// network, and nodes without a neighbor to key with stay silent.
+pub(crate) fn pairwise_participants(nodes: &NodesVec) -> impl Iterator<Item = &Node> {
+    nodes
+        .iter()
+        .filter(|node| node.is_active() && !node.neighbors.is_empty())
+}
+
+// Group key transport shared by matrix and pool_hash: the gateway encrypts the key of its cluster
+// for each member and sends it along the shortest path, the member decrypts it and sends back an
+// encrypted acknowledgement the gateway decrypts, every relay forwarding both messages. Members
+// out of reach of their gateway never get the key and spend nothing.
+fn record_key_transport(nodes: &NodesVec, config: &SimulationConfig, ledger: &mut EnergyLedger) {
+    let routes = RoutingTable::to_gateways(nodes);
+    for cluster in nodes.clusters() {
+        record_cluster_key_transport(nodes, config, &routes, &cluster, ledger);
+    }
+}
+
+// Key transport of a single cluster, also used to refresh its group key
+fn record_cluster_key_transport(
+    nodes: &NodesVec,
+    config: &SimulationConfig,
+    routes: &RoutingTable,
+    cluster: &Cluster,
+    ledger: &mut EnergyLedger,
+) {
+    let key_size = config.received_message_size as f32;
+    let acknowledgement_size = config.sent_message_size as f32;
+    let gateway = cluster.gateway;
+    for &member in cluster.members.iter() {
+        let Some(relays) = routes.relays(member) else {
+            continue;
+        };
+        // Gateway, relays then member
+        let mut path: Vec<usize> = Vec::with_capacity(relays.len() + 2);
+        path.push(gateway);
+        path.extend_from_slice(relays);
+        path.push(member);
+        let last = path.len() - 1;
+        ledger.encryptions(gateway, 1, config);
+        ledger.transmit(
+            gateway,
+            key_size,
+            link_distance(nodes, gateway, path[1], config),
+            config,
+        );
+        ledger.receive(gateway, acknowledgement_size, config);
+        ledger.decryptions(gateway, 1, config);
+        ledger.receive(member, key_size, config);
+        ledger.decryptions(member, 1, config);
+        ledger.encryptions(member, 1, config);
+        ledger.transmit(
+            member,
+            acknowledgement_size,
+            link_distance(nodes, member, path[last - 1], config),
+            config,
+        );
+        for i in 1..last {
+            let relay = path[i];
+            let key_distance = link_distance(nodes, relay, path[i + 1], config);
+            let acknowledgement_distance = link_distance(nodes, relay, path[i - 1], config);
+            ledger.forward(relay, key_size, key_distance, config);
+            ledger.forward(
+                relay,
+                acknowledgement_size,
+                acknowledgement_distance,
+                config,
+            );
+        }
+    }
+}
+
+// Every scheme implemented by the crate, in the order they are reported
+pub fn all_schemes() -> Vec<Box<dyn KeyManagementScheme>> {
+    vec![
+        Box::new(evkms::Evkms),
+        Box::new(matrix::Matrix),
+        Box::new(ifkms::Ifkms),
+        Box::new(pool_hash::PoolHash),
+    ]
+}
+
+pub fn scheme_by_name(name: &str) -> Option<Box<dyn KeyManagementScheme>> {
+    all_schemes()
+        .into_iter()
+        .find(|scheme| scheme.name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rng::rng_from_seed,
+        topology::{assign_gateway_clusters, initialize_network_from_seed},
+        NodeType,
+    };
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-4 * a.abs().max(b.abs()).max(1e-9)
+    }
+
+    // A departed node has no link left, it neither says hello nor hears one
+    #[test]
+    fn closed_forms_leave_departed_nodes_out() {
+        let config = SimulationConfig::default();
+        let mut nodes = initialize_network_from_seed(60, 6, 3, 8, 3);
+        assign_gateway_clusters(&mut nodes, 10, &mut rng_from_seed(3));
+        let departed = nodes
+            .iter()
+            .find(|node| node.kind == NodeType::Constrained)
+            .expect("the network has constrained nodes")
+            .id;
+        nodes.remove_node(departed);
+        for scheme in all_schemes() {
+            let material = scheme.predistribute(&nodes, &config, &mut rng_from_seed(3));
+            let mut ledger = EnergyLedger::for_network(&nodes);
+            scheme.record_pairwise(&nodes, &config, 8, &material, &mut ledger);
+            assert_eq!(ledger.node(departed).total(), 0.0, "{}", scheme.name());
+            let energy = scheme.pairwise_communication_energy(&nodes, &config, 8, &material);
+            let sent = scheme.pairwise_communication_sent(&nodes, &config, 8, &material);
+            let received = scheme.pairwise_communication_received(&nodes, &config, 8, &material);
+            assert!(
+                close(energy, ledger.total().communication()),
+                "{}",
+                scheme.name()
+            );
+            assert!(close(sent, ledger.bytes_sent()), "{}", scheme.name());
+            assert!(
+                close(received, ledger.bytes_received()),
+                "{}",
+                scheme.name()
+            );
+        }
+    }
+}
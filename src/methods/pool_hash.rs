@@ -1,105 +1,829 @@
-use std::env;
-
-use lazy_static::lazy_static;
-
-use crate::{NodeType, NodesVec};
-
-lazy_static! {
-    static ref EPSB: f32 = env::var("EPSB")
-        .unwrap_or(0.0001.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref EPRB: f32 = env::var("EPRB")
-        .unwrap_or(0.0001.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref HASH_ENERGY: f32 = env::var("HASH_ENERGY")
-        .unwrap_or(0.0001.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref ENCRYPTION_ENERGY: f32 = env::var("ENCRYPTION_ENERGY")
-        .unwrap_or(0.0001.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref KEY_RING_SIZE: u32 = env::var("KEY_RING_SIZE")
-        .unwrap_or(58.to_string())
-        .parse::<u32>()
-        .unwrap();
-    static ref KEY_POOL_SIZE: u32 = env::var("POOL_SIZE")
-        .unwrap_or(1000.to_string())
-        .parse::<u32>()
-        .unwrap();
-    static ref NODE_ID_SIZE: f32 = env::var("NODE_ID_SIZE")
-        .unwrap_or(4.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref AES_BLOCK_SIZE: u32 = env::var("AES_BLOCK_SIZE")
-        .unwrap_or(16.to_string())
-        .parse::<u32>()
-        .unwrap();
-    static ref MESSAGE_TYPE_SIZE: f32 = env::var("MESSAGE_TYPE_SIZE")
-        .unwrap_or(1.to_string())
-        .parse::<f32>()
-        .unwrap();
-    static ref NONCE_SIZE: f32 = env::var("NONCE_SIZE")
-        .unwrap_or(4.to_string())
-        .parse::<f32>()
-        .unwrap();
-}
-
-pub fn pairwise_communication_energy(nodes: NodesVec, aes_block_size: u32) -> f32 {
-    let mut energy = 0.0;
-    let neighbors_ids_size = *NODE_ID_SIZE as f32 * *KEY_RING_SIZE as f32;
-    let message_size_before_encryption: f32 =
-        *MESSAGE_TYPE_SIZE + *NODE_ID_SIZE + *NONCE_SIZE + neighbors_ids_size;
-    let message = if message_size_before_encryption % aes_block_size as f32 == 0.0 {
-        message_size_before_encryption
+use std::collections::{HashMap, VecDeque};
+
+use rand::{seq::index, RngCore};
+
+use crate::{
+    config::SimulationConfig,
+    ledger::EnergyLedger,
+    radio::{broadcast_distance, link_distance},
+    resilience::Exposure,
+    revocation::Revocation,
+    routing::RoutingTable,
+    simulation::{Engine, KeyTransport, Message, MessageKind, PairwiseProgress, Protocol},
+    Cluster, NodeType, NodesVec,
+};
+
+use super::{
+    pairwise_participants, record_cluster_key_transport, record_key_transport, KeyDiscovery,
+    KeyManagementScheme, KeyMaterial, RekeyTrigger,
+};
+
+// Size of the key identifiers broadcast, padded to a whole number of AES blocks
+fn encrypted_message_size(config: &SimulationConfig) -> f32 {
+    let neighbors_ids_size = config.node_id_size * config.key_ring_size;
+    let message_size_before_encryption =
+        config.message_type_size + config.node_id_size + config.nonce_size + neighbors_ids_size;
+    (message_size_before_encryption.div_ceil(config.aes_block_size) * config.aes_block_size) as f32
+}
+
+// Bytes of the key identifier broadcasts, each received by every neighbor
+fn identifiers_sent(nodes: &NodesVec, config: &SimulationConfig) -> f32 {
+    pairwise_participants(nodes).count() as f32 * encrypted_message_size(config)
+}
+
+fn identifiers_received(nodes: &NodesVec, config: &SimulationConfig) -> f32 {
+    pairwise_participants(nodes)
+        .map(|node| node.neighbors.len() as f32 * encrypted_message_size(config))
+        .sum()
+}
+
+// Size of a path key message: both node ids and a key as long as one AES block, encrypted
+fn path_key_message_size(config: &SimulationConfig) -> f32 {
+    let message_size_before_encryption = config.message_type_size
+        + 2 * config.node_id_size
+        + config.nonce_size
+        + config.aes_block_size;
+    (message_size_before_encryption.div_ceil(config.aes_block_size) * config.aes_block_size) as f32
+}
+
+// Draw for every node KEY_RING_SIZE distinct key identifiers out of the KEY_POOL_SIZE keys of the
+// pool, each ring sorted
+pub fn assign_key_rings(
+    nodes: &NodesVec,
+    config: &SimulationConfig,
+    rng: &mut dyn RngCore,
+) -> Vec<Vec<u32>> {
+    let mut rings: Vec<Vec<u32>> = Vec::with_capacity(nodes.len());
+    for _ in nodes.iter() {
+        let mut ring: Vec<u32> = index::sample(
+            rng,
+            config.key_pool_size as usize,
+            config.key_ring_size as usize,
+        )
+        .into_iter()
+        .map(|key| key as u32)
+        .collect();
+        ring.sort_unstable();
+        rings.push(ring);
+    }
+    rings
+}
+
+// Smallest key identifier held by both sorted rings
+pub fn shared_key(a: &[u32], b: &[u32]) -> Option<u32> {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            return Some(a[i]);
+        }
+        if a[i] < b[j] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    None
+}
+
+// Secured links between `from` and `to` on the shortest path of at most `max_hops` hops,
+// ordered from `from`
+fn secure_path(
+    secured: &[Vec<usize>],
+    from: usize,
+    to: usize,
+    max_hops: usize,
+    parent: &mut [usize],
+) -> Option<Vec<usize>> {
+    let mut visited: Vec<usize> = vec![from];
+    let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+    parent[from] = from;
+    queue.push_back((from, 0));
+    let mut found = false;
+    while let Some((current, hops)) = queue.pop_front() {
+        if hops == max_hops {
+            continue;
+        }
+        for &neighbor in secured[current].iter() {
+            if parent[neighbor] != usize::MAX {
+                continue;
+            }
+            parent[neighbor] = current;
+            visited.push(neighbor);
+            if neighbor == to {
+                found = true;
+                break;
+            }
+            queue.push_back((neighbor, hops + 1));
+        }
+        if found {
+            break;
+        }
+    }
+    let path = if found {
+        let mut path: Vec<usize> = vec![to];
+        let mut current = to;
+        while current != from {
+            current = parent[current];
+            path.push(current);
+        }
+        path.reverse();
+        Some(path)
     } else {
-        (message_size_before_encryption as u32 / aes_block_size + 1) as f32 * aes_block_size as f32
+        None
     };
-    let sent_energy = message as f32 * *EPSB;
+    for node in visited {
+        parent[node] = usize::MAX;
+    }
+    path
+}
+
+// How a pair of neighbors ends up keyed
+enum LinkKey {
+    // Pool key held by both rings
+    Direct(u32),
+    // Path of directly keyed hops the path key travels through, from one end to the other
+    PathKey(Vec<usize>),
+    Unsecured,
+}
+
+struct KeyedLink {
+    from: usize,
+    to: usize,
+    key: LinkKey,
+}
+
+// Neighbors sharing a pool key use it directly. The others get a path key generated by the
+// lower id end and sent encrypted hop by hop over directly keyed links, and stay unsecured when
+// no such path of at most PATH_KEY_MAX_HOPS hops exists.
+fn key_links(nodes: &NodesVec, config: &SimulationConfig, rings: &[Vec<u32>]) -> Vec<KeyedLink> {
+    let mut links: Vec<KeyedLink> = Vec::new();
+    let mut secured: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
     for node in nodes.iter() {
-        if node.kind == NodeType::Gateway {
-            continue;
+        for &neighbor in node.neighbors.iter() {
+            if neighbor < node.id {
+                continue;
+            }
+            let key = match shared_key(&rings[node.id], &rings[neighbor]) {
+                Some(key) => {
+                    secured[node.id].push(neighbor);
+                    secured[neighbor].push(node.id);
+                    LinkKey::Direct(key)
+                }
+                None => LinkKey::Unsecured,
+            };
+            links.push(KeyedLink {
+                from: node.id,
+                to: neighbor,
+                key,
+            });
+        }
+    }
+
+    let max_hops = config.path_key_max_hops as usize;
+    let mut parent: Vec<usize> = vec![usize::MAX; nodes.len()];
+    for link in links.iter_mut() {
+        if let LinkKey::Unsecured = link.key {
+            if let Some(path) = secure_path(&secured, link.from, link.to, max_hops, &mut parent) {
+                link.key = LinkKey::PathKey(path);
+            }
         }
-        // For each neighbor of this node, we receive NODE_ID_SIZE * KEY_RING_SIZE bytes
-        let received_energy = node.neighbors.len() as f32 * message * *EPRB;
-        energy += sent_energy + received_energy;
     }
-    energy
+    links
 }
 
-pub fn pairwise_communication_sent(nodes: NodesVec, aes_block_size: u32) -> f32 {
-    let neighbors_ids_size = *NODE_ID_SIZE as f32 * *KEY_RING_SIZE as f32;
-    let message_size_before_encryption: f32 =
-        *MESSAGE_TYPE_SIZE + *NODE_ID_SIZE + *NONCE_SIZE + neighbors_ids_size;
-    let message = if message_size_before_encryption % aes_block_size as f32 == 0.0 {
-        message_size_before_encryption
-    } else {
-        (message_size_before_encryption as u32 / aes_block_size + 1) as f32 * aes_block_size as f32
-    };
-    // Get the number of nodes that are not gateways
-    let constrained_nodes_count = nodes.iter().filter(|n| n.kind != NodeType::Gateway).count();
-    message * constrained_nodes_count as f32
-}
-
-pub fn pairwise_communication_received(nodes: NodesVec, aes_block_size: u32) -> f32 {
-    let mut received = 0.0;
-    let neighbors_ids_size = *NODE_ID_SIZE as f32 * *KEY_RING_SIZE as f32;
-    let message_size_before_encryption: f32 =
-        *MESSAGE_TYPE_SIZE + *NODE_ID_SIZE + *NONCE_SIZE + neighbors_ids_size;
-    let message = if message_size_before_encryption % aes_block_size as f32 == 0.0 {
-        message_size_before_encryption
-    } else {
-        (message_size_before_encryption as u32 / aes_block_size + 1) as f32 * aes_block_size as f32
+// The path key is encrypted by each hop for the next one, sent over the link and decrypted
+fn record_path_key(
+    nodes: &NodesVec,
+    config: &SimulationConfig,
+    path: &[usize],
+    ledger: &mut EnergyLedger,
+) {
+    let message = path_key_message_size(config);
+    for hop in path.windows(2) {
+        ledger.encryptions(hop[0], 1, config);
+        ledger.transmit(
+            hop[0],
+            message,
+            link_distance(nodes, hop[0], hop[1], config),
+            config,
+        );
+        ledger.receive(hop[1], message, config);
+        ledger.decryptions(hop[1], 1, config);
+    }
+}
+
+// Every node broadcasts its encrypted key identifiers. A node handling the identifiers of a
+// neighbor hashes the shared pool key, or when there is none and the link has a path key, the end
+// with the lower id sends the path key hop by hop over secured links. Once a gateway settled all
+// its links it sends the group key to its members.
+pub struct PoolHashProtocol {
+    progress: PairwiseProgress,
+    transport: KeyTransport,
+    // Keyed links indexed by their end points, the lower id first
+    links: HashMap<(usize, usize), LinkKey>,
+    identifiers_size: f32,
+    path_key_size: f32,
+}
+
+impl PoolHashProtocol {
+    fn link(&self, a: usize, b: usize) -> Option<&LinkKey> {
+        self.links.get(&(a.min(b), a.max(b)))
+    }
+
+    fn settle(&mut self, node: usize, neighbor: usize, engine: &mut Engine) {
+        if self.progress.resolve(node, neighbor) && engine.nodes()[node].kind == NodeType::Gateway {
+            self.transport.start(node, engine);
+        }
+    }
+
+    fn try_complete(&self, node: usize, engine: &mut Engine) {
+        if self.progress.is_done(node) && self.transport.is_done(node) {
+            engine.complete(node);
+        }
+    }
+
+    fn receive_identifiers(&mut self, node: usize, neighbor: usize, engine: &mut Engine) {
+        let config = engine.config();
+        engine.ledger().decryptions(node, 1, config);
+        match self.link(node, neighbor) {
+            Some(LinkKey::Direct(_)) => {
+                engine.ledger().hashes(node, 1, config);
+                self.settle(node, neighbor, engine);
+            }
+            Some(LinkKey::PathKey(path)) => {
+                // The other end settles the link when the path key reaches it
+                if path[0] == node {
+                    let message = Message::routed(
+                        MessageKind::PathKey,
+                        node,
+                        path[1],
+                        path[path.len() - 1],
+                        self.path_key_size,
+                    );
+                    engine.ledger().encryptions(node, 1, config);
+                    engine.send(message);
+                    self.settle(node, neighbor, engine);
+                }
+            }
+            Some(LinkKey::Unsecured) | None => self.settle(node, neighbor, engine),
+        }
+    }
+
+    fn receive_path_key(&mut self, node: usize, message: &Message, engine: &mut Engine) {
+        let config = engine.config();
+        let Some(target) = message.target else {
+            return;
+        };
+        engine.ledger().decryptions(node, 1, config);
+        if target == node {
+            self.settle(node, message.origin, engine);
+            return;
+        }
+        // Relays decrypt with the key of the previous hop and encrypt with the key of the next
+        let next = match self.link(message.origin, target) {
+            Some(LinkKey::PathKey(path)) => path
+                .iter()
+                .position(|&hop| hop == node)
+                .and_then(|position| path.get(position + 1).copied()),
+            _ => None,
+        };
+        if let Some(next) = next {
+            engine.ledger().encryptions(node, 1, config);
+            engine.send(message.relay(node, next));
+        }
+    }
+}
+
+impl Protocol for PoolHashProtocol {
+    fn start(&mut self, node: usize, engine: &mut Engine) {
+        let config = engine.config();
+        engine.ledger().encryptions(node, 1, config);
+        engine.send(Message::broadcast(
+            MessageKind::KeyIdentifiers,
+            node,
+            self.identifiers_size,
+        ));
+        if self.progress.is_done(node) && engine.nodes()[node].kind == NodeType::Gateway {
+            self.transport.start(node, engine);
+        }
+        self.try_complete(node, engine);
+    }
+
+    fn receive(&mut self, node: usize, message: &Message, engine: &mut Engine) {
+        match message.kind {
+            MessageKind::KeyIdentifiers => self.receive_identifiers(node, message.from, engine),
+            MessageKind::PathKey => self.receive_path_key(node, message, engine),
+            _ => self.transport.receive(node, message, engine),
+        }
+        self.try_complete(node, engine);
+    }
+
+    fn unsettled_links(&self) -> usize {
+        self.progress.unsettled_links()
+    }
+}
+
+pub struct PoolHash;
+
+impl PoolHash {
+    // The closed-form pairwise metrics depend on the rings, they are read from the ledger
+    fn pairwise_ledger(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        material: &KeyMaterial,
+    ) -> EnergyLedger {
+        let mut ledger = EnergyLedger::for_network(nodes);
+        self.record_pairwise(nodes, config, 0, material, &mut ledger);
+        ledger
+    }
+}
+
+impl KeyManagementScheme for PoolHash {
+    fn name(&self) -> &'static str {
+        "pool_hash"
+    }
+
+    // The pairwise key is a hash of the shared pool key, no field arithmetic is involved
+    fn number_of_multiplications(&self, _nodes: &NodesVec, _config: &SimulationConfig) -> u32 {
+        0
+    }
+
+    // Each end of a link sharing a pool key hashes it together with both node ids
+    fn number_pairwise_hashes(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        material: &KeyMaterial,
+    ) -> u32 {
+        self.pairwise_ledger(nodes, config, material)
+            .operations()
+            .hashes
+    }
+
+    // Each node encrypts its key identifiers broadcast once, and every hop of a path key is
+    // encrypted for the next one. Gateways hold a ring too and discover their keys the same way.
+    fn number_of_pairwise_encryptions(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        material: &KeyMaterial,
+    ) -> u32 {
+        self.pairwise_ledger(nodes, config, material)
+            .operations()
+            .encryptions
+    }
+
+    // The broadcast is padded to AES_BLOCK_SIZE, the MAC size plays no part in the discovery
+    fn pairwise_communication_energy(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        _mac_size: u32,
+        material: &KeyMaterial,
+    ) -> f32 {
+        self.pairwise_ledger(nodes, config, material)
+            .total()
+            .communication()
+    }
+
+    fn pairwise_communication_sent(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        _mac_size: u32,
+        material: &KeyMaterial,
+    ) -> f32 {
+        self.pairwise_ledger(nodes, config, material).bytes_sent()
+    }
+
+    fn pairwise_communication_received(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        _mac_size: u32,
+        material: &KeyMaterial,
+    ) -> f32 {
+        self.pairwise_ledger(nodes, config, material)
+            .bytes_received()
+    }
+
+    // The gateway transports the group key to each member under their pool-derived link key
+    // and the member acknowledges it
+    fn groupwise_communication_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32 {
+        let mut energy = 0.0;
+        let mut ledger = EnergyLedger::for_network(nodes);
+        record_key_transport(nodes, config, &mut ledger);
+        energy += ledger.total().communication();
+        energy
+    }
+
+    // The gateway encrypts the group key for every reachable member and decrypts its
+    // acknowledgement, the member decrypts the key and encrypts the acknowledgement
+    fn groupwise_encryptions_energy(&self, nodes: &NodesVec, config: &SimulationConfig) -> f32 {
+        let mut energy = 0.0;
+        let mut ledger = EnergyLedger::for_network(nodes);
+        record_key_transport(nodes, config, &mut ledger);
+        let total = ledger.total();
+        energy += total.encrypt + total.decrypt;
+        energy
+    }
+
+    fn groupwise_hashes_energy(&self, _nodes: &NodesVec, _config: &SimulationConfig) -> f32 {
+        0.0
+    }
+
+    // Every node broadcasts its encrypted key identifiers and decrypts those of its neighbors.
+    // Both ends of a link sharing a pool key hash it, the other links secured by a path key
+    // carry it hop by hop.
+    fn record_pairwise(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        _mac_size: u32,
+        material: &KeyMaterial,
+        ledger: &mut EnergyLedger,
+    ) {
+        let message = encrypted_message_size(config);
+        for node in pairwise_participants(nodes) {
+            let neighbors_count = node.neighbors.len() as u32;
+            ledger.encryptions(node.id, 1, config);
+            ledger.transmit(
+                node.id,
+                message,
+                broadcast_distance(nodes, node.id, config),
+                config,
+            );
+            // The broadcast of every neighbor is received and decrypted to read its key identifiers
+            ledger.receive(node.id, neighbors_count as f32 * message, config);
+            ledger.decryptions(node.id, neighbors_count, config);
+        }
+        for link in key_links(nodes, config, &material.key_rings) {
+            match link.key {
+                LinkKey::Direct(_) => {
+                    ledger.hashes(link.from, 1, config);
+                    ledger.hashes(link.to, 1, config);
+                }
+                LinkKey::PathKey(path) => record_path_key(nodes, config, &path, ledger),
+                LinkKey::Unsecured => {}
+            }
+        }
+    }
+
+    fn record_groupwise(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        ledger: &mut EnergyLedger,
+    ) {
+        record_key_transport(nodes, config, ledger);
+    }
+
+    // A new key is sent to every member whatever the trigger
+    fn record_group_rekey(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        cluster: &Cluster,
+        _trigger: RekeyTrigger,
+        ledger: &mut EnergyLedger,
+    ) {
+        let routes = RoutingTable::to_gateways(nodes);
+        record_cluster_key_transport(nodes, config, &routes, cluster, ledger);
+    }
+
+    // The revocation lists the key identifiers of the revoked ring
+    fn revocation_message_size(&self, config: &SimulationConfig, mac_size: u32) -> f32 {
+        (config.message_type_size
+            + config.node_id_size
+            + config.node_id_size * config.key_ring_size
+            + mac_size) as f32
+    }
+
+    // Every node the revocation reached erases the pool keys of the revoked ring. Its links keyed
+    // with an erased key fall back on another pool key both ends hold, hashed by each end, or
+    // else on a path key over the links still keyed, and stay unsecured otherwise. Nodes the
+    // revocation missed keep the erased keys and still share them with the revoked node.
+    fn record_revocation(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        _mac_size: u32,
+        revocation: &Revocation,
+        material: &KeyMaterial,
+        ledger: &mut EnergyLedger,
+    ) -> Vec<usize> {
+        let mut rings = material.key_rings.clone();
+        let mut revoked_keys: Vec<bool> = vec![false; config.key_pool_size as usize];
+        for &key in rings[revocation.node].iter() {
+            revoked_keys[key as usize] = true;
+        }
+        let affected: Vec<(usize, usize)> = key_links(nodes, config, &rings)
+            .into_iter()
+            .filter(|link| matches!(link.key, LinkKey::Direct(key) if revoked_keys[key as usize]))
+            .map(|link| (link.from, link.to))
+            .collect();
+        for (id, ring) in rings.iter_mut().enumerate() {
+            if revocation.reached[id] {
+                ring.retain(|&key| !revoked_keys[key as usize]);
+            }
+        }
+
+        let mut rekeyed: HashMap<(usize, usize), LinkKey> = key_links(nodes, config, &rings)
+            .into_iter()
+            .map(|link| ((link.from, link.to), link.key))
+            .collect();
+        for link in affected.iter() {
+            match rekeyed.remove(link) {
+                Some(LinkKey::Direct(_)) => {
+                    ledger.hashes(link.0, 1, config);
+                    ledger.hashes(link.1, 1, config);
+                }
+                Some(LinkKey::PathKey(path)) => record_path_key(nodes, config, &path, ledger),
+                Some(LinkKey::Unsecured) | None => {}
+            }
+        }
+        self.record_leave(nodes, config, &revocation.departure, ledger);
+
+        nodes
+            .iter()
+            .filter(|node| {
+                node.is_active() && rings[node.id].iter().any(|&key| revoked_keys[key as usize])
+            })
+            .map(|node| node.id)
+            .collect()
+    }
+
+    fn predistribute(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        rng: &mut dyn RngCore,
+    ) -> KeyMaterial {
+        KeyMaterial {
+            key_rings: assign_key_rings(nodes, config, rng),
+        }
+    }
+
+    fn key_discovery(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        _mac_size: u32,
+        material: &KeyMaterial,
+    ) -> Option<KeyDiscovery> {
+        let rings = &material.key_rings;
+        let mut discovery = KeyDiscovery {
+            discovery_bytes_sent: identifiers_sent(nodes, config),
+            discovery_bytes_received: identifiers_received(nodes, config),
+            ..KeyDiscovery::default()
+        };
+        let message = path_key_message_size(config);
+        for link in key_links(nodes, config, rings) {
+            discovery.links += 1;
+            match link.key {
+                LinkKey::Direct(_) => discovery.direct_links += 1,
+                LinkKey::PathKey(path) => {
+                    discovery.path_key_links += 1;
+                    for hop in path.windows(2) {
+                        discovery.path_key_messages += 1;
+                        discovery.path_key_bytes_sent += message;
+                        discovery.path_key_bytes_received += message;
+                        discovery.path_key_energy += config.encryption_energy
+                            + config.transmit_energy(
+                                message,
+                                link_distance(nodes, hop[0], hop[1], config),
+                            )
+                            + config.receive_energy(message)
+                            + config.decryption_energy;
+                    }
+                }
+                LinkKey::Unsecured => discovery.unsecured_links += 1,
+            }
+        }
+        Some(discovery)
+    }
+
+    fn protocol(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        _mac_size: u32,
+        material: &KeyMaterial,
+    ) -> Box<dyn Protocol> {
+        let links = key_links(nodes, config, &material.key_rings)
+            .into_iter()
+            .map(|link| ((link.from, link.to), link.key))
+            .collect();
+        Box::new(PoolHashProtocol {
+            progress: PairwiseProgress::new(nodes),
+            transport: KeyTransport::new(
+                nodes,
+                config.received_message_size as f32,
+                config.sent_message_size as f32,
+            ),
+            links,
+            identifiers_size: encrypted_message_size(config),
+            path_key_size: path_key_message_size(config),
+        })
+    }
+
+    // A direct key is exposed once any captured ring holds its pool key, the ids it is hashed
+    // with being public. A path key is exposed when a relay of its path is captured or the key
+    // of one of its hops is exposed. Unsecured links have no key to expose.
+    fn link_exposure(
+        &self,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        captured: &[bool],
+        material: &KeyMaterial,
+    ) -> Exposure {
+        let rings = &material.key_rings;
+        let mut known: Vec<bool> = vec![false; config.key_pool_size as usize];
+        for (id, ring) in rings.iter().enumerate() {
+            if captured[id] {
+                for &key in ring.iter() {
+                    known[key as usize] = true;
+                }
+            }
+        }
+        let hop_exposed = |from: usize, to: usize| {
+            shared_key(&rings[from], &rings[to]).is_some_and(|key| known[key as usize])
+        };
+
+        let mut exposure = Exposure::default();
+        for link in key_links(nodes, config, rings) {
+            if captured[link.from] || captured[link.to] {
+                continue;
+            }
+            let exposed = match &link.key {
+                LinkKey::Direct(key) => known[*key as usize],
+                LinkKey::PathKey(path) => {
+                    path[1..path.len() - 1].iter().any(|&relay| captured[relay])
+                        || path.windows(2).any(|hop| hop_exposed(hop[0], hop[1]))
+                }
+                LinkKey::Unsecured => continue,
+            };
+            exposure.total += 1;
+            if exposed {
+                exposure.exposed += 1;
+            }
+        }
+        exposure
+    }
+
+    // Every key of the ring is as long as one AES block
+    fn key_storage_bytes(&self, config: &SimulationConfig) -> Option<u32> {
+        Some(config.key_ring_size * config.aes_block_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rng::rng_from_seed,
+        simulation::simulate,
+        topology::{assign_gateway_clusters, initialize_network_from_seed},
+        Node,
     };
-    for node in nodes.iter() {
-        if node.kind == NodeType::Gateway {
-            continue;
+
+    fn network(seed: u64) -> NodesVec {
+        let mut nodes = initialize_network_from_seed(60, 6, 3, 8, seed);
+        assign_gateway_clusters(&mut nodes, 10, &mut rng_from_seed(seed));
+        nodes
+    }
+
+    // Triangle 0-1-2 where only 0-1 and 1-2 share a key, and node 3 hanging off 0 with a key
+    // nobody else holds
+    fn triangle() -> (NodesVec, KeyMaterial) {
+        let links: [&[usize]; 4] = [&[1, 2, 3], &[0, 2], &[0, 1], &[0]];
+        let mut nodes = NodesVec::new();
+        for (id, neighbors) in links.iter().enumerate() {
+            let kind = if id == 0 {
+                NodeType::Gateway
+            } else {
+                NodeType::Constrained
+            };
+            nodes.push(Node::new(id, kind, neighbors.to_vec(), neighbors.len()));
+        }
+        let material = KeyMaterial {
+            key_rings: vec![vec![1, 5], vec![5, 9], vec![9, 12], vec![20]],
+        };
+        (nodes, material)
+    }
+
+    #[test]
+    fn shared_key_is_the_smallest_common_identifier() {
+        assert_eq!(shared_key(&[1, 4, 7, 9], &[2, 7, 9]), Some(7));
+        assert_eq!(shared_key(&[1, 3], &[2, 4]), None);
+        assert_eq!(shared_key(&[], &[2, 4]), None);
+    }
+
+    #[test]
+    fn links_without_a_shared_key_go_through_a_path_key() {
+        let (nodes, material) = triangle();
+        let config = SimulationConfig::default();
+        let links: Vec<(usize, usize, String)> = key_links(&nodes, &config, &material.key_rings)
+            .into_iter()
+            .map(|link| {
+                let key = match link.key {
+                    LinkKey::Direct(key) => format!("direct {}", key),
+                    LinkKey::PathKey(path) => format!("path {:?}", path),
+                    LinkKey::Unsecured => "unsecured".to_string(),
+                };
+                (link.from, link.to, key)
+            })
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                (0, 1, "direct 5".to_string()),
+                (0, 2, "path [0, 1, 2]".to_string()),
+                (0, 3, "unsecured".to_string()),
+                (1, 2, "direct 9".to_string()),
+            ]
+        );
+
+        // A path of two hops is too long for a single hop budget
+        let mut config = config;
+        config.set("PATH_KEY_MAX_HOPS", "1").unwrap();
+        let discovery = PoolHash
+            .key_discovery(&nodes, &config, 8, &material)
+            .unwrap();
+        assert_eq!(discovery.path_key_links, 0);
+        assert_eq!(discovery.unsecured_links, 2);
+    }
+
+    #[test]
+    fn pairwise_costs_follow_the_rings() {
+        let (nodes, material) = triangle();
+        let config = SimulationConfig::default();
+        let mut ledger = EnergyLedger::for_network(&nodes);
+        PoolHash.record_pairwise(&nodes, &config, 8, &material, &mut ledger);
+        let operations = ledger.operations();
+        // Both ends of the two direct links hash
+        assert_eq!(operations.hashes, 4);
+        assert_eq!(
+            PoolHash.number_pairwise_hashes(&nodes, &config, &material),
+            4
+        );
+        // Four broadcasts and the two hops of the path key
+        assert_eq!(operations.messages, 6);
+        assert_eq!(operations.encryptions, 6);
+        assert_eq!(operations.decryptions, 2 * 4 + 2);
+        assert_eq!(
+            PoolHash.pairwise_communication_sent(&nodes, &config, 8, &material),
+            4.0 * encrypted_message_size(&config) + 2.0 * path_key_message_size(&config)
+        );
+        // The relay of the path key is charged its hop
+        assert_eq!(ledger.node(1).encrypt, 2.0 * config.encryption_energy);
+    }
+
+    #[test]
+    fn discovery_fractions_match_the_rings() {
+        let config = SimulationConfig::default();
+        for seed in 0..3 {
+            let nodes = network(seed);
+            let material = PoolHash.predistribute(&nodes, &config, &mut rng_from_seed(seed));
+            let discovery = PoolHash
+                .key_discovery(&nodes, &config, 8, &material)
+                .unwrap();
+            let rings = &material.key_rings;
+            let mut links = 0;
+            let mut direct = 0;
+            for node in nodes.iter() {
+                for &neighbor in node
+                    .neighbors
+                    .iter()
+                    .filter(|&&neighbor| neighbor > node.id)
+                {
+                    links += 1;
+                    if shared_key(&rings[node.id], &rings[neighbor]).is_some() {
+                        direct += 1;
+                    }
+                }
+            }
+            assert_eq!(discovery.links, links);
+            assert_eq!(discovery.direct_links, direct);
+            let total = discovery.direct_fraction()
+                + discovery.path_key_fraction()
+                + discovery.unsecured_fraction();
+            assert!((total - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn keying_round_matches_the_protocol() {
+        let config = SimulationConfig::default();
+        for seed in 0..3 {
+            let nodes = network(seed);
+            let material = PoolHash.predistribute(&nodes, &config, &mut rng_from_seed(seed));
+            let expected = PoolHash.keying_round_ledger(&nodes, &config, 8, &material);
+            let mut protocol = PoolHash.protocol(&nodes, &config, 8, &material);
+            let report = simulate(&nodes, &config, protocol.as_mut(), &mut rng_from_seed(seed));
+            assert_eq!(report.ledger.operations(), expected.operations());
+            assert_eq!(report.ledger.bytes_sent(), expected.bytes_sent());
         }
-        // For each neighbor of this node, we receive NODE_ID_SIZE * KEY_RING_SIZE bytes
-        let received_message = node.neighbors.len() as f32 * message;
-        received += received_message;
     }
-    received
 }
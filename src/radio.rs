@@ -0,0 +1,206 @@
+use std::{fmt, str::FromStr, sync::Arc};
+
+use serde::Serialize;
+
+use crate::{config::SimulationConfig, NodesVec};
+
+// Energy spent by the radio to move bytes over a link of a given length in meters
+pub trait RadioModel: fmt::Debug + Send + Sync {
+    fn transmit_energy(&self, bytes: f32, distance: f32) -> f32;
+
+    fn receive_energy(&self, bytes: f32) -> f32;
+}
+
+// Constant energy per byte whatever the distance
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FlatRadio {
+    pub epsb: f32,
+    pub eprb: f32,
+}
+
+impl RadioModel for FlatRadio {
+    fn transmit_energy(&self, bytes: f32, _distance: f32) -> f32 {
+        bytes * self.epsb
+    }
+
+    fn receive_energy(&self, bytes: f32) -> f32 {
+        bytes * self.eprb
+    }
+}
+
+// First-order radio model: electronics energy per bit for both ends, plus amplifier energy at
+// the transmitter growing with d² in free space and with d⁴ past the crossover distance
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FirstOrderRadio {
+    pub electronics_energy: f32,
+    pub free_space_amplifier: f32,
+    pub multipath_amplifier: f32,
+}
+
+impl FirstOrderRadio {
+    // Distance at which both amplifier models spend the same energy
+    pub fn crossover_distance(&self) -> f32 {
+        (self.free_space_amplifier / self.multipath_amplifier).sqrt()
+    }
+}
+
+impl RadioModel for FirstOrderRadio {
+    fn transmit_energy(&self, bytes: f32, distance: f32) -> f32 {
+        let bits = 8.0 * bytes;
+        let amplifier = if distance < self.crossover_distance() {
+            self.free_space_amplifier * distance.powi(2)
+        } else {
+            self.multipath_amplifier * distance.powi(4)
+        };
+        bits * (self.electronics_energy + amplifier)
+    }
+
+    fn receive_energy(&self, bytes: f32) -> f32 {
+        8.0 * bytes * self.electronics_energy
+    }
+}
+
+// Radio model of a configuration, shared by its clones
+#[derive(Debug, Clone)]
+pub struct SharedRadio(Arc<dyn RadioModel>);
+
+impl SharedRadio {
+    pub fn new(model: impl RadioModel + 'static) -> Self {
+        Self(Arc::new(model))
+    }
+
+    pub fn model(&self) -> &dyn RadioModel {
+        self.0.as_ref()
+    }
+}
+
+// The model is built from the radio parameters of the configuration, which are compared on
+// their own
+impl PartialEq for SharedRadio {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RadioKind {
+    // EPSB and EPRB per byte
+    Flat,
+    // Distance-dependent transmit cost
+    FirstOrder,
+}
+
+impl FromStr for RadioKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "flat" => Ok(RadioKind::Flat),
+            "first_order" => Ok(RadioKind::FirstOrder),
+            _ => Err(format!("unknown radio model: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for RadioKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RadioKind::Flat => write!(f, "flat"),
+            RadioKind::FirstOrder => write!(f, "first_order"),
+        }
+    }
+}
+
+// Length of the link between two nodes, the configured default when they have no coordinates
+pub fn link_distance(nodes: &NodesVec, from: usize, to: usize, config: &SimulationConfig) -> f32 {
+    match (&nodes[from].position, &nodes[to].position) {
+        (Some(a), Some(b)) => a.distance(b) as f32,
+        _ => config.default_link_distance,
+    }
+}
+
+// Range a broadcast must cover to reach every neighbor of the node
+pub fn broadcast_distance(nodes: &NodesVec, node: usize, config: &SimulationConfig) -> f32 {
+    let mut distance: f32 = 0.0;
+    for &neighbor in nodes[node].neighbors.iter() {
+        distance = distance.max(link_distance(nodes, node, neighbor, config));
+    }
+    distance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_order() -> FirstOrderRadio {
+        FirstOrderRadio {
+            electronics_energy: 50e-9,
+            free_space_amplifier: 10e-12,
+            multipath_amplifier: 0.0013e-12,
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() <= 1e-4 * expected.abs(),
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn amplifiers_meet_at_the_crossover_distance() {
+        let radio = first_order();
+        let crossover = radio.crossover_distance();
+        assert_close(crossover, 87.705);
+        assert_close(
+            radio.free_space_amplifier * crossover.powi(2),
+            radio.multipath_amplifier * crossover.powi(4),
+        );
+        // Free space below the crossover, multipath from it on
+        let below = 0.5 * crossover;
+        assert_close(
+            radio.transmit_energy(1.0, below),
+            8.0 * (radio.electronics_energy + radio.free_space_amplifier * below.powi(2)),
+        );
+        let above = 2.0 * crossover;
+        assert_close(
+            radio.transmit_energy(1.0, above),
+            8.0 * (radio.electronics_energy + radio.multipath_amplifier * above.powi(4)),
+        );
+        assert_close(
+            radio.transmit_energy(1.0, crossover * 0.99999),
+            radio.transmit_energy(1.0, crossover),
+        );
+        assert_close(radio.receive_energy(2.0), 16.0 * radio.electronics_energy);
+    }
+
+    #[test]
+    fn flat_model_charges_epsb_and_eprb() {
+        let mut config = SimulationConfig::default();
+        config.set("EPSB", "0.5").unwrap();
+        config.set("EPRB", "0.25").unwrap();
+        for distance in [0.0, 10.0, 1000.0] {
+            assert_eq!(config.transmit_energy(4.0, distance), 2.0);
+        }
+        assert_eq!(config.receive_energy(4.0), 1.0);
+    }
+
+    #[test]
+    fn configuration_calls_the_selected_model() {
+        let mut config = SimulationConfig::default();
+        config.set("RADIO_MODEL", "first_order").unwrap();
+        let radio = config.first_order;
+        assert_eq!(
+            config.transmit_energy(4.0, 120.0),
+            radio.transmit_energy(4.0, 120.0)
+        );
+        // Parameters set after the model still apply to it
+        config.set("RADIO_ELECTRONICS_ENERGY", "0.000001").unwrap();
+        assert_eq!(config.receive_energy(1.0), 8.0 * 0.000001);
+        config.set("RADIO_MODEL", "flat").unwrap();
+        assert_eq!(config.receive_energy(1.0), config.eprb);
+    }
+}
@@ -0,0 +1,584 @@
+use std::{
+    io::{self, Write},
+    ops::AddAssign,
+};
+
+use rand::{Rng, RngCore};
+use serde::Serialize;
+
+use crate::{
+    config::SimulationConfig,
+    ledger::EnergyLedger,
+    methods::{KeyManagementScheme, KeyMaterial, RekeyTrigger},
+    results::write_csv_row,
+    revocation::Revocation,
+    Cluster, NodeType, NodesVec,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RekeyEvent {
+    // A node joined or left the cluster of the gateway, its group key is replaced
+    MembershipChange { round: u32, gateway: usize },
+    // The node was found compromised, it is revoked and the keys it held are replaced
+    Compromise { round: u32, node: usize },
+}
+
+impl RekeyEvent {
+    pub fn round(&self) -> u32 {
+        match self {
+            RekeyEvent::MembershipChange { round, .. } => *round,
+            RekeyEvent::Compromise { round, .. } => *round,
+        }
+    }
+}
+
+// Draw the membership changes and compromises of `rounds` rounds, the same for every scheme
+pub fn draw_events(
+    nodes: &NodesVec,
+    config: &SimulationConfig,
+    rounds: u32,
+    rng: &mut dyn RngCore,
+) -> Vec<RekeyEvent> {
+    let clusters = nodes.clusters();
+    let mut events: Vec<RekeyEvent> = Vec::new();
+    for round in 1..=rounds {
+        for cluster in clusters.iter() {
+            if rng.gen::<f32>() < config.membership_change_rate {
+                events.push(RekeyEvent::MembershipChange {
+                    round,
+                    gateway: cluster.gateway,
+                });
+            }
+        }
+        for node in nodes.iter() {
+            if node.kind == NodeType::Constrained && rng.gen::<f32>() < config.compromise_rate {
+                events.push(RekeyEvent::Compromise {
+                    round,
+                    node: node.id,
+                });
+            }
+        }
+    }
+    events
+}
+
+// Energy and traffic of the whole network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct RekeyCost {
+    pub energy: f64,
+    pub bytes_sent: f64,
+    pub bytes_received: f64,
+}
+
+impl RekeyCost {
+    pub fn from_ledger(ledger: &EnergyLedger) -> Self {
+        Self {
+            energy: ledger.total().total() as f64,
+            bytes_sent: ledger.bytes_sent() as f64,
+            bytes_received: ledger.bytes_received() as f64,
+        }
+    }
+}
+
+impl AddAssign for RekeyCost {
+    fn add_assign(&mut self, other: Self) {
+        self.energy += other.energy;
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RekeyRound {
+    pub round: u32,
+    // Clusters whose group key was refreshed
+    pub group_rekeys: usize,
+    pub pairwise_rekey: bool,
+    pub cost: RekeyCost,
+    // Cost of the rounds up to this one, the initial keying included
+    pub cumulative: RekeyCost,
+}
+
+// Cost of a deployment keyed once then rekeyed on schedule and on events
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rekeying {
+    pub scheme: &'static str,
+    // Round 0 is the initial pairwise then groupwise establishment
+    pub rounds: Vec<RekeyRound>,
+}
+
+impl Rekeying {
+    pub fn total(&self) -> RekeyCost {
+        self.rounds
+            .last()
+            .map(|round| round.cumulative)
+            .unwrap_or_default()
+    }
+}
+
+// Cost of one refresh for each trigger
+#[derive(Debug, Clone, Copy)]
+struct RefreshCost {
+    periodic: RekeyCost,
+    exclusion: RekeyCost,
+}
+
+impl RefreshCost {
+    fn measure<F: Fn(RekeyTrigger, &mut EnergyLedger)>(nodes: &NodesVec, record: F) -> Self {
+        Self {
+            periodic: measure(nodes, |ledger| record(RekeyTrigger::Periodic, ledger)),
+            exclusion: measure(nodes, |ledger| record(RekeyTrigger::Exclusion, ledger)),
+        }
+    }
+
+    fn get(&self, trigger: RekeyTrigger) -> RekeyCost {
+        match trigger {
+            RekeyTrigger::Periodic => self.periodic,
+            RekeyTrigger::Exclusion => self.exclusion,
+        }
+    }
+}
+
+fn measure<F: FnOnce(&mut EnergyLedger)>(nodes: &NodesVec, record: F) -> RekeyCost {
+    let mut ledger = EnergyLedger::for_network(nodes);
+    record(&mut ledger);
+    RekeyCost::from_ledger(&ledger)
+}
+
+// Rekeying of every scheme facing the same events
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RekeyReport {
+    pub events: Vec<RekeyEvent>,
+    pub schedules: Vec<Rekeying>,
+}
+
+fn is_scheduled(round: u32, interval: u32) -> bool {
+    interval > 0 && round.is_multiple_of(interval)
+}
+
+// Cost of the scheduled refreshes on a topology, measured again whenever it changes
+struct ScheduledCosts {
+    clusters: Vec<Cluster>,
+    pairwise: RekeyCost,
+    group: Vec<RefreshCost>,
+}
+
+impl ScheduledCosts {
+    fn measure(
+        scheme: &dyn KeyManagementScheme,
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        mac_size: u32,
+        material: &KeyMaterial,
+    ) -> Self {
+        let clusters = nodes.clusters();
+        let pairwise = measure(nodes, |ledger| {
+            scheme.record_pairwise_rekey(
+                nodes,
+                config,
+                mac_size,
+                material,
+                RekeyTrigger::Periodic,
+                ledger,
+            )
+        });
+        let group = clusters
+            .iter()
+            .map(|cluster| {
+                RefreshCost::measure(nodes, |trigger, ledger| {
+                    scheme.record_group_rekey(nodes, config, cluster, trigger, ledger)
+                })
+            })
+            .collect();
+        Self {
+            clusters,
+            pairwise,
+            group,
+        }
+    }
+}
+
+// Run `rounds` rounds of the scheme after its initial keying. Group keys are refreshed every
+// REKEY_GROUP_INTERVAL rounds and pairwise keys every REKEY_PAIRWISE_INTERVAL rounds, the
+// events of a round replacing the keys they expose on top of the schedule. A compromised node is
+// revoked for the rest of the run as if every node had heard of it: the scheme replaces the keys
+// the node held and its cluster gets a new key it is kept out of. In a round with a scheduled
+// pairwise refresh only its cluster key is replaced, the refresh covering the pairwise keys.
+pub fn simulate_rekeying(
+    scheme: &dyn KeyManagementScheme,
+    nodes: &NodesVec,
+    config: &SimulationConfig,
+    mac_size: u32,
+    material: &KeyMaterial,
+    events: &[RekeyEvent],
+    rounds: u32,
+) -> Rekeying {
+    let mut network = nodes.clone();
+    // The cost of a scheduled refresh only changes when a node is revoked
+    let mut scheduled = ScheduledCosts::measure(scheme, &network, config, mac_size, material);
+
+    let setup =
+        RekeyCost::from_ledger(&scheme.keying_round_ledger(nodes, config, mac_size, material));
+    let mut cumulative = setup;
+    let mut series: Vec<RekeyRound> = Vec::with_capacity(rounds as usize + 1);
+    series.push(RekeyRound {
+        round: 0,
+        group_rekeys: scheduled.clusters.len(),
+        pairwise_rekey: true,
+        cost: setup,
+        cumulative,
+    });
+
+    for round in 1..=rounds {
+        let round_events: Vec<&RekeyEvent> = events
+            .iter()
+            .filter(|event| event.round() == round)
+            .collect();
+        let scheduled_pairwise = is_scheduled(round, config.rekey_pairwise_interval);
+        let mut cost = RekeyCost::default();
+
+        // Gateways whose cluster key a revocation already replaced
+        let mut replaced: Vec<usize> = Vec::new();
+        let mut revoked = 0;
+        for event in round_events.iter() {
+            let RekeyEvent::Compromise { node, .. } = **event else {
+                continue;
+            };
+            // Compromised in an earlier round, or twice in this one
+            if !network[node].is_active() {
+                continue;
+            }
+            let departure = network.revoke_node(node);
+            replaced.extend(departure.gateway);
+            revoked += 1;
+            let mut ledger = EnergyLedger::for_network(&network);
+            if scheduled_pairwise {
+                scheme.record_leave(&network, config, &departure, &mut ledger);
+            } else {
+                let revocation = Revocation {
+                    node,
+                    issuer: departure.gateway,
+                    reached: vec![true; network.len()],
+                    departure,
+                };
+                scheme.record_revocation(
+                    &network,
+                    config,
+                    mac_size,
+                    &revocation,
+                    material,
+                    &mut ledger,
+                );
+            }
+            cost += RekeyCost::from_ledger(&ledger);
+        }
+        if revoked > 0 {
+            scheduled = ScheduledCosts::measure(scheme, &network, config, mac_size, material);
+        }
+
+        let mut group: Vec<Option<RekeyTrigger>> = vec![None; scheduled.clusters.len()];
+        if is_scheduled(round, config.rekey_group_interval) {
+            group.fill(Some(RekeyTrigger::Periodic));
+        }
+        for event in round_events.iter() {
+            if let RekeyEvent::MembershipChange { gateway, .. } = **event {
+                if let Some(index) = scheduled
+                    .clusters
+                    .iter()
+                    .position(|cluster| cluster.gateway == gateway)
+                {
+                    group[index] = Some(RekeyTrigger::Exclusion);
+                }
+            }
+        }
+        let mut group_rekeys = 0;
+        for (index, trigger) in group.iter().enumerate() {
+            if replaced.contains(&scheduled.clusters[index].gateway) {
+                group_rekeys += 1;
+            } else if let Some(trigger) = *trigger {
+                cost += scheduled.group[index].get(trigger);
+                group_rekeys += 1;
+            }
+        }
+        if scheduled_pairwise {
+            cost += scheduled.pairwise;
+        }
+        cumulative += cost;
+        series.push(RekeyRound {
+            round,
+            group_rekeys,
+            pairwise_rekey: scheduled_pairwise || revoked > 0,
+            cost,
+            cumulative,
+        });
+    }
+
+    Rekeying {
+        scheme: scheme.name(),
+        rounds: series,
+    }
+}
+
+// One row per scheme and round, the events and the total of each scheme as `#` comments
+pub fn write_rekeying_csv<W: Write>(writer: &mut W, report: &RekeyReport) -> io::Result<()> {
+    let events = &report.events;
+    let membership_changes = events
+        .iter()
+        .filter(|event| matches!(event, RekeyEvent::MembershipChange { .. }))
+        .count();
+    writeln!(writer, "# membership_changes={}", membership_changes)?;
+    writeln!(
+        writer,
+        "# compromises={}",
+        events.len() - membership_changes
+    )?;
+    for schedule in report.schedules.iter() {
+        let total = schedule.total();
+        writeln!(
+            writer,
+            "# {}.total_energy={} {}.total_bytes_sent={}",
+            schedule.scheme, total.energy, schedule.scheme, total.bytes_sent
+        )?;
+    }
+    writeln!(
+        writer,
+        "scheme,round,group_rekeys,pairwise_rekey,energy,bytes_sent,bytes_received,cumulative_energy,cumulative_bytes_sent,cumulative_bytes_received"
+    )?;
+    for schedule in report.schedules.iter() {
+        for round in schedule.rounds.iter() {
+            write_csv_row(
+                writer,
+                &[
+                    &schedule.scheme,
+                    &round.round,
+                    &round.group_rekeys,
+                    &round.pairwise_rekey,
+                    &round.cost.energy,
+                    &round.cost.bytes_sent,
+                    &round.cost.bytes_received,
+                    &round.cumulative.energy,
+                    &round.cumulative.bytes_sent,
+                    &round.cumulative.bytes_received,
+                ],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        methods::matrix::Matrix,
+        rng::rng_from_seed,
+        topology::{assign_gateway_clusters, initialize_network_from_seed},
+    };
+
+    fn network(seed: u64) -> NodesVec {
+        let mut nodes = initialize_network_from_seed(60, 6, 3, 8, seed);
+        assign_gateway_clusters(&mut nodes, 10, &mut rng_from_seed(seed));
+        nodes
+    }
+
+    fn config(group_interval: u32, pairwise_interval: u32) -> SimulationConfig {
+        let mut config = SimulationConfig::default();
+        config.rekey_group_interval = group_interval;
+        config.rekey_pairwise_interval = pairwise_interval;
+        config
+    }
+
+    fn run(config: &SimulationConfig, events: &[RekeyEvent], rounds: u32) -> Rekeying {
+        simulate_rekeying(
+            &Matrix,
+            &network(1),
+            config,
+            8,
+            &KeyMaterial::default(),
+            events,
+            rounds,
+        )
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1e-6 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    // Energies summed per cluster and over the whole network differ in their last f32 bits
+    fn assert_same_cost(cost: RekeyCost, expected: RekeyCost) {
+        assert!(
+            close(cost.energy, expected.energy),
+            "{:?} {:?}",
+            cost,
+            expected
+        );
+        assert_eq!(cost.bytes_sent, expected.bytes_sent);
+        assert_eq!(cost.bytes_received, expected.bytes_received);
+    }
+
+    #[test]
+    fn refreshes_follow_their_interval() {
+        assert!(!is_scheduled(0, 0));
+        assert!(!is_scheduled(5, 0));
+        let scheduled: Vec<u32> = (1..=7).filter(|&round| is_scheduled(round, 3)).collect();
+        assert_eq!(scheduled, vec![3, 6]);
+        assert!((1..=4).all(|round| is_scheduled(round, 1)));
+    }
+
+    #[test]
+    fn events_are_drawn_at_their_rates() {
+        let nodes = network(1);
+        assert!(draw_events(&nodes, &config(0, 0), 10, &mut rng_from_seed(1)).is_empty());
+
+        let mut certain = config(0, 0);
+        certain.membership_change_rate = 1.0;
+        certain.compromise_rate = 1.0;
+        let events = draw_events(&nodes, &certain, 2, &mut rng_from_seed(1));
+        let constrained = nodes
+            .iter()
+            .filter(|node| node.kind == NodeType::Constrained)
+            .count();
+        assert_eq!(events.len(), 2 * (nodes.clusters().len() + constrained));
+        assert!(events.iter().all(|event| match *event {
+            RekeyEvent::MembershipChange { gateway, .. } =>
+                nodes[gateway].kind == NodeType::Gateway,
+            RekeyEvent::Compromise { node, .. } => nodes[node].kind == NodeType::Constrained,
+        }));
+
+        let mut some = config(0, 0);
+        some.membership_change_rate = 0.3;
+        some.compromise_rate = 0.05;
+        let events = draw_events(&nodes, &some, 5, &mut rng_from_seed(2));
+        assert_eq!(events, draw_events(&nodes, &some, 5, &mut rng_from_seed(2)));
+        assert!(events
+            .windows(2)
+            .all(|pair| pair[0].round() <= pair[1].round()));
+        assert!(events.iter().all(|event| (1..=5).contains(&event.round())));
+    }
+
+    #[test]
+    fn the_cumulative_cost_adds_up_the_rounds() {
+        let config = config(2, 3);
+        let rekeying = run(&config, &[], 6);
+        let nodes = network(1);
+        let setup = RekeyCost::from_ledger(&Matrix.keying_round_ledger(
+            &nodes,
+            &config,
+            8,
+            &KeyMaterial::default(),
+        ));
+        assert_eq!(rekeying.rounds[0].cost, setup);
+        for pair in rekeying.rounds.windows(2) {
+            let (previous, round) = (&pair[0], &pair[1]);
+            assert!(close(
+                round.cumulative.energy,
+                previous.cumulative.energy + round.cost.energy
+            ));
+            assert!(close(
+                round.cumulative.bytes_sent,
+                previous.cumulative.bytes_sent + round.cost.bytes_sent
+            ));
+        }
+        let clusters = nodes.clusters().len();
+        let schedule: Vec<(usize, bool)> = rekeying.rounds[1..]
+            .iter()
+            .map(|round| (round.group_rekeys, round.pairwise_rekey))
+            .collect();
+        assert_eq!(
+            schedule,
+            vec![
+                (0, false),
+                (clusters, false),
+                (0, true),
+                (clusters, false),
+                (0, false),
+                (clusters, true)
+            ]
+        );
+        assert_eq!(rekeying.rounds[1].cost, RekeyCost::default());
+        assert_eq!(rekeying.rounds[2].cost, rekeying.rounds[4].cost);
+        assert_eq!(rekeying.total(), rekeying.rounds[6].cumulative);
+    }
+
+    // A member of some cluster, compromised at round 1 and found again at round 2
+    fn compromised_member(nodes: &NodesVec) -> usize {
+        nodes
+            .iter()
+            .find(|node| node.gateway.is_some() && !node.neighbors.is_empty())
+            .expect("some node has a cluster")
+            .id
+    }
+
+    #[test]
+    fn a_compromised_node_stays_revoked() {
+        let nodes = network(1);
+        let node = compromised_member(&nodes);
+        let events = [
+            RekeyEvent::Compromise { round: 1, node },
+            RekeyEvent::Compromise { round: 2, node },
+        ];
+        let config = config(3, 0);
+        let rekeying = run(&config, &events, 3);
+
+        let mut revoked = nodes.clone();
+        let departure = revoked.revoke_node(node);
+        let gateway = departure.gateway.unwrap();
+        let mut ledger = EnergyLedger::for_network(&revoked);
+        Matrix.record_revocation(
+            &revoked,
+            &config,
+            8,
+            &Revocation {
+                node,
+                issuer: Some(gateway),
+                reached: vec![true; revoked.len()],
+                departure,
+            },
+            &KeyMaterial::default(),
+            &mut ledger,
+        );
+        let round = &rekeying.rounds[1];
+        assert_same_cost(round.cost, RekeyCost::from_ledger(&ledger));
+        assert_eq!((round.group_rekeys, round.pairwise_rekey), (1, true));
+        // Already out of the network
+        assert_eq!(rekeying.rounds[2].cost, RekeyCost::default());
+        // The scheduled refresh no longer reaches it
+        let mut refresh = EnergyLedger::for_network(&revoked);
+        for cluster in revoked.clusters().iter() {
+            Matrix.record_group_rekey(
+                &revoked,
+                &config,
+                cluster,
+                RekeyTrigger::Periodic,
+                &mut refresh,
+            );
+        }
+        assert_same_cost(rekeying.rounds[3].cost, RekeyCost::from_ledger(&refresh));
+    }
+
+    #[test]
+    fn a_scheduled_pairwise_refresh_covers_the_compromised_links() {
+        let nodes = network(1);
+        let node = compromised_member(&nodes);
+        let config = config(0, 1);
+        let rekeying = run(&config, &[RekeyEvent::Compromise { round: 1, node }], 1);
+
+        let mut revoked = nodes.clone();
+        let departure = revoked.revoke_node(node);
+        let mut ledger = EnergyLedger::for_network(&revoked);
+        Matrix.record_leave(&revoked, &config, &departure, &mut ledger);
+        let mut expected = RekeyCost::from_ledger(&ledger);
+        let mut refresh = EnergyLedger::for_network(&revoked);
+        Matrix.record_pairwise_rekey(
+            &revoked,
+            &config,
+            8,
+            &KeyMaterial::default(),
+            RekeyTrigger::Periodic,
+            &mut refresh,
+        );
+        expected += RekeyCost::from_ledger(&refresh);
+        assert_same_cost(rekeying.rounds[1].cost, expected);
+    }
+}
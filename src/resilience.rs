@@ -0,0 +1,213 @@
+use std::{fmt, str::FromStr};
+
+use rand::{seq::index, RngCore};
+use serde::Serialize;
+
+use crate::{NodeType, NodesVec};
+
+// Which nodes the adversary picks from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureTarget {
+    // Constrained nodes
+    Nodes,
+    Gateways,
+}
+
+impl FromStr for CaptureTarget {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "nodes" => Ok(CaptureTarget::Nodes),
+            "gateways" => Ok(CaptureTarget::Gateways),
+            _ => Err(format!("unknown capture target: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for CaptureTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureTarget::Nodes => write!(f, "nodes"),
+            CaptureTarget::Gateways => write!(f, "gateways"),
+        }
+    }
+}
+
+// Capture `count` distinct random nodes of the target kind, all of them when there are fewer.
+// The result is indexed by node id.
+pub fn capture_nodes(
+    nodes: &NodesVec,
+    target: CaptureTarget,
+    count: usize,
+    rng: &mut dyn RngCore,
+) -> Vec<bool> {
+    let kind = match target {
+        CaptureTarget::Nodes => NodeType::Constrained,
+        CaptureTarget::Gateways => NodeType::Gateway,
+    };
+    let candidates: Vec<usize> = nodes
+        .iter()
+        .filter(|node| node.kind == kind)
+        .map(|node| node.id)
+        .collect();
+    let mut captured: Vec<bool> = vec![false; nodes.len()];
+    for i in index::sample(rng, candidates.len(), count.min(candidates.len())) {
+        captured[candidates[i]] = true;
+    }
+    captured
+}
+
+// Keys held only by uncaptured nodes and how many of them the adversary can compute
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Exposure {
+    pub total: usize,
+    pub exposed: usize,
+}
+
+impl Exposure {
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.exposed as f64 / self.total as f64
+        }
+    }
+}
+
+// Pairwise keys computed from key material unique to each pair: capturing nodes only exposes
+// their own links until more than `threshold` nodes are captured, after which the adversary
+// can compute every key
+pub fn threshold_exposure(nodes: &NodesVec, captured: &[bool], threshold: Option<u32>) -> Exposure {
+    let captured_count = captured.iter().filter(|&&captured| captured).count();
+    let broken = threshold.is_some_and(|threshold| captured_count > threshold as usize);
+    let mut exposure = Exposure::default();
+    for node in nodes.iter() {
+        if captured[node.id] {
+            continue;
+        }
+        for &neighbor in node.neighbors.iter() {
+            if neighbor < node.id || captured[neighbor] {
+                continue;
+            }
+            exposure.total += 1;
+            if broken {
+                exposure.exposed += 1;
+            }
+        }
+    }
+    exposure
+}
+
+// Uncaptured cluster members and how many of them share a group key the adversary holds.
+// A group key is exposed as soon as its gateway or one of its members is captured.
+pub fn group_exposure(nodes: &NodesVec, captured: &[bool]) -> Exposure {
+    let mut exposure = Exposure::default();
+    for cluster in nodes.clusters() {
+        let exposed =
+            captured[cluster.gateway] || cluster.members.iter().any(|&member| captured[member]);
+        for &member in cluster.members.iter() {
+            if captured[member] {
+                continue;
+            }
+            exposure.total += 1;
+            if exposed {
+                exposure.exposed += 1;
+            }
+        }
+    }
+    exposure
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rng::rng_from_seed, Node};
+
+    // Gateway 0 with members 1, 2 and 3, gateway 5 with member 4, linked along
+    // 0-1, 0-2, 1-2, 2-3, 3-4 and 4-5
+    fn two_clusters() -> NodesVec {
+        let links: [(usize, NodeType, Vec<usize>, Option<usize>); 6] = [
+            (0, NodeType::Gateway, vec![1, 2], None),
+            (1, NodeType::Constrained, vec![0, 2], Some(0)),
+            (2, NodeType::Constrained, vec![0, 1, 3], Some(0)),
+            (3, NodeType::Constrained, vec![2, 4], Some(0)),
+            (4, NodeType::Constrained, vec![3, 5], Some(5)),
+            (5, NodeType::Gateway, vec![4], None),
+        ];
+        let mut nodes = NodesVec::new();
+        for (id, kind, neighbors, gateway) in links {
+            let mut node = Node::new(id, kind, neighbors, 3);
+            node.gateway = gateway;
+            nodes.push(node);
+        }
+        nodes
+    }
+
+    fn captured(ids: &[usize]) -> Vec<bool> {
+        (0..6).map(|id| ids.contains(&id)).collect()
+    }
+
+    #[test]
+    fn capture_is_limited_to_the_candidates() {
+        let nodes = two_clusters();
+        let mut rng = rng_from_seed(1);
+        assert_eq!(
+            capture_nodes(&nodes, CaptureTarget::Gateways, 10, &mut rng),
+            captured(&[0, 5])
+        );
+        let some = capture_nodes(&nodes, CaptureTarget::Nodes, 2, &mut rng);
+        assert_eq!(some.iter().filter(|&&captured| captured).count(), 2);
+        assert!(!some[0] && !some[5]);
+    }
+
+    #[test]
+    fn keys_fall_only_once_the_threshold_is_exceeded() {
+        let nodes = two_clusters();
+        let pair = captured(&[1, 4]);
+        // Only 0-2 and 2-3 remain between uncaptured nodes
+        let unbroken = Exposure {
+            total: 2,
+            exposed: 0,
+        };
+        assert_eq!(threshold_exposure(&nodes, &pair, None), unbroken);
+        assert_eq!(threshold_exposure(&nodes, &pair, Some(2)), unbroken);
+        let broken = threshold_exposure(&nodes, &pair, Some(1));
+        assert_eq!(
+            broken,
+            Exposure {
+                total: 2,
+                exposed: 2,
+            }
+        );
+        assert_eq!(broken.fraction(), 1.0);
+        // Without a threshold no number of captures breaks the scheme
+        assert_eq!(
+            threshold_exposure(&nodes, &captured(&[0, 1, 2, 5]), None).exposed,
+            0
+        );
+    }
+
+    #[test]
+    fn a_captured_gateway_exposes_its_whole_cluster() {
+        let nodes = two_clusters();
+        assert_eq!(
+            group_exposure(&nodes, &captured(&[0])),
+            Exposure {
+                total: 4,
+                exposed: 3,
+            }
+        );
+        // A captured member exposes the others of its cluster only
+        assert_eq!(
+            group_exposure(&nodes, &captured(&[1])),
+            Exposure {
+                total: 3,
+                exposed: 2,
+            }
+        );
+        assert_eq!(group_exposure(&nodes, &captured(&[4])).exposed, 0);
+        assert_eq!(Exposure::default().fraction(), 0.0);
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::{NetworkConfig, SimulationConfig},
+    experiment::{ExperimentSpec, Metric},
+    stats::Summary,
+};
+
+// Quote a CSV field when it holds a comma, a quote or a line break, doubling its quotes
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// One CSV record, every field quoted as needed
+pub fn write_csv_row<W: Write>(writer: &mut W, fields: &[&dyn Display]) -> io::Result<()> {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| csv_field(&field.to_string()))
+        .collect();
+    writeln!(writer, "{}", fields.join(","))
+}
+
+// Statistics of a metric for one scheme at one sweep value
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Measurement {
+    pub scheme: &'static str,
+    pub sweep_parameter: String,
+    pub sweep_value: f64,
+    pub metric: Metric,
+    #[serde(flatten)]
+    pub summary: Summary,
+    // Iterations where the metric had no value on the topology, left out of the summary
+    pub missing: usize,
+    // Value of every iteration in order, `None` where it had none. Only kept when the spec
+    // asks for it.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub samples: Vec<Option<f64>>,
+}
+
+// Everything needed to reproduce a run
+#[derive(Debug, Clone, Serialize)]
+pub struct RunMetadata {
+    pub spec: ExperimentSpec,
+    pub config: SimulationConfig,
+    pub network: NetworkConfig,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExperimentResults {
+    pub metadata: RunMetadata,
+    pub rows: Vec<Measurement>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    Json,
+}
+
+impl ExperimentResults {
+    pub fn write<W: Write>(&self, writer: &mut W, format: OutputFormat) -> io::Result<()> {
+        match format {
+            OutputFormat::Csv => self.write_csv(writer),
+            OutputFormat::Json => self.write_json(writer),
+        }
+    }
+
+    // One row per scheme, sweep value and metric, preceded by the metadata as `#` comments
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        // Go through the serialized text so f32 values are written in their shortest form
+        for (section, text) in [
+            ("spec", serde_json::to_string(&self.metadata.spec)?),
+            ("config", serde_json::to_string(&self.metadata.config)?),
+            ("network", serde_json::to_string(&self.metadata.network)?),
+        ] {
+            if let serde_json::Value::Object(fields) = serde_json::from_str(&text)? {
+                for (key, value) in fields.iter() {
+                    writeln!(writer, "# {}.{}={}", section, key, value)?;
+                }
+            }
+        }
+        writeln!(
+            writer,
+            "scheme,sweep_parameter,sweep_value,metric,count,missing,mean,std_dev,min,max,median,ci95_low,ci95_high"
+        )?;
+        for row in self.rows.iter() {
+            let summary = &row.summary;
+            write_csv_row(
+                writer,
+                &[
+                    &row.scheme,
+                    &row.sweep_parameter,
+                    &row.sweep_value,
+                    &row.metric.name(),
+                    &summary.count,
+                    &row.missing,
+                    &summary.mean,
+                    &summary.std_dev,
+                    &summary.min,
+                    &summary.max,
+                    &summary.median,
+                    &summary.ci95_low,
+                    &summary.ci95_high,
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    // Raw per-iteration values, one row per scheme, sweep value, metric and iteration. The value
+    // is left empty for an iteration where the metric had none.
+    pub fn write_samples_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "scheme,sweep_parameter,sweep_value,metric,iteration,value"
+        )?;
+        for row in self.rows.iter() {
+            for (iteration, value) in row.samples.iter().enumerate() {
+                let value = value.map(|value| value.to_string()).unwrap_or_default();
+                write_csv_row(
+                    writer,
+                    &[
+                        &row.scheme,
+                        &row.sweep_parameter,
+                        &row.sweep_value,
+                        &row.metric.name(),
+                        &iteration,
+                        &value,
+                    ],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut *writer, self)?;
+        writeln!(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_field("bytes"), "bytes");
+        assert_eq!(csv_field("[0, 1]"), "\"[0, 1]\"");
+        assert_eq!(csv_field("one of \"a\""), "\"one of \"\"a\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    fn results() -> ExperimentResults {
+        let spec = ExperimentSpec::from_toml(
+            r#"
+            name = "a, \"quoted\" name"
+            iterations = 2
+            metrics = ["key_storage_bytes"]
+
+            [sweep]
+            parameter = "MAC_SIZE"
+            start = 8
+            end = 8
+            step = 1
+            "#,
+        )
+        .unwrap();
+        let network = NetworkConfig::from_lookup(|key| match key {
+            "NUMBER_OF_NODES" | "NUMBER_OF_GATEWAY_MEMBERS" => Some("10".to_string()),
+            "NUMBER_OF_GATEWAYS" | "NUMBER_OF_MIN_POSSIBLE_NEIGHBORS" => Some("1".to_string()),
+            "NUMBER_OF_MAX_POSSIBLE_NEIGHBORS" => Some("3".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        ExperimentResults {
+            metadata: RunMetadata {
+                spec,
+                config: SimulationConfig::default(),
+                network,
+            },
+            rows: vec![Measurement {
+                scheme: "pool, \"hash\"",
+                sweep_parameter: "MAC_SIZE".to_string(),
+                sweep_value: 8.0,
+                metric: Metric::KeyStorageBytes,
+                summary: Summary::from_samples(&[1.0, 3.0]),
+                missing: 1,
+                samples: vec![Some(1.0), None, Some(3.0)],
+            }],
+        }
+    }
+
+    fn read(text: &[u8]) -> (csv::StringRecord, Vec<csv::StringRecord>) {
+        let mut reader = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .from_reader(text);
+        let header = reader.headers().unwrap().clone();
+        let records = reader.records().map(|record| record.unwrap()).collect();
+        (header, records)
+    }
+
+    #[test]
+    fn results_read_back_through_a_csv_reader() {
+        let mut text: Vec<u8> = Vec::new();
+        results().write_csv(&mut text).unwrap();
+        let (header, records) = read(&text);
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.len(), header.len());
+        let field = |name: &str| &record[header.iter().position(|column| column == name).unwrap()];
+        assert_eq!(field("scheme"), "pool, \"hash\"");
+        assert_eq!(field("metric"), "key_storage_bytes");
+        assert_eq!(field("count"), "2");
+        assert_eq!(field("missing"), "1");
+        assert_eq!(field("mean"), "2");
+    }
+
+    #[test]
+    fn samples_read_back_through_a_csv_reader() {
+        let mut text: Vec<u8> = Vec::new();
+        results().write_samples_csv(&mut text).unwrap();
+        let (header, records) = read(&text);
+        assert_eq!(header.len(), 6);
+        let values: Vec<(&str, &str, &str)> = records
+            .iter()
+            .map(|record| (&record[0], &record[4], &record[5]))
+            .collect();
+        let scheme = "pool, \"hash\"";
+        assert_eq!(
+            values,
+            vec![(scheme, "0", "1"), (scheme, "1", ""), (scheme, "2", "3")]
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::VecDeque;
+
+use serde::Serialize;
+
+use crate::{
+    config::SimulationConfig,
+    ledger::EnergyLedger,
+    membership::MembershipCost,
+    methods::{KeyManagementScheme, KeyMaterial},
+    radio::broadcast_distance,
+    topology::Departure,
+    NodeType, NodesVec,
+};
+
+// A revoked node and how far its revocation spread
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revocation {
+    pub node: usize,
+    pub departure: Departure,
+    // Gateway that issued the revocation, `None` when no gateway is left
+    pub issuer: Option<usize>,
+    // Nodes the revocation reached, indexed by node id
+    pub reached: Vec<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RevocationReport {
+    pub scheme: &'static str,
+    pub node: usize,
+    pub issuer: Option<usize>,
+    pub reached_nodes: usize,
+    // Spreading the revocation through the network
+    pub flood: MembershipCost,
+    // Erasing and replacing the keys the revoked node held
+    pub rekey: MembershipCost,
+    // Active nodes the revoked node can still talk to
+    pub still_trusting: Vec<usize>,
+}
+
+impl RevocationReport {
+    pub fn energy(&self) -> f64 {
+        self.flood.energy + self.rekey.energy
+    }
+
+    pub fn bytes_sent(&self) -> f64 {
+        self.flood.bytes_sent + self.rekey.bytes_sent
+    }
+
+    pub fn is_isolated(&self) -> bool {
+        self.still_trusting.is_empty()
+    }
+}
+
+// The gateway of the revoked node issues the revocation, any other gateway when it has none or
+// is the one revoked
+fn issuer(nodes: &NodesVec, departure: &Departure) -> Option<usize> {
+    match departure.gateway {
+        Some(gateway) if gateway != departure.node && nodes[gateway].is_active() => Some(gateway),
+        _ => nodes
+            .iter()
+            .find(|node| node.kind == NodeType::Gateway && node.is_active())
+            .map(|node| node.id),
+    }
+}
+
+// Flood the revocation from the issuer, which tags it: every node it reaches verifies the tag
+// and broadcasts it once, hearing the copy of each of its neighbors
+fn record_flood(
+    nodes: &NodesVec,
+    config: &SimulationConfig,
+    issuer: Option<usize>,
+    message_size: f32,
+    ledger: &mut EnergyLedger,
+) -> Vec<bool> {
+    let mut reached: Vec<bool> = vec![false; nodes.len()];
+    let Some(issuer) = issuer else {
+        return reached;
+    };
+    let mut queue: VecDeque<usize> = VecDeque::new();
+    reached[issuer] = true;
+    queue.push_back(issuer);
+    while let Some(node) = queue.pop_front() {
+        ledger.hashes(node, 1, config);
+        ledger.transmit(
+            node,
+            message_size,
+            broadcast_distance(nodes, node, config),
+            config,
+        );
+        for &neighbor in nodes[node].neighbors.iter() {
+            ledger.receive(neighbor, message_size, config);
+            if !reached[neighbor] {
+                reached[neighbor] = true;
+                queue.push_back(neighbor);
+            }
+        }
+    }
+    reached
+}
+
+// Revoke the node: it is taken out of the network, a gateway floods its id and the scheme
+// replaces the keys it held, `material` being what the scheme predistributed on `nodes`. `nodes`
+// is left unchanged, the revoked network is returned with the report.
+pub fn revoke(
+    scheme: &dyn KeyManagementScheme,
+    nodes: &NodesVec,
+    config: &SimulationConfig,
+    mac_size: u32,
+    node: usize,
+    material: &KeyMaterial,
+) -> (NodesVec, RevocationReport) {
+    let mut network = nodes.clone();
+    let departure = network.revoke_node(node);
+    let issuer = issuer(&network, &departure);
+
+    let mut flood = EnergyLedger::for_network(&network);
+    let reached = record_flood(
+        &network,
+        config,
+        issuer,
+        scheme.revocation_message_size(config, mac_size),
+        &mut flood,
+    );
+    let revocation = Revocation {
+        node,
+        departure,
+        issuer,
+        reached,
+    };
+
+    let mut rekey = EnergyLedger::for_network(&network);
+    let still_trusting = scheme.record_revocation(
+        &network,
+        config,
+        mac_size,
+        &revocation,
+        material,
+        &mut rekey,
+    );
+    let report = RevocationReport {
+        scheme: scheme.name(),
+        node,
+        issuer,
+        reached_nodes: revocation
+            .reached
+            .iter()
+            .filter(|&&reached| reached)
+            .count(),
+        flood: MembershipCost::from_ledger(&flood),
+        rekey: MembershipCost::from_ledger(&rekey),
+        still_trusting,
+    };
+    (network, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        methods::{all_schemes, matrix::Matrix, pool_hash::PoolHash},
+        rng::rng_from_seed,
+        topology::{assign_gateway_clusters, initialize_network_from_seed},
+        Node, NodeState,
+    };
+
+    fn network(seed: u64) -> NodesVec {
+        let mut nodes = initialize_network_from_seed(60, 6, 3, 8, seed);
+        assign_gateway_clusters(&mut nodes, 10, &mut rng_from_seed(seed));
+        nodes
+    }
+
+    // Gateway 0 with members 1 and 2, and the pair 3-4 out of its reach
+    fn split() -> NodesVec {
+        let mut nodes = NodesVec::new();
+        nodes.push(Node::new(0, NodeType::Gateway, vec![1, 2], 2));
+        for (id, neighbors) in [(1, vec![0, 2]), (2, vec![0, 1])] {
+            let mut member = Node::new(id, NodeType::Constrained, neighbors, 2);
+            member.gateway = Some(0);
+            nodes.push(member);
+        }
+        nodes.push(Node::new(3, NodeType::Constrained, vec![4], 1));
+        nodes.push(Node::new(4, NodeType::Constrained, vec![3], 1));
+        nodes
+    }
+
+    #[test]
+    fn the_revoked_node_ends_up_isolated() {
+        let config = SimulationConfig::default();
+        let nodes = network(5);
+        let node = nodes
+            .iter()
+            .find(|node| node.gateway.is_some())
+            .expect("some node has a cluster")
+            .id;
+        for scheme in all_schemes() {
+            let material = scheme.predistribute(&nodes, &config, &mut rng_from_seed(5));
+            let (revoked, report) = revoke(scheme.as_ref(), &nodes, &config, 8, node, &material);
+            assert_eq!(revoked[node].state, NodeState::Revoked);
+            assert!(revoked[node].neighbors.is_empty());
+            assert_eq!(revoked[node].gateway, None);
+            assert!(revoked.iter().all(|other| !other.neighbors.contains(&node)));
+            assert_eq!(report.issuer, nodes[node].gateway);
+            assert!(report.is_isolated(), "{}", scheme.name());
+        }
+    }
+
+    #[test]
+    fn reached_nodes_erase_the_revoked_ring() {
+        let config = SimulationConfig::default();
+        let nodes = network(6);
+        let material = PoolHash.predistribute(&nodes, &config, &mut rng_from_seed(6));
+        let rings = &material.key_rings;
+        // A member sharing pool keys with other nodes, so there is something to erase
+        let node = nodes
+            .iter()
+            .filter(|node| node.gateway.is_some())
+            .find(|node| {
+                nodes.iter().any(|other| {
+                    other.id != node.id
+                        && rings[other.id]
+                            .iter()
+                            .any(|key| rings[node.id].contains(key))
+                })
+            })
+            .expect("rings overlap")
+            .id;
+        let (revoked, report) = revoke(&PoolHash, &nodes, &config, 8, node, &material);
+        // Every active node was reached, none still holds a key of the revoked ring
+        let active = revoked.iter().filter(|other| other.is_active()).count();
+        assert_eq!(report.reached_nodes, active);
+        assert!(report.is_isolated());
+    }
+
+    #[test]
+    fn nodes_the_revocation_misses_are_reported() {
+        let config = SimulationConfig::default();
+        let nodes = split();
+        let (_, report) = revoke(&Matrix, &nodes, &config, 8, 1, &KeyMaterial::default());
+        assert_eq!(report.issuer, Some(0));
+        assert_eq!(report.reached_nodes, 2);
+        assert_eq!(report.still_trusting, vec![3, 4]);
+        assert!(!report.is_isolated());
+
+        // Only node 3 shares a pool key with the revoked node
+        let material = KeyMaterial {
+            key_rings: vec![vec![1], vec![5, 6], vec![6], vec![5], vec![7]],
+        };
+        let (_, report) = revoke(&PoolHash, &nodes, &config, 8, 1, &material);
+        assert_eq!(report.still_trusting, vec![3]);
+    }
+
+    #[test]
+    fn nothing_is_reached_without_a_gateway() {
+        let config = SimulationConfig::default();
+        let (_, report) = revoke(&Matrix, &split(), &config, 8, 0, &KeyMaterial::default());
+        assert_eq!(report.issuer, None);
+        assert_eq!(report.reached_nodes, 0);
+        assert_eq!(report.flood, MembershipCost::default());
+        assert_eq!(report.still_trusting, vec![1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+
+use crate::{NodeType, NodesVec};
+
+// Shortest paths from every gateway to the members of its cluster over the neighbor graph.
+// Each gateway grows a breadth-first collection tree through constrained nodes, so a member
+// may be relayed by nodes of another cluster when its own cluster is not connected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingTable {
+    // Nodes forwarding the traffic of each member, ordered from the gateway to the member.
+    // `None` for gateways, unassigned nodes and members their gateway cannot reach.
+    relays: Vec<Option<Vec<usize>>>,
+}
+
+impl RoutingTable {
+    pub fn to_gateways(nodes: &NodesVec) -> Self {
+        let mut relays: Vec<Option<Vec<usize>>> = vec![None; nodes.len()];
+        let mut parent: Vec<usize> = vec![usize::MAX; nodes.len()];
+        // Id of the last gateway whose search visited the node, avoids clearing `parent`
+        let mut visited_by: Vec<usize> = vec![usize::MAX; nodes.len()];
+        let mut queue: VecDeque<usize> = VecDeque::new();
+
+        for cluster in nodes.clusters() {
+            let gateway = cluster.gateway;
+            let mut remaining = cluster.members.len();
+            visited_by[gateway] = gateway;
+            queue.clear();
+            queue.push_back(gateway);
+            while remaining > 0 {
+                let Some(current) = queue.pop_front() else {
+                    break;
+                };
+                for &neighbor in nodes[current].neighbors.iter() {
+                    if visited_by[neighbor] == gateway || nodes[neighbor].kind == NodeType::Gateway
+                    {
+                        continue;
+                    }
+                    visited_by[neighbor] = gateway;
+                    parent[neighbor] = current;
+                    if nodes[neighbor].gateway == Some(gateway) {
+                        remaining -= 1;
+                    }
+                    queue.push_back(neighbor);
+                }
+            }
+
+            for &member in cluster.members.iter() {
+                if visited_by[member] != gateway {
+                    continue;
+                }
+                let mut path: Vec<usize> = Vec::new();
+                let mut current = parent[member];
+                while current != gateway {
+                    path.push(current);
+                    current = parent[current];
+                }
+                path.reverse();
+                relays[member] = Some(path);
+            }
+        }
+
+        Self { relays }
+    }
+
+    // Number of transmissions between the gateway of the node and the node
+    pub fn hops(&self, node: usize) -> Option<usize> {
+        self.relays[node].as_ref().map(|relays| relays.len() + 1)
+    }
+
+    pub fn relays(&self, node: usize) -> Option<&[usize]> {
+        self.relays[node].as_deref()
+    }
+
+    pub fn is_reachable(&self, node: usize) -> bool {
+        self.relays[node].is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Node;
+
+    // Gateway 0 with members 1, 3 and 4, gateway 5 with members 2 and 6. Node 2 relays for the
+    // cluster of 0 and node 4 has no link at all.
+    fn network() -> NodesVec {
+        let links: [&[usize]; 7] = [&[1], &[0, 2], &[1, 3, 6], &[2], &[], &[6], &[5, 2]];
+        let gateways = [None, Some(0), Some(5), Some(0), Some(0), None, Some(5)];
+        let mut nodes = NodesVec::new();
+        for (id, neighbors) in links.iter().enumerate() {
+            let kind = if gateways[id].is_none() {
+                NodeType::Gateway
+            } else {
+                NodeType::Constrained
+            };
+            let mut node = Node::new(id, kind, neighbors.to_vec(), neighbors.len());
+            node.gateway = gateways[id];
+            nodes.push(node);
+        }
+        nodes
+    }
+
+    #[test]
+    fn members_are_reached_over_shortest_paths() {
+        let routes = RoutingTable::to_gateways(&network());
+        let hops: Vec<Option<usize>> = (0..7).map(|node| routes.hops(node)).collect();
+        assert_eq!(
+            hops,
+            vec![None, Some(1), Some(2), Some(3), None, None, Some(1)]
+        );
+        assert_eq!(routes.relays(1), Some(&[][..]));
+        // Node 2 of the other cluster relays for node 3
+        assert_eq!(routes.relays(3), Some(&[1, 2][..]));
+        assert_eq!(routes.relays(2), Some(&[6][..]));
+    }
+
+    #[test]
+    fn isolated_members_and_gateways_have_no_route() {
+        let routes = RoutingTable::to_gateways(&network());
+        assert!(!routes.is_reachable(4));
+        assert_eq!(routes.relays(4), None);
+        assert!(!routes.is_reachable(0));
+        assert!(!routes.is_reachable(5));
+    }
+
+    #[test]
+    fn routes_do_not_cross_other_gateways() {
+        let mut nodes = network();
+        // Gateway 5 now only links to gateway 0, its members are out of reach
+        nodes[5].neighbors = vec![0];
+        nodes[0].neighbors.push(5);
+        nodes[6].neighbors.retain(|&neighbor| neighbor != 5);
+        let routes = RoutingTable::to_gateways(&nodes);
+        assert!(!routes.is_reachable(6));
+        assert!(!routes.is_reachable(2));
+        assert_eq!(routes.hops(3), Some(3));
+    }
+}
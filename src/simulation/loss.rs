@@ -0,0 +1,89 @@
+use std::{fmt, str::FromStr};
+
+use serde::Serialize;
+
+// Only the protocol simulation draws frame losses and retransmits, the closed-form metrics
+// assume every frame arrives and are rejected under any other model than `None`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LossModel {
+    // Every frame arrives, no acknowledgements are exchanged
+    None,
+    // Every frame is lost with LINK_LOSS_PROBABILITY
+    Constant,
+    // Link quality degrades with distance, on top of LINK_LOSS_PROBABILITY
+    Distance,
+}
+
+impl LossModel {
+    // Probability that a frame sent over a link of `distance` meters does not arrive. In the
+    // distance model the frames that survive the base loss are lost with d² / (d² + d½²), so
+    // half of them are lost at LOSS_HALF_DISTANCE.
+    pub fn loss_probability(&self, base: f64, half_distance: f64, distance: f64) -> f64 {
+        match self {
+            LossModel::None => 0.0,
+            LossModel::Constant => base,
+            LossModel::Distance => {
+                let squared = distance * distance;
+                let fading = squared / (squared + half_distance * half_distance);
+                base + (1.0 - base) * fading
+            }
+        }
+    }
+}
+
+impl FromStr for LossModel {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "none" => Ok(LossModel::None),
+            "constant" => Ok(LossModel::Constant),
+            "distance" => Ok(LossModel::Distance),
+            _ => Err(format!("unknown loss model: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for LossModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LossModel::None => write!(f, "none"),
+            LossModel::Constant => write!(f, "constant"),
+            LossModel::Distance => write!(f, "distance"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loss_grows_with_distance_on_top_of_the_base() {
+        assert_eq!(LossModel::None.loss_probability(0.5, 30.0, 100.0), 0.0);
+        assert_eq!(
+            LossModel::Constant.loss_probability(0.25, 30.0, 100.0),
+            0.25
+        );
+        let distance = LossModel::Distance;
+        assert_eq!(distance.loss_probability(0.2, 30.0, 0.0), 0.2);
+        assert!((distance.loss_probability(0.0, 30.0, 30.0) - 0.5).abs() < 1e-12);
+        assert!((distance.loss_probability(0.2, 30.0, 30.0) - 0.6).abs() < 1e-12);
+        let mut previous = 0.0;
+        for meters in [1.0, 10.0, 30.0, 100.0, 1000.0] {
+            let probability = distance.loss_probability(0.1, 30.0, meters);
+            assert!(probability > previous && probability < 1.0);
+            previous = probability;
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for model in [LossModel::None, LossModel::Constant, LossModel::Distance] {
+            assert_eq!(model.to_string().parse::<LossModel>(), Ok(model));
+        }
+        assert_eq!(" Distance ".parse::<LossModel>(), Ok(LossModel::Distance));
+        assert!("fading".parse::<LossModel>().is_err());
+    }
+}
@@ -0,0 +1,757 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+};
+
+use rand::{Rng, RngCore};
+use serde::Serialize;
+
+use crate::{
+    config::SimulationConfig,
+    ledger::EnergyLedger,
+    methods::pairwise_participants,
+    radio::{broadcast_distance, link_distance},
+    NodesVec,
+};
+
+mod loss;
+mod transport;
+
+pub use loss::LossModel;
+pub use transport::{KeyTransport, PairwiseProgress};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    Hello,
+    Reply,
+    KeyIdentifiers,
+    PathKey,
+    GroupKey,
+    Acknowledgement,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub kind: MessageKind,
+    // Node transmitting this hop
+    pub from: usize,
+    // `None` for a broadcast to every neighbor
+    pub to: Option<usize>,
+    // End points of a message relayed over several hops
+    pub origin: usize,
+    pub target: Option<usize>,
+    pub bytes: f32,
+    // Time the origin handed the message to its radio
+    pub created_at: f64,
+}
+
+impl Message {
+    pub fn broadcast(kind: MessageKind, from: usize, bytes: f32) -> Self {
+        Self {
+            kind,
+            from,
+            to: None,
+            origin: from,
+            target: None,
+            bytes,
+            created_at: 0.0,
+        }
+    }
+
+    // Message for `target` whose first hop is `to`
+    pub fn routed(kind: MessageKind, from: usize, to: usize, target: usize, bytes: f32) -> Self {
+        Self {
+            kind,
+            from,
+            to: Some(to),
+            origin: from,
+            target: Some(target),
+            bytes,
+            created_at: 0.0,
+        }
+    }
+
+    pub fn unicast(kind: MessageKind, from: usize, to: usize, bytes: f32) -> Self {
+        Self::routed(kind, from, to, to, bytes)
+    }
+
+    // Next hop of a relayed message, its end points and creation time unchanged
+    pub fn relay(&self, from: usize, to: usize) -> Self {
+        Self {
+            from,
+            to: Some(to),
+            ..self.clone()
+        }
+    }
+
+    pub fn is_for(&self, node: usize) -> bool {
+        self.target.is_none_or(|target| target == node)
+    }
+}
+
+#[derive(Debug)]
+enum EventKind {
+    // The last bit of a frame reached the radio of the node
+    Delivery {
+        node: usize,
+        message: Message,
+        transmission: u64,
+    },
+    // The link-layer acknowledgement of `receiver` reached the sender of the frame
+    Acknowledgement {
+        node: usize,
+        receiver: usize,
+        transmission: u64,
+    },
+    // No acknowledgement came back from some receiver after the attempt
+    Timeout {
+        transmission: u64,
+        attempt: u32,
+    },
+    // The node finished handling the message at the head of its inbox
+    Processing {
+        node: usize,
+    },
+}
+
+#[derive(Debug)]
+struct Event {
+    time: f64,
+    // Ties are broken in scheduling order so runs are reproducible
+    sequence: u64,
+    kind: EventKind,
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Event {}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed so the binary heap pops the earliest event first
+impl Ord for Event {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .time
+            .total_cmp(&self.time)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+// Behaviour of every node during one keying round. Handlers run when the node starts and once
+// it has processed a message from its inbox, they act through the engine.
+pub trait Protocol {
+    fn start(&mut self, node: usize, engine: &mut Engine);
+
+    fn receive(&mut self, node: usize, message: &Message, engine: &mut Engine);
+
+    // Links whose two ends did not both settle their pairwise key by the end of the run
+    fn unsettled_links(&self) -> usize;
+}
+
+// Frame waiting for the acknowledgements of its receivers
+#[derive(Debug)]
+struct Transmission {
+    message: Message,
+    awaiting: Vec<usize>,
+    attempts: u32,
+}
+
+// Event queue, simulated clock and per-node radios and inboxes
+pub struct Engine<'a> {
+    nodes: &'a NodesVec,
+    config: &'a SimulationConfig,
+    rng: &'a mut dyn RngCore,
+    now: f64,
+    sequence: u64,
+    queue: BinaryHeap<Event>,
+    inboxes: Vec<VecDeque<Message>>,
+    // Whether a processing event is pending for the node
+    processing: Vec<bool>,
+    // A radio sends one message at a time
+    radio_free_at: Vec<f64>,
+    // Frames are acknowledged and retransmitted only when they may be lost
+    arq: bool,
+    next_transmission: u64,
+    transmissions: HashMap<u64, Transmission>,
+    // Frames already handed to the inbox of each node, so retransmissions are not handled twice
+    received: HashSet<(usize, u64)>,
+    frames_sent: usize,
+    retransmissions: usize,
+    lost_frames: usize,
+    undelivered: usize,
+    ledger: EnergyLedger,
+    started: Vec<bool>,
+    completed_at: Vec<Option<f64>>,
+    messages_sent: usize,
+    bytes_sent: f32,
+    bytes_received: f32,
+    delivered: usize,
+    latency_total: f64,
+    latency_max: f64,
+}
+
+impl<'a> Engine<'a> {
+    pub fn new(
+        nodes: &'a NodesVec,
+        config: &'a SimulationConfig,
+        rng: &'a mut dyn RngCore,
+    ) -> Self {
+        Self {
+            nodes,
+            config,
+            rng,
+            now: 0.0,
+            sequence: 0,
+            queue: BinaryHeap::new(),
+            inboxes: vec![VecDeque::new(); nodes.len()],
+            processing: vec![false; nodes.len()],
+            radio_free_at: vec![0.0; nodes.len()],
+            arq: config.loss_model != LossModel::None,
+            next_transmission: 0,
+            transmissions: HashMap::new(),
+            received: HashSet::new(),
+            frames_sent: 0,
+            retransmissions: 0,
+            lost_frames: 0,
+            undelivered: 0,
+            ledger: EnergyLedger::for_network(nodes),
+            started: vec![false; nodes.len()],
+            completed_at: vec![None; nodes.len()],
+            messages_sent: 0,
+            bytes_sent: 0.0,
+            bytes_received: 0.0,
+            delivered: 0,
+            latency_total: 0.0,
+            latency_max: 0.0,
+        }
+    }
+
+    pub fn now(&self) -> f64 {
+        self.now
+    }
+
+    pub fn nodes(&self) -> &'a NodesVec {
+        self.nodes
+    }
+
+    pub fn config(&self) -> &'a SimulationConfig {
+        self.config
+    }
+
+    pub fn ledger(&mut self) -> &mut EnergyLedger {
+        &mut self.ledger
+    }
+
+    fn schedule(&mut self, time: f64, kind: EventKind) {
+        self.sequence += 1;
+        self.queue.push(Event {
+            time,
+            sequence: self.sequence,
+            kind,
+        });
+    }
+
+    fn airtime(&self, bytes: f32) -> f64 {
+        bytes as f64 * 8.0 / self.config.link_data_rate as f64
+    }
+
+    // Whether a frame from `from` to `to` gets lost, no random draw without a loss model
+    fn is_lost(&mut self, from: usize, to: usize) -> bool {
+        if !self.arq {
+            return false;
+        }
+        let config = self.config;
+        let probability = config.loss_model.loss_probability(
+            config.link_loss_probability as f64,
+            config.loss_half_distance as f64,
+            link_distance(self.nodes, from, to, config) as f64,
+        );
+        let lost = self.rng.gen::<f64>() < probability;
+        if lost {
+            self.lost_frames += 1;
+        }
+        lost
+    }
+
+    // Queue the message on the radio of its sender. With a loss model every receiver
+    // acknowledges the frame and the sender retransmits it until all did or the retry budget is
+    // spent.
+    pub fn send(&mut self, mut message: Message) {
+        let from = message.from;
+        // Relayed messages keep the creation time of their first hop
+        if message.origin == from {
+            message.created_at = self.now;
+        }
+        self.messages_sent += 1;
+        let transmission = self.next_transmission;
+        self.next_transmission += 1;
+        if self.arq {
+            let awaiting = match message.to {
+                Some(to) => vec![to],
+                None => self.nodes[from].neighbors.clone(),
+            };
+            self.transmissions.insert(
+                transmission,
+                Transmission {
+                    message: message.clone(),
+                    awaiting,
+                    attempts: 0,
+                },
+            );
+        }
+        self.transmit(message, transmission, 0);
+    }
+
+    // One attempt at sending the frame, after a random backoff once the radio is free. Every
+    // node in range hears a broadcast again when it is retransmitted.
+    fn transmit(&mut self, message: Message, transmission: u64, attempt: u32) {
+        let config = self.config;
+        let from = message.from;
+        let backoff = self.rng.gen::<f64>() * config.mac_backoff as f64;
+        let start = (self.now + backoff).max(self.radio_free_at[from]);
+        let arrival = start + self.airtime(message.bytes);
+        self.radio_free_at[from] = arrival;
+        self.frames_sent += 1;
+        self.bytes_sent += message.bytes;
+
+        let (receivers, distance) = match message.to {
+            Some(to) => (vec![to], link_distance(self.nodes, from, to, config)),
+            None => (
+                self.nodes[from].neighbors.clone(),
+                broadcast_distance(self.nodes, from, config),
+            ),
+        };
+        self.ledger.transmit(from, message.bytes, distance, config);
+        for receiver in receivers {
+            if !self.is_lost(from, receiver) {
+                self.schedule(
+                    arrival,
+                    EventKind::Delivery {
+                        node: receiver,
+                        message: message.clone(),
+                        transmission,
+                    },
+                );
+            }
+        }
+        if self.arq {
+            self.schedule(
+                arrival + config.ack_timeout as f64,
+                EventKind::Timeout {
+                    transmission,
+                    attempt,
+                },
+            );
+        }
+    }
+
+    // Link-layer acknowledgement sent right away, without waiting for the radio
+    fn acknowledge(&mut self, node: usize, sender: usize, transmission: u64) {
+        let config = self.config;
+        let bytes = config.link_ack_size as f32;
+        self.ledger.transmit(
+            node,
+            bytes,
+            link_distance(self.nodes, node, sender, config),
+            config,
+        );
+        self.bytes_sent += bytes;
+        if !self.is_lost(node, sender) {
+            self.schedule(
+                self.now + self.airtime(bytes),
+                EventKind::Acknowledgement {
+                    node: sender,
+                    receiver: node,
+                    transmission,
+                },
+            );
+        }
+    }
+
+    fn acknowledged(&mut self, node: usize, receiver: usize, transmission: u64) {
+        let bytes = self.config.link_ack_size as f32;
+        self.ledger.receive(node, bytes, self.config);
+        self.bytes_received += bytes;
+        if let Some(pending) = self.transmissions.get_mut(&transmission) {
+            pending.awaiting.retain(|&awaiting| awaiting != receiver);
+            if pending.awaiting.is_empty() {
+                self.transmissions.remove(&transmission);
+            }
+        }
+    }
+
+    fn timeout(&mut self, transmission: u64, attempt: u32) {
+        let Some(pending) = self.transmissions.get_mut(&transmission) else {
+            return;
+        };
+        // A later attempt is already on its way
+        if pending.attempts != attempt {
+            return;
+        }
+        if attempt < self.config.max_retransmissions {
+            pending.attempts += 1;
+            let message = pending.message.clone();
+            self.retransmissions += 1;
+            self.transmit(message, transmission, attempt + 1);
+        } else if let Some(pending) = self.transmissions.remove(&transmission) {
+            self.undelivered += pending.awaiting.len();
+        }
+    }
+
+    // The node holds every key of the round, only the first call counts
+    pub fn complete(&mut self, node: usize) {
+        if self.completed_at[node].is_none() {
+            self.completed_at[node] = Some(self.now);
+        }
+    }
+
+    fn deliver(&mut self, node: usize, message: Message, transmission: u64) {
+        self.ledger.receive(node, message.bytes, self.config);
+        self.bytes_received += message.bytes;
+        if self.arq {
+            self.acknowledge(node, message.from, transmission);
+            if !self.received.insert((node, transmission)) {
+                return;
+            }
+        }
+        self.inboxes[node].push_back(message);
+        if !self.processing[node] {
+            self.processing[node] = true;
+            self.schedule(
+                self.now + self.config.processing_delay as f64,
+                EventKind::Processing { node },
+            );
+        }
+    }
+
+    fn process(&mut self, node: usize, protocol: &mut dyn Protocol) {
+        if let Some(message) = self.inboxes[node].pop_front() {
+            if message.is_for(node) {
+                let latency = self.now - message.created_at;
+                self.delivered += 1;
+                self.latency_total += latency;
+                self.latency_max = self.latency_max.max(latency);
+            }
+            protocol.receive(node, &message, self);
+        }
+        if self.inboxes[node].is_empty() {
+            self.processing[node] = false;
+        } else {
+            self.schedule(
+                self.now + self.config.processing_delay as f64,
+                EventKind::Processing { node },
+            );
+        }
+    }
+
+    // Start every node taking part in the pairwise establishment at time zero and handle events
+    // until the queue runs dry
+    pub fn run(mut self, protocol: &mut dyn Protocol) -> RunReport {
+        let nodes = self.nodes;
+        for node in pairwise_participants(nodes) {
+            self.started[node.id] = true;
+            protocol.start(node.id, &mut self);
+        }
+        let mut events = 0;
+        while let Some(event) = self.queue.pop() {
+            self.now = event.time;
+            events += 1;
+            match event.kind {
+                EventKind::Delivery {
+                    node,
+                    message,
+                    transmission,
+                } => self.deliver(node, message, transmission),
+                EventKind::Acknowledgement {
+                    node,
+                    receiver,
+                    transmission,
+                } => self.acknowledged(node, receiver, transmission),
+                EventKind::Timeout {
+                    transmission,
+                    attempt,
+                } => self.timeout(transmission, attempt),
+                EventKind::Processing { node } => self.process(node, protocol),
+            }
+        }
+
+        let completion_time = self
+            .completed_at
+            .iter()
+            .zip(self.started.iter())
+            .filter(|&(_, &started)| started)
+            .try_fold(0.0_f64, |latest, (time, _)| {
+                time.map(|time| latest.max(time))
+            });
+        RunReport {
+            completion_time,
+            started: self.started,
+            completed_at: self.completed_at,
+            end_time: self.now,
+            events,
+            messages_sent: self.messages_sent,
+            frames_sent: self.frames_sent,
+            retransmissions: self.retransmissions,
+            lost_frames: self.lost_frames,
+            undelivered: self.undelivered,
+            unsettled_links: protocol.unsettled_links(),
+            bytes_sent: self.bytes_sent,
+            bytes_received: self.bytes_received,
+            mean_latency: if self.delivered == 0 {
+                0.0
+            } else {
+                self.latency_total / self.delivered as f64
+            },
+            max_latency: self.latency_max,
+            ledger: self.ledger,
+        }
+    }
+}
+
+// Outcome of one protocol run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunReport {
+    // Time the last started node completed, `None` when some node never did
+    pub completion_time: Option<f64>,
+    // Nodes the round was run on, departed, revoked and isolated nodes are left out
+    pub started: Vec<bool>,
+    pub completed_at: Vec<Option<f64>>,
+    // Time of the last event
+    pub end_time: f64,
+    pub events: usize,
+    pub messages_sent: usize,
+    // Data frames on the air, retransmissions included
+    pub frames_sent: usize,
+    pub retransmissions: usize,
+    // Data frames and acknowledgements lost on some link
+    pub lost_frames: usize,
+    // Receivers of a frame given up after the last retransmission
+    pub undelivered: usize,
+    pub unsettled_links: usize,
+    // Data frames and acknowledgements, retransmissions included
+    pub bytes_sent: f32,
+    pub bytes_received: f32,
+    // From the creation of a message to its handling by the node it was meant for
+    pub mean_latency: f64,
+    pub max_latency: f64,
+    pub ledger: EnergyLedger,
+}
+
+impl RunReport {
+    // Fraction of the started nodes that completed
+    pub fn completed_fraction(&self) -> f64 {
+        let started = self.started.iter().filter(|&&started| started).count();
+        if started == 0 {
+            return 1.0;
+        }
+        let completed = self
+            .completed_at
+            .iter()
+            .zip(self.started.iter())
+            .filter(|&(time, &started)| started && time.is_some())
+            .count();
+        completed as f64 / started as f64
+    }
+}
+
+pub fn simulate(
+    nodes: &NodesVec,
+    config: &SimulationConfig,
+    protocol: &mut dyn Protocol,
+    rng: &mut dyn RngCore,
+) -> RunReport {
+    Engine::new(nodes, config, rng).run(protocol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rng::rng_from_seed, Node, NodeState, NodeType};
+
+    // Every node says hello and settles the link of each hello it handles, except the silent
+    // ones which never say hello
+    struct HelloProtocol {
+        progress: PairwiseProgress,
+        silent: Vec<usize>,
+    }
+
+    impl Protocol for HelloProtocol {
+        fn start(&mut self, node: usize, engine: &mut Engine) {
+            if !self.silent.contains(&node) {
+                engine.send(Message::broadcast(MessageKind::Hello, node, 10.0));
+            }
+            if self.progress.is_done(node) {
+                engine.complete(node);
+            }
+        }
+
+        fn receive(&mut self, node: usize, message: &Message, engine: &mut Engine) {
+            if self.progress.resolve(node, message.from) {
+                engine.complete(node);
+            }
+        }
+
+        fn unsettled_links(&self) -> usize {
+            self.progress.unsettled_links()
+        }
+    }
+
+    // Chain 0-1-2 of constrained nodes
+    fn chain() -> NodesVec {
+        let mut nodes = NodesVec::new();
+        nodes.push(Node::new(0, NodeType::Constrained, vec![1], 2));
+        nodes.push(Node::new(1, NodeType::Constrained, vec![0, 2], 2));
+        nodes.push(Node::new(2, NodeType::Constrained, vec![1], 2));
+        nodes
+    }
+
+    // No backoff, so every time is fixed
+    fn config() -> SimulationConfig {
+        let mut config = SimulationConfig::default();
+        config.mac_backoff = 0.0;
+        config.processing_delay = 0.001;
+        config.link_data_rate = 250_000.0;
+        config
+    }
+
+    fn run(nodes: &NodesVec, silent: Vec<usize>) -> RunReport {
+        run_with(nodes, &config(), silent, 0)
+    }
+
+    fn run_with(
+        nodes: &NodesVec,
+        config: &SimulationConfig,
+        silent: Vec<usize>,
+        seed: u64,
+    ) -> RunReport {
+        let mut protocol = HelloProtocol {
+            progress: PairwiseProgress::new(nodes),
+            silent,
+        };
+        simulate(nodes, config, &mut protocol, &mut rng_from_seed(seed))
+    }
+
+    fn lossy_config(probability: f32) -> SimulationConfig {
+        let mut config = config();
+        config.loss_model = LossModel::Constant;
+        config.link_loss_probability = probability;
+        config.max_retransmissions = 3;
+        config.link_ack_size = 5;
+        config
+    }
+
+    #[test]
+    fn events_are_handled_in_time_then_scheduling_order() {
+        let mut queue = BinaryHeap::new();
+        for (time, sequence) in [(2.0, 1), (1.0, 3), (1.0, 2), (0.5, 4)] {
+            queue.push(Event {
+                time,
+                sequence,
+                kind: EventKind::Processing { node: 0 },
+            });
+        }
+        let order: Vec<u64> = std::iter::from_fn(|| queue.pop())
+            .map(|event| event.sequence)
+            .collect();
+        assert_eq!(order, vec![4, 2, 3, 1]);
+    }
+
+    #[test]
+    fn the_round_completes_once_the_last_hello_is_handled() {
+        let report = run(&chain(), Vec::new());
+        // Every hello is on the air for 80 bits at 250 kbit/s, node 1 handles its two hellos
+        // one after the other
+        let airtime = 80.0 / 250_000.0;
+        let completed: Vec<f64> = report
+            .completed_at
+            .iter()
+            .map(|time| time.expect("every node completes"))
+            .collect();
+        for (node, handled) in [(0, 1.0), (1, 2.0), (2, 1.0)] {
+            assert!((completed[node] - (airtime + handled * 0.001)).abs() < 1e-9);
+        }
+        assert_eq!(report.completion_time, Some(completed[1]));
+        assert_eq!(report.unsettled_links, 0);
+        assert_eq!(report.messages_sent, 3);
+        assert_eq!(report.bytes_sent, 30.0);
+        assert_eq!(report.bytes_received, 40.0);
+        // Four deliveries, each handled once
+        assert_eq!(report.events, 8);
+    }
+
+    #[test]
+    fn a_silent_node_leaves_its_links_unsettled() {
+        let report = run(&chain(), vec![2]);
+        assert_eq!(report.unsettled_links, 1);
+        assert_eq!(report.completion_time, None);
+        assert!(report.completed_at[2].is_some());
+        assert_eq!(report.completed_at[1], None);
+        assert!((report.completed_fraction() - 2.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn only_pairwise_participants_are_started() {
+        let mut nodes = chain();
+        // An isolated node and a revoked one
+        nodes.push(Node::new(3, NodeType::Constrained, Vec::new(), 2));
+        let mut revoked = Node::new(4, NodeType::Constrained, Vec::new(), 2);
+        revoked.state = NodeState::Revoked;
+        nodes.push(revoked);
+        let report = run(&nodes, Vec::new());
+        assert_eq!(report.started, vec![true, true, true, false, false]);
+        assert_eq!(report.completed_at[3], None);
+        assert_eq!(report.messages_sent, 3);
+        assert!(report.completion_time.is_some());
+        assert_eq!(report.completed_fraction(), 1.0);
+    }
+
+    #[test]
+    fn without_losses_every_frame_is_acknowledged_once() {
+        let report = run_with(&chain(), &lossy_config(0.0), Vec::new(), 0);
+        assert_eq!(report.frames_sent, 3);
+        assert_eq!(report.retransmissions, 0);
+        assert_eq!(report.lost_frames, 0);
+        assert_eq!(report.undelivered, 0);
+        assert_eq!(report.unsettled_links, 0);
+        // The hellos and an acknowledgement from each of their four receivers
+        assert_eq!(report.bytes_sent, 30.0 + 4.0 * 5.0);
+        assert_eq!(report.bytes_received, 40.0 + 4.0 * 5.0);
+    }
+
+    #[test]
+    fn frames_are_given_up_after_the_last_retransmission() {
+        let report = run_with(&chain(), &lossy_config(1.0), Vec::new(), 0);
+        // Every hello goes out once and three more times, none of them reaches its receivers
+        assert_eq!(report.messages_sent, 3);
+        assert_eq!(report.frames_sent, 3 * 4);
+        assert_eq!(report.retransmissions, 3 * 3);
+        assert_eq!(report.lost_frames, 4 * 4);
+        assert_eq!(report.undelivered, 4);
+        assert_eq!(report.unsettled_links, 2);
+        assert_eq!(report.completion_time, None);
+        assert_eq!(report.bytes_received, 0.0);
+    }
+
+    #[test]
+    fn losses_are_drawn_from_the_seed() {
+        let config = lossy_config(0.3);
+        let report = run_with(&chain(), &config, Vec::new(), 7);
+        assert_eq!(report, run_with(&chain(), &config, Vec::new(), 7));
+        assert!(report.retransmissions <= 3 * report.messages_sent);
+        assert_eq!(
+            report.frames_sent,
+            report.messages_sent + report.retransmissions
+        );
+        assert!(report.undelivered <= 4);
+    }
+}
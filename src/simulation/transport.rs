@@ -0,0 +1,323 @@
+use std::collections::HashSet;
+
+use crate::{routing::RoutingTable, NodeType, NodesVec};
+
+use super::{Engine, Message, MessageKind};
+
+// Neighbors each node still has to settle a pairwise key with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairwiseProgress {
+    neighbors: Vec<Vec<usize>>,
+    remaining: Vec<usize>,
+    // (node, neighbor) for every neighbor the node settled
+    settled: HashSet<(usize, usize)>,
+}
+
+impl PairwiseProgress {
+    pub fn new(nodes: &NodesVec) -> Self {
+        Self {
+            neighbors: nodes.iter().map(|node| node.neighbors.clone()).collect(),
+            remaining: nodes.iter().map(|node| node.neighbors.len()).collect(),
+            settled: HashSet::new(),
+        }
+    }
+
+    // The node settled its link with the neighbor, true when it was the last one
+    pub fn resolve(&mut self, node: usize, neighbor: usize) -> bool {
+        if !self.settled.insert((node, neighbor)) || self.remaining[node] == 0 {
+            return false;
+        }
+        self.remaining[node] -= 1;
+        self.remaining[node] == 0
+    }
+
+    // Links that one of their ends has not settled
+    pub fn unsettled_links(&self) -> usize {
+        let mut unsettled = 0;
+        for (node, neighbors) in self.neighbors.iter().enumerate() {
+            for &neighbor in neighbors.iter() {
+                if node < neighbor
+                    && !(self.settled.contains(&(node, neighbor))
+                        && self.settled.contains(&(neighbor, node)))
+                {
+                    unsettled += 1;
+                }
+            }
+        }
+        unsettled
+    }
+
+    pub fn is_done(&self, node: usize) -> bool {
+        self.remaining[node] == 0
+    }
+}
+
+// Group key transport shared by matrix and pool_hash, the message-level counterpart of the
+// closed-form one: each gateway encrypts the key of its cluster for every member and sends it
+// along its route, the member decrypts it and sends back an encrypted acknowledgement the gateway
+// decrypts
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyTransport {
+    routes: RoutingTable,
+    key_size: f32,
+    acknowledgement_size: f32,
+    // False when the key is only carried to the members, see `unacknowledged`
+    acknowledged: bool,
+    // Acknowledgements each gateway still waits for
+    awaiting: Vec<usize>,
+    // Members holding the key and gateways acknowledged by every reachable member
+    done: Vec<bool>,
+}
+
+impl KeyTransport {
+    pub fn new(nodes: &NodesVec, key_size: f32, acknowledgement_size: f32) -> Self {
+        let routes = RoutingTable::to_gateways(nodes);
+        let mut awaiting = vec![0; nodes.len()];
+        // Constrained nodes outside every cluster have no group key to wait for
+        let done: Vec<bool> = nodes
+            .iter()
+            .map(|node| node.kind == NodeType::Constrained && node.gateway.is_none())
+            .collect();
+        for cluster in nodes.clusters() {
+            awaiting[cluster.gateway] = cluster
+                .members
+                .iter()
+                .filter(|&&member| routes.is_reachable(member))
+                .count();
+        }
+        Self {
+            routes,
+            key_size,
+            acknowledgement_size,
+            acknowledged: true,
+            awaiting,
+            done,
+        }
+    }
+
+    // Carry the key to the members without encrypting it or waiting for acknowledgements, for
+    // schemes that charge the cryptography of their group key themselves
+    pub fn unacknowledged(nodes: &NodesVec, key_size: f32) -> Self {
+        Self {
+            acknowledged: false,
+            ..Self::new(nodes, key_size, 0.0)
+        }
+    }
+
+    pub fn is_done(&self, node: usize) -> bool {
+        self.done[node]
+    }
+
+    // Gateway, relays then member
+    fn path(&self, nodes: &NodesVec, member: usize) -> Option<Vec<usize>> {
+        let gateway = nodes[member].gateway?;
+        let relays = self.routes.relays(member)?;
+        let mut path: Vec<usize> = Vec::with_capacity(relays.len() + 2);
+        path.push(gateway);
+        path.extend_from_slice(relays);
+        path.push(member);
+        Some(path)
+    }
+
+    // Node after `node` on the path of `member`, walking toward the member or toward the gateway
+    fn next_hop(
+        &self,
+        nodes: &NodesVec,
+        member: usize,
+        node: usize,
+        toward_member: bool,
+    ) -> Option<usize> {
+        let path = self.path(nodes, member)?;
+        let position = path.iter().position(|&hop| hop == node)?;
+        if toward_member {
+            path.get(position + 1).copied()
+        } else {
+            position.checked_sub(1).map(|previous| path[previous])
+        }
+    }
+
+    // Send the key to every reachable member of the cluster of the gateway
+    pub fn start(&mut self, gateway: usize, engine: &mut Engine) {
+        let nodes = engine.nodes();
+        let config = engine.config();
+        for cluster in nodes.clusters() {
+            if cluster.gateway != gateway {
+                continue;
+            }
+            for &member in cluster.members.iter() {
+                if let Some(first_hop) = self.next_hop(nodes, member, gateway, true) {
+                    if self.acknowledged {
+                        engine.ledger().encryptions(gateway, 1, config);
+                    }
+                    engine.send(Message::routed(
+                        MessageKind::GroupKey,
+                        gateway,
+                        first_hop,
+                        member,
+                        self.key_size,
+                    ));
+                }
+            }
+        }
+        self.done[gateway] = !self.acknowledged || self.awaiting[gateway] == 0;
+    }
+
+    // Forward or consume a transport message
+    pub fn receive(&mut self, node: usize, message: &Message, engine: &mut Engine) {
+        let nodes = engine.nodes();
+        let config = engine.config();
+        let Some(target) = message.target else {
+            return;
+        };
+        match message.kind {
+            MessageKind::GroupKey if target == node => {
+                self.done[node] = true;
+                if !self.acknowledged {
+                    return;
+                }
+                engine.ledger().decryptions(node, 1, config);
+                engine.ledger().encryptions(node, 1, config);
+                if let Some(previous) = self.next_hop(nodes, node, node, false) {
+                    engine.send(Message::routed(
+                        MessageKind::Acknowledgement,
+                        node,
+                        previous,
+                        message.origin,
+                        self.acknowledgement_size,
+                    ));
+                }
+            }
+            MessageKind::GroupKey => {
+                if let Some(next) = self.next_hop(nodes, target, node, true) {
+                    engine.send(message.relay(node, next));
+                }
+            }
+            MessageKind::Acknowledgement if target == node && self.awaiting[node] > 0 => {
+                engine.ledger().decryptions(node, 1, config);
+                self.awaiting[node] -= 1;
+                self.done[node] = self.awaiting[node] == 0;
+            }
+            MessageKind::Acknowledgement => {
+                if let Some(previous) = self.next_hop(nodes, message.origin, node, false) {
+                    engine.send(message.relay(node, previous));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::SimulationConfig,
+        rng::rng_from_seed,
+        simulation::{simulate, Protocol},
+        Node,
+    };
+
+    // Gateways hand out the group key, every node completes once its side of the transport is
+    // done
+    struct TransportProtocol {
+        transport: KeyTransport,
+    }
+
+    impl TransportProtocol {
+        fn try_complete(&self, node: usize, engine: &mut Engine) {
+            if self.transport.is_done(node) {
+                engine.complete(node);
+            }
+        }
+    }
+
+    impl Protocol for TransportProtocol {
+        fn start(&mut self, node: usize, engine: &mut Engine) {
+            if engine.nodes()[node].kind == NodeType::Gateway {
+                self.transport.start(node, engine);
+            }
+            self.try_complete(node, engine);
+        }
+
+        fn receive(&mut self, node: usize, message: &Message, engine: &mut Engine) {
+            self.transport.receive(node, message, engine);
+            self.try_complete(node, engine);
+        }
+
+        fn unsettled_links(&self) -> usize {
+            0
+        }
+    }
+
+    // Gateway 0 reaching member 2 through member 1
+    fn chain() -> NodesVec {
+        let mut nodes = NodesVec::new();
+        nodes.push(Node::new(0, NodeType::Gateway, vec![1], 2));
+        for (id, neighbors) in [(1, vec![0, 2]), (2, vec![1])] {
+            let mut member = Node::new(id, NodeType::Constrained, neighbors, 2);
+            member.gateway = Some(0);
+            nodes.push(member);
+        }
+        nodes
+    }
+
+    #[test]
+    fn keys_and_acknowledgements_are_relayed_along_the_route() {
+        let nodes = chain();
+        let config = SimulationConfig::default();
+        let mut protocol = TransportProtocol {
+            transport: KeyTransport::new(&nodes, 16.0, 8.0),
+        };
+        let report = simulate(&nodes, &config, &mut protocol, &mut rng_from_seed(0));
+        assert!(report.completion_time.is_some());
+        // Two keys from the gateway, an acknowledgement from each member, and node 1 relays
+        // the key of node 2 and its acknowledgement
+        assert_eq!(report.messages_sent, 6);
+        assert_eq!(report.bytes_sent, 2.0 * 16.0 + 2.0 * 8.0 + 16.0 + 8.0);
+        let ledger = &report.ledger;
+        for (node, count) in [(0, 2.0), (1, 1.0), (2, 1.0)] {
+            assert_eq!(ledger.node(node).encrypt, count * config.encryption_energy);
+            assert_eq!(ledger.node(node).decrypt, count * config.decryption_energy);
+        }
+        assert_eq!(
+            ledger.node(1).receive,
+            config.receive_energy(16.0 + 8.0 + 16.0)
+        );
+    }
+
+    #[test]
+    fn an_unacknowledged_key_is_only_carried() {
+        let nodes = chain();
+        let config = SimulationConfig::default();
+        let mut protocol = TransportProtocol {
+            transport: KeyTransport::unacknowledged(&nodes, 16.0),
+        };
+        let report = simulate(&nodes, &config, &mut protocol, &mut rng_from_seed(0));
+        assert!(report.completion_time.is_some());
+        // The two keys and the relayed one
+        assert_eq!(report.messages_sent, 3);
+        let total = report.ledger.total();
+        assert_eq!(total.encrypt + total.decrypt, 0.0);
+    }
+
+    #[test]
+    fn unreachable_members_are_not_waited_for() {
+        let mut nodes = chain();
+        let mut member = Node::new(3, NodeType::Constrained, Vec::new(), 2);
+        member.gateway = Some(0);
+        nodes.push(member);
+        let transport = KeyTransport::new(&nodes, 16.0, 8.0);
+        assert_eq!(transport.awaiting[0], 2);
+        assert!(!transport.is_done(3));
+        let mut protocol = TransportProtocol { transport };
+        let report = simulate(
+            &nodes,
+            &SimulationConfig::default(),
+            &mut protocol,
+            &mut rng_from_seed(0),
+        );
+        // The member out of reach is not started, the gateway completes without it
+        assert!(report.completion_time.is_some());
+        assert_eq!(report.completed_at[3], None);
+    }
+}
@@ -0,0 +1,141 @@
+use serde::Serialize;
+
+// Two-sided 97.5% quantiles of Student's t distribution for 1 to 30 degrees of freedom
+const T_QUANTILES: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+const Z_QUANTILE: f64 = 1.96;
+
+fn t_quantile(degrees_of_freedom: usize) -> f64 {
+    match degrees_of_freedom {
+        0 => f64::NAN,
+        1..=30 => T_QUANTILES[degrees_of_freedom - 1],
+        _ => Z_QUANTILE,
+    }
+}
+
+// Descriptive statistics of the samples collected for one sweep point
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Summary {
+    pub count: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub max: f64,
+    pub median: f64,
+    // 95% confidence interval of the mean, NaN with fewer than two samples
+    pub ci95_low: f64,
+    pub ci95_high: f64,
+}
+
+impl Summary {
+    pub fn from_samples(samples: &[f64]) -> Self {
+        let count = samples.len();
+        if count == 0 {
+            return Self {
+                count,
+                mean: f64::NAN,
+                std_dev: f64::NAN,
+                min: f64::NAN,
+                max: f64::NAN,
+                median: f64::NAN,
+                ci95_low: f64::NAN,
+                ci95_high: f64::NAN,
+            };
+        }
+        let mean = samples.iter().sum::<f64>() / count as f64;
+        // Sample standard deviation, zero when there is a single sample
+        let std_dev = if count > 1 {
+            let squares: f64 = samples.iter().map(|sample| (sample - mean).powi(2)).sum();
+            (squares / (count - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+        let mut sorted: Vec<f64> = samples.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let median = if count % 2 == 1 {
+            sorted[count / 2]
+        } else {
+            (sorted[count / 2 - 1] + sorted[count / 2]) / 2.0
+        };
+        // A single sample says nothing about the spread, its interval is left undefined
+        let half_width = if count > 1 {
+            t_quantile(count - 1) * std_dev / (count as f64).sqrt()
+        } else {
+            f64::NAN
+        };
+        Self {
+            count,
+            mean,
+            std_dev,
+            min: sorted[0],
+            max: sorted[count - 1],
+            median,
+            ci95_low: mean - half_width,
+            ci95_high: mean + half_width,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn summary_of_a_known_sample() {
+        let summary = Summary::from_samples(&[9.0, 2.0, 4.0, 4.0, 5.0, 4.0, 7.0, 5.0]);
+        assert_eq!(summary.count, 8);
+        assert_close(summary.mean, 5.0);
+        // Squared deviations sum to 32 over 7 degrees of freedom
+        let std_dev = (32.0_f64 / 7.0).sqrt();
+        assert_close(summary.std_dev, std_dev);
+        assert_close(summary.min, 2.0);
+        assert_close(summary.max, 9.0);
+        assert_close(summary.median, 4.5);
+        let half_width = 2.365 * std_dev / 8.0_f64.sqrt();
+        assert_close(summary.ci95_low, 5.0 - half_width);
+        assert_close(summary.ci95_high, 5.0 + half_width);
+    }
+
+    #[test]
+    fn median_of_an_odd_sample() {
+        assert_close(Summary::from_samples(&[3.0, 1.0, 2.0]).median, 2.0);
+    }
+
+    #[test]
+    fn large_samples_use_the_normal_quantile() {
+        let samples: Vec<f64> = (0..100).map(|k| (k % 2) as f64).collect();
+        let summary = Summary::from_samples(&samples);
+        let half_width = Z_QUANTILE * summary.std_dev / 10.0;
+        assert_close(summary.ci95_high - summary.mean, half_width);
+    }
+
+    #[test]
+    fn single_sample_has_no_spread() {
+        let summary = Summary::from_samples(&[4.0]);
+        assert_eq!(summary.count, 1);
+        assert_close(summary.std_dev, 0.0);
+        assert_close(summary.mean, 4.0);
+        assert!(summary.ci95_low.is_nan());
+        assert!(summary.ci95_high.is_nan());
+    }
+
+    #[test]
+    fn empty_sample_is_undefined() {
+        let summary = Summary::from_samples(&[]);
+        assert_eq!(summary.count, 0);
+        assert!(summary.mean.is_nan());
+        assert!(summary.ci95_low.is_nan());
+    }
+}
@@ -0,0 +1,281 @@
+use rand::Rng;
+
+use crate::{Node, NodeState, NodeType, NodesVec, Position};
+
+// How a joining node is wired into the neighbor graph, following the topology it joins
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Attachment {
+    // Paired with random nodes that still have room, up to `max_possible_neighbors` of them
+    Random {
+        max_possible_neighbors: usize,
+    },
+    // Connected to every node within `radio_range` of `position`
+    InRange {
+        position: Position,
+        radio_range: f64,
+    },
+}
+
+// Links and cluster of a node at the time it left
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Departure {
+    pub node: usize,
+    pub kind: NodeType,
+    pub neighbors: Vec<usize>,
+    // Cluster the node belonged to, or led when it was a gateway
+    pub gateway: Option<usize>,
+    // Members of a departing gateway, left without a cluster
+    pub orphans: Vec<usize>,
+}
+
+impl NodesVec {
+    // Add an active node with the next id and wire it into the network. Random attachments only
+    // pair it with nodes under their degree limit. A constrained node then joins the cluster of
+    // a neighbor with fewer than `capacity` members, a gateway starts an empty cluster.
+    pub fn add_node<R: Rng + ?Sized>(
+        &mut self,
+        kind: NodeType,
+        attachment: Attachment,
+        capacity: usize,
+        rng: &mut R,
+    ) -> usize {
+        let id = self.len();
+        self.push(Node::new(id, kind, vec![], 0));
+        match attachment {
+            Attachment::Random {
+                max_possible_neighbors,
+            } => {
+                self[id].max_possible_neighbors = max_possible_neighbors;
+                let mut candidates: Vec<usize> = self
+                    .iter()
+                    .filter(|other| {
+                        other.id != id
+                            && other.is_active()
+                            && other.neighbors.len() < other.max_possible_neighbors
+                    })
+                    .map(|other| other.id)
+                    .collect();
+                while self[id].neighbors.len() < max_possible_neighbors && !candidates.is_empty() {
+                    let neighbor = candidates.swap_remove(rng.gen_range(0..candidates.len()));
+                    self[id].neighbors.push(neighbor);
+                    self[neighbor].neighbors.push(id);
+                }
+            }
+            Attachment::InRange {
+                position,
+                radio_range,
+            } => {
+                self[id].position = Some(position);
+                // In range is the only limit of geometric topologies, the degree of the nodes
+                // reached grows with the new link
+                for other in 0..id {
+                    let in_range = self[other].is_active()
+                        && self[other]
+                            .position
+                            .is_some_and(|other| other.distance(&position) <= radio_range);
+                    if in_range {
+                        self[id].neighbors.push(other);
+                        self[other].neighbors.push(id);
+                        self[other].max_possible_neighbors += 1;
+                    }
+                }
+                self[id].max_possible_neighbors = self[id].neighbors.len();
+            }
+        }
+        if kind == NodeType::Constrained {
+            self.join_cluster(id, capacity);
+        }
+        id
+    }
+
+    // A neighboring gateway with room, else the cluster of a neighbor with room
+    fn join_cluster(&mut self, node: usize, capacity: usize) {
+        let clusters = self.clusters();
+        let has_room = |gateway: usize| {
+            clusters
+                .iter()
+                .any(|cluster| cluster.gateway == gateway && cluster.members.len() < capacity)
+        };
+        let neighbors = &self[node].neighbors;
+        let gateway = neighbors
+            .iter()
+            .copied()
+            .filter(|&neighbor| self[neighbor].kind == NodeType::Gateway)
+            .chain(
+                neighbors
+                    .iter()
+                    .filter_map(|&neighbor| self[neighbor].gateway),
+            )
+            .find(|&gateway| has_room(gateway));
+        self[node].gateway = gateway;
+    }
+
+    // Take the node out of the network, its neighbors drop their links to it
+    pub fn remove_node(&mut self, node: usize) -> Departure {
+        self.retire(node, NodeState::Departed)
+    }
+
+    // Take out a node the network no longer trusts
+    pub fn revoke_node(&mut self, node: usize) -> Departure {
+        self.retire(node, NodeState::Revoked)
+    }
+
+    fn retire(&mut self, node: usize, state: NodeState) -> Departure {
+        let neighbors = std::mem::take(&mut self[node].neighbors);
+        for &neighbor in neighbors.iter() {
+            self[neighbor].neighbors.retain(|&id| id != node);
+        }
+        let kind = self[node].kind;
+        let mut orphans: Vec<usize> = Vec::new();
+        let gateway = match kind {
+            NodeType::Gateway => {
+                for other in self.iter_mut() {
+                    if other.gateway == Some(node) {
+                        other.gateway = None;
+                        orphans.push(other.id);
+                    }
+                }
+                Some(node)
+            }
+            NodeType::Constrained => self[node].gateway.take(),
+        };
+        self[node].state = state;
+        Departure {
+            node,
+            kind,
+            neighbors,
+            gateway,
+            orphans,
+        }
+    }
+
+    // The network reduced to the links of the node, to key it with its neighbors alone
+    pub fn links_of(&self, node: usize) -> NodesVec {
+        let mut links = self.clone();
+        for other in links.iter_mut() {
+            if other.id != node {
+                other.neighbors.retain(|&neighbor| neighbor == node);
+            }
+        }
+        links
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rng::rng_from_seed,
+        topology::{assign_gateway_clusters, initialize_network_from_seed},
+    };
+
+    fn network(seed: u64) -> NodesVec {
+        let mut nodes = initialize_network_from_seed(60, 6, 3, 8, seed);
+        assign_gateway_clusters(&mut nodes, 10, &mut rng_from_seed(seed));
+        nodes
+    }
+
+    fn assert_symmetric(nodes: &NodesVec) {
+        for node in nodes.iter() {
+            for &neighbor in node.neighbors.iter() {
+                assert!(nodes[neighbor].neighbors.contains(&node.id));
+                assert!(nodes[neighbor].is_active());
+            }
+        }
+    }
+
+    #[test]
+    fn random_joins_keep_links_symmetric_and_degrees_bounded() {
+        let mut nodes = network(2);
+        let mut rng = rng_from_seed(2);
+        for _ in 0..10 {
+            let id = nodes.add_node(
+                NodeType::Constrained,
+                Attachment::Random {
+                    max_possible_neighbors: 4,
+                },
+                10,
+                &mut rng,
+            );
+            assert_eq!(id, nodes.len() - 1);
+            assert!(!nodes[id].neighbors.is_empty() && nodes[id].neighbors.len() <= 4);
+            if let Some(gateway) = nodes[id].gateway {
+                let cluster = nodes.cluster_of(gateway).unwrap();
+                assert!(cluster.members.len() <= 10);
+            }
+        }
+        assert_symmetric(&nodes);
+        assert!(nodes
+            .iter()
+            .all(|node| node.neighbors.len() <= node.max_possible_neighbors));
+    }
+
+    #[test]
+    fn in_range_joins_link_every_node_in_range() {
+        let mut nodes = NodesVec::new();
+        for (id, x) in [(0, 0.0), (1, 10.0), (2, 50.0)] {
+            let mut node = Node::new(id, NodeType::Constrained, Vec::new(), 0);
+            node.position = Some(Position { x, y: 0.0, z: 0.0 });
+            nodes.push(node);
+        }
+        let id = nodes.add_node(
+            NodeType::Gateway,
+            Attachment::InRange {
+                position: Position {
+                    x: 5.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                radio_range: 20.0,
+            },
+            10,
+            &mut rng_from_seed(0),
+        );
+        assert_eq!(nodes[id].neighbors, vec![0, 1]);
+        assert_eq!(nodes[id].max_possible_neighbors, 2);
+        assert_eq!(nodes[0].max_possible_neighbors, 1);
+        assert_symmetric(&nodes);
+    }
+
+    #[test]
+    fn departures_drop_every_link_to_the_node() {
+        let mut nodes = network(3);
+        let member = nodes
+            .iter()
+            .find(|node| node.gateway.is_some())
+            .unwrap()
+            .clone();
+        let departure = nodes.remove_node(member.id);
+        assert_eq!(departure.neighbors, member.neighbors);
+        assert_eq!(departure.gateway, member.gateway);
+        assert!(nodes[member.id].neighbors.is_empty());
+        assert_eq!(nodes[member.id].state, NodeState::Departed);
+        assert_symmetric(&nodes);
+
+        let gateway = nodes.clusters()[0].clone();
+        let departure = nodes.revoke_node(gateway.gateway);
+        assert_eq!(departure.orphans, gateway.members);
+        assert_eq!(nodes[gateway.gateway].state, NodeState::Revoked);
+        assert!(gateway
+            .members
+            .iter()
+            .all(|&member| nodes[member].gateway.is_none()));
+        assert_symmetric(&nodes);
+    }
+
+    #[test]
+    fn links_of_keep_only_the_links_of_the_node() {
+        let nodes = network(4);
+        let node = 10;
+        let links = nodes.links_of(node);
+        assert_eq!(links[node].neighbors, nodes[node].neighbors);
+        for other in links.iter().filter(|other| other.id != node) {
+            let expected: Vec<usize> = if nodes[node].neighbors.contains(&other.id) {
+                vec![node]
+            } else {
+                Vec::new()
+            };
+            assert_eq!(other.neighbors, expected);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+
+use rand::Rng;
+
+use crate::{NodeType, NodesVec};
+
+// Assign every constrained node to a gateway cluster of at most `capacity` members.
+// Clusters grow hop by hop from all gateways at once over the neighbor graph, so a node joins
+// the closest gateway that still has room and each cluster stays connected through its own
+// members. Nodes no cluster could reach are then assigned to a random gateway with room left,
+// and stay unassigned once every cluster is full.
+pub fn assign_gateway_clusters<R: Rng + ?Sized>(
+    nodes: &mut NodesVec,
+    capacity: usize,
+    rng: &mut R,
+) {
+    let mut remaining: Vec<usize> = vec![0; nodes.len()];
+    let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+    for node in nodes.iter_mut() {
+        node.gateway = None;
+    }
+    for node in nodes.iter() {
+        if node.kind == NodeType::Gateway {
+            remaining[node.id] = capacity;
+            queue.push_back((node.id, node.id));
+        }
+    }
+
+    while let Some((current, gateway)) = queue.pop_front() {
+        for k in 0..nodes[current].neighbors.len() {
+            if remaining[gateway] == 0 {
+                break;
+            }
+            let neighbor = nodes[current].neighbors[k];
+            if nodes[neighbor].kind == NodeType::Gateway || nodes[neighbor].gateway.is_some() {
+                continue;
+            }
+            nodes[neighbor].gateway = Some(gateway);
+            remaining[gateway] -= 1;
+            queue.push_back((neighbor, gateway));
+        }
+    }
+
+    let mut open_gateways: Vec<usize> = nodes
+        .iter()
+        .filter(|node| node.kind == NodeType::Gateway && remaining[node.id] > 0)
+        .map(|node| node.id)
+        .collect();
+    for i in 0..nodes.len() {
+        if open_gateways.is_empty() {
+            break;
+        }
+        if nodes[i].kind == NodeType::Gateway || nodes[i].gateway.is_some() {
+            continue;
+        }
+        let index = rng.gen_range(0..open_gateways.len());
+        let gateway = open_gateways[index];
+        nodes[i].gateway = Some(gateway);
+        remaining[gateway] -= 1;
+        if remaining[gateway] == 0 {
+            open_gateways.swap_remove(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rng::rng_from_seed, topology::initialize_network_from_seed, Cluster, Node};
+
+    // Gateways 0 and 5 at both ends of the chain 0-1-2-3-4-5, node 6 on its own
+    fn chain() -> NodesVec {
+        let links: [&[usize]; 7] = [&[1], &[0, 2], &[1, 3], &[2, 4], &[3, 5], &[4], &[]];
+        let mut nodes = NodesVec::new();
+        for (id, neighbors) in links.iter().enumerate() {
+            let kind = if id == 0 || id == 5 {
+                NodeType::Gateway
+            } else {
+                NodeType::Constrained
+            };
+            nodes.push(Node::new(id, kind, neighbors.to_vec(), neighbors.len()));
+        }
+        nodes
+    }
+
+    #[test]
+    fn nodes_join_the_closest_gateway() {
+        let mut nodes = chain();
+        assign_gateway_clusters(&mut nodes, 2, &mut rng_from_seed(1));
+        assert_eq!(
+            nodes.clusters(),
+            vec![
+                Cluster {
+                    gateway: 0,
+                    members: vec![1, 2],
+                },
+                Cluster {
+                    gateway: 5,
+                    members: vec![3, 4],
+                },
+            ]
+        );
+        // Both clusters are full, the unreachable node is left out
+        assert_eq!(nodes[6].gateway, None);
+    }
+
+    #[test]
+    fn full_clusters_leave_nodes_unassigned() {
+        let mut nodes = chain();
+        assign_gateway_clusters(&mut nodes, 1, &mut rng_from_seed(1));
+        let gateways: Vec<Option<usize>> = nodes.iter().map(|node| node.gateway).collect();
+        assert_eq!(
+            gateways,
+            vec![None, Some(0), None, None, Some(5), None, None]
+        );
+    }
+
+    #[test]
+    fn unreachable_nodes_fill_the_remaining_room() {
+        let mut nodes = chain();
+        assign_gateway_clusters(&mut nodes, 3, &mut rng_from_seed(1));
+        assert!(matches!(nodes[6].gateway, Some(0) | Some(5)));
+        let sizes: Vec<usize> = nodes
+            .clusters()
+            .iter()
+            .map(|cluster| cluster.members.len())
+            .collect();
+        assert_eq!(sizes.iter().sum::<usize>(), 5);
+        assert!(sizes.iter().all(|&size| size <= 3));
+    }
+
+    #[test]
+    fn clusters_never_exceed_their_capacity() {
+        for seed in 0..5 {
+            for capacity in [3, 9, 20] {
+                let mut nodes = initialize_network_from_seed(60, 6, 3, 8, seed);
+                assign_gateway_clusters(&mut nodes, capacity, &mut rng_from_seed(seed));
+                let clusters = nodes.clusters();
+                assert_eq!(clusters.len(), 6);
+                assert!(clusters
+                    .iter()
+                    .all(|cluster| cluster.members.len() <= capacity));
+                let assigned: usize = clusters.iter().map(|cluster| cluster.members.len()).sum();
+                // Every constrained node is covered as long as there is room for it
+                assert_eq!(assigned, (6 * capacity).min(54));
+                assert!(nodes
+                    .iter()
+                    .filter(|node| node.kind == NodeType::Gateway)
+                    .all(|node| node.gateway.is_none()));
+            }
+        }
+    }
+}
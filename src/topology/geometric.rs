@@ -0,0 +1,339 @@
+use std::{collections::HashMap, f64::consts::PI, fmt, str::FromStr};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::Serialize;
+
+use crate::{Node, NodeType, NodesVec, Position};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Placement {
+    // Independent uniform positions over the area
+    Uniform,
+    // Regular lattice covering the area, filled in row order
+    Grid,
+    // Normally distributed around uniformly placed cluster centers
+    Clustered,
+}
+
+impl FromStr for Placement {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "uniform" => Ok(Placement::Uniform),
+            "grid" => Ok(Placement::Grid),
+            "clustered" => Ok(Placement::Clustered),
+            _ => Err(format!("unknown placement: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for Placement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Placement::Uniform => write!(f, "uniform"),
+            Placement::Grid => write!(f, "grid"),
+            Placement::Clustered => write!(f, "clustered"),
+        }
+    }
+}
+
+// Deployment area in meters, a depth of 0 gives a planar deployment
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Area {
+    pub width: f64,
+    pub height: f64,
+    pub depth: f64,
+}
+
+impl Area {
+    fn is_planar(&self) -> bool {
+        self.depth <= 0.0
+    }
+
+    fn clamp(&self, position: Position) -> Position {
+        Position {
+            x: position.x.clamp(0.0, self.width),
+            y: position.y.clamp(0.0, self.height),
+            z: position.z.clamp(0.0, self.depth.max(0.0)),
+        }
+    }
+
+    fn uniform<R: Rng + ?Sized>(&self, rng: &mut R) -> Position {
+        Position {
+            x: rng.gen::<f64>() * self.width,
+            y: rng.gen::<f64>() * self.height,
+            z: if self.is_planar() {
+                0.0
+            } else {
+                rng.gen::<f64>() * self.depth
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GeometricTopology {
+    pub area: Area,
+    pub placement: Placement,
+    // Two nodes are neighbors when they are at most this far apart
+    pub radio_range: f64,
+    pub number_of_clusters: usize,
+    // Standard deviation of the distance to the cluster center
+    pub cluster_spread: f64,
+}
+
+// Standard normal sample with the Box-Muller transform
+fn standard_normal<R: Rng + ?Sized>(rng: &mut R) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+impl GeometricTopology {
+    fn positions<R: Rng + ?Sized>(&self, number_of_nodes: usize, rng: &mut R) -> Vec<Position> {
+        let area = &self.area;
+        match self.placement {
+            Placement::Uniform => (0..number_of_nodes).map(|_| area.uniform(rng)).collect(),
+            Placement::Grid => {
+                let dimensions = if area.is_planar() { 2.0 } else { 3.0 };
+                let side = (number_of_nodes as f64)
+                    .powf(1.0 / dimensions)
+                    .ceil()
+                    .max(1.0) as usize;
+                let step = |length: f64| {
+                    if side > 1 {
+                        length / (side - 1) as f64
+                    } else {
+                        0.0
+                    }
+                };
+                (0..number_of_nodes)
+                    .map(|i| Position {
+                        x: (i % side) as f64 * step(area.width),
+                        y: ((i / side) % side) as f64 * step(area.height),
+                        z: if area.is_planar() {
+                            0.0
+                        } else {
+                            (i / (side * side)) as f64 * step(area.depth)
+                        },
+                    })
+                    .collect()
+            }
+            Placement::Clustered => {
+                let centers: Vec<Position> = (0..self.number_of_clusters.max(1))
+                    .map(|_| area.uniform(rng))
+                    .collect();
+                (0..number_of_nodes)
+                    .map(|_| {
+                        let center = centers[rng.gen_range(0..centers.len())];
+                        area.clamp(Position {
+                            x: center.x + standard_normal(rng) * self.cluster_spread,
+                            y: center.y + standard_normal(rng) * self.cluster_spread,
+                            z: if area.is_planar() {
+                                0.0
+                            } else {
+                                center.z + standard_normal(rng) * self.cluster_spread
+                            },
+                        })
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    // Nodes joining after deployment land anywhere in the area, whatever the placement
+    pub fn joining_position<R: Rng + ?Sized>(&self, rng: &mut R) -> Position {
+        self.area.uniform(rng)
+    }
+
+    // Place the nodes, pick the gateways at random and connect every pair within radio range.
+    // Nodes are bucketed in cells of the size of the radio range so only adjacent cells are
+    // searched for neighbors.
+    pub fn build<R: Rng + ?Sized>(
+        &self,
+        number_of_nodes: i32,
+        number_of_gateways: i32,
+        rng: &mut R,
+    ) -> NodesVec {
+        let number_of_nodes = number_of_nodes.max(0) as usize;
+        let number_of_gateways = (number_of_gateways.max(0) as usize).min(number_of_nodes);
+        let positions = self.positions(number_of_nodes, rng);
+
+        let mut kinds: Vec<NodeType> = (0..number_of_nodes)
+            .map(|i| {
+                if i < number_of_gateways {
+                    NodeType::Gateway
+                } else {
+                    NodeType::Constrained
+                }
+            })
+            .collect();
+        kinds.shuffle(rng);
+
+        let mut nodes: NodesVec = NodesVec::new();
+        for (id, (kind, position)) in kinds.into_iter().zip(positions).enumerate() {
+            let mut node = Node::new(id, kind, vec![], 0);
+            node.position = Some(position);
+            nodes.push(node);
+        }
+
+        let cell_size = self.radio_range.max(f64::MIN_POSITIVE);
+        let cell = |position: &Position| -> (i64, i64, i64) {
+            (
+                (position.x / cell_size).floor() as i64,
+                (position.y / cell_size).floor() as i64,
+                (position.z / cell_size).floor() as i64,
+            )
+        };
+        let mut cells: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+        for node in nodes.iter() {
+            if let Some(position) = &node.position {
+                cells.entry(cell(position)).or_default().push(node.id);
+            }
+        }
+
+        for i in 0..nodes.len() {
+            let position = nodes[i].position.expect("geometric nodes have a position");
+            let (cx, cy, cz) = cell(&position);
+            let mut neighbors: Vec<usize> = Vec::new();
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let Some(candidates) = cells.get(&(cx + dx, cy + dy, cz + dz)) else {
+                            continue;
+                        };
+                        for &j in candidates.iter() {
+                            if j == i {
+                                continue;
+                            }
+                            let other = nodes[j].position.expect("geometric nodes have a position");
+                            if position.distance(&other) <= self.radio_range {
+                                neighbors.push(j);
+                            }
+                        }
+                    }
+                }
+            }
+            neighbors.sort_unstable();
+            nodes[i].max_possible_neighbors = neighbors.len();
+            nodes[i].neighbors = neighbors;
+        }
+
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::rng_from_seed;
+
+    fn topology(placement: Placement, depth: f64) -> GeometricTopology {
+        GeometricTopology {
+            area: Area {
+                width: 100.0,
+                height: 50.0,
+                depth,
+            },
+            placement,
+            radio_range: 20.0,
+            number_of_clusters: 3,
+            cluster_spread: 10.0,
+        }
+    }
+
+    fn inside(area: &Area, position: &Position) -> bool {
+        (0.0..=area.width).contains(&position.x)
+            && (0.0..=area.height).contains(&position.y)
+            && (0.0..=area.depth.max(0.0)).contains(&position.z)
+    }
+
+    #[test]
+    fn placements_stay_in_the_area() {
+        for placement in [Placement::Uniform, Placement::Grid, Placement::Clustered] {
+            for depth in [0.0, 30.0] {
+                let topology = topology(placement, depth);
+                let nodes = topology.build(80, 4, &mut rng_from_seed(5));
+                assert_eq!(nodes.len(), 80);
+                for node in nodes.iter() {
+                    let position = node.position.unwrap();
+                    assert!(inside(&topology.area, &position), "{:?}", position);
+                    if depth == 0.0 {
+                        assert_eq!(position.z, 0.0);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn grid_fills_the_lattice_in_row_order() {
+        let nodes = topology(Placement::Grid, 0.0).build(9, 0, &mut rng_from_seed(1));
+        // Three columns 50 m apart and three rows 25 m apart
+        let positions: Vec<(f64, f64)> = nodes
+            .iter()
+            .map(|node| {
+                let position = node.position.unwrap();
+                (position.x, position.y)
+            })
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                (0.0, 0.0),
+                (50.0, 0.0),
+                (100.0, 0.0),
+                (0.0, 25.0),
+                (50.0, 25.0),
+                (100.0, 25.0),
+                (0.0, 50.0),
+                (50.0, 50.0),
+                (100.0, 50.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn nodes_within_radio_range_are_linked() {
+        for placement in [Placement::Uniform, Placement::Clustered] {
+            let topology = topology(placement, 30.0);
+            let nodes = topology.build(120, 6, &mut rng_from_seed(9));
+            for node in nodes.iter() {
+                let position = node.position.unwrap();
+                let expected: Vec<usize> = nodes
+                    .iter()
+                    .filter(|other| other.id != node.id)
+                    .filter(|other| position.distance(&other.position.unwrap()) <= 20.0)
+                    .map(|other| other.id)
+                    .collect();
+                assert_eq!(node.neighbors, expected);
+                assert_eq!(node.max_possible_neighbors, expected.len());
+            }
+        }
+    }
+
+    #[test]
+    fn gateways_are_capped_by_the_node_count() {
+        let gateways = |nodes: &NodesVec| {
+            nodes
+                .iter()
+                .filter(|node| node.kind == NodeType::Gateway)
+                .count()
+        };
+        let topology = topology(Placement::Uniform, 0.0);
+        assert_eq!(gateways(&topology.build(30, 4, &mut rng_from_seed(2))), 4);
+        assert_eq!(gateways(&topology.build(3, 4, &mut rng_from_seed(2))), 3);
+        assert!(topology.build(-1, 4, &mut rng_from_seed(2)).is_empty());
+    }
+
+    #[test]
+    fn placements_parse_back_from_their_names() {
+        for placement in [Placement::Uniform, Placement::Grid, Placement::Clustered] {
+            assert_eq!(placement.to_string().parse::<Placement>(), Ok(placement));
+        }
+        assert_eq!(" Grid ".parse::<Placement>(), Ok(Placement::Grid));
+        assert!("hexagonal".parse::<Placement>().is_err());
+    }
+}
@@ -0,0 +1,42 @@
+use std::{fmt, str::FromStr};
+
+use serde::Serialize;
+
+pub mod churn;
+pub mod clusters;
+pub mod geometric;
+pub mod random;
+
+pub use churn::{Attachment, Departure};
+pub use clusters::assign_gateway_clusters;
+pub use random::{initialize_network, initialize_network_from_seed};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TopologyKind {
+    // Random pairing with a bounded number of neighbors per node
+    Random,
+    // Nodes placed in an area and connected within radio range
+    Geometric,
+}
+
+impl FromStr for TopologyKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "random" => Ok(TopologyKind::Random),
+            "geometric" => Ok(TopologyKind::Geometric),
+            _ => Err(format!("unknown topology: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for TopologyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TopologyKind::Random => write!(f, "random"),
+            TopologyKind::Geometric => write!(f, "geometric"),
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{rng::rng_from_seed, Node, NodeType, NodesVec};
+
+// Gateways and constrained nodes in random order, each with a random maximum number of neighbors
+fn create_nodes<R: Rng + ?Sized>(
+    number_of_nodes: i32,
+    number_of_gateways: i32,
+    number_of_min_possible_neighbors: i32,
+    number_of_max_possible_neighbors: i32,
+    rng: &mut R,
+) -> NodesVec {
+    let mut nodes: NodesVec = NodesVec::new();
+
+    // Push gateway nodes
+    for _ in 0..number_of_gateways {
+        let number_of_neighbors: i32 =
+            rng.gen_range(number_of_min_possible_neighbors..=number_of_max_possible_neighbors);
+        let node = Node::new(
+            nodes.len(),
+            NodeType::Gateway,
+            vec![],
+            number_of_neighbors as usize,
+        );
+        nodes.push(node);
+    }
+
+    // Push constrained nodes
+    for _ in 0..(number_of_nodes - number_of_gateways) {
+        let number_of_neighbors: i32 =
+            rng.gen_range(number_of_min_possible_neighbors..=number_of_max_possible_neighbors);
+        let node = Node::new(
+            nodes.len(),
+            NodeType::Constrained,
+            vec![],
+            number_of_neighbors as usize,
+        );
+        nodes.push(node);
+    }
+
+    // Sort nodes randomly
+    nodes.shuffle(rng);
+
+    // Update id of each node to match its index in the nodes array
+    for (i, node) in nodes.iter_mut().enumerate() {
+        node.id = i;
+    }
+
+    nodes
+}
+
+// Nodes that can still accept a neighbor, with O(1) removal
+struct OpenNodes {
+    nodes: Vec<usize>,
+    // Index of each node in `nodes`, usize::MAX once the node is full
+    positions: Vec<usize>,
+}
+
+impl OpenNodes {
+    fn new(nodes: &NodesVec) -> Self {
+        let mut open = Self {
+            nodes: Vec::with_capacity(nodes.len()),
+            positions: vec![usize::MAX; nodes.len()],
+        };
+        for node in nodes.iter() {
+            if node.neighbors.len() < node.max_possible_neighbors {
+                open.positions[node.id] = open.nodes.len();
+                open.nodes.push(node.id);
+            }
+        }
+        open
+    }
+
+    fn contains(&self, node: usize) -> bool {
+        self.positions[node] != usize::MAX
+    }
+
+    fn remove(&mut self, node: usize) {
+        let position = self.positions[node];
+        if position == usize::MAX {
+            return;
+        }
+        let last = self.nodes.len() - 1;
+        self.nodes.swap(position, last);
+        self.positions[self.nodes[position]] = position;
+        self.nodes.pop();
+        self.positions[node] = usize::MAX;
+    }
+}
+
+// Each node, in order, picks uniformly among the nodes that are not itself, not already its
+// neighbor and not full, until it is full or no such node is left. Candidates are drawn from the
+// set of open nodes and rejected when they are already neighbors, which keeps the generation in
+// O(n * k) for n nodes of at most k neighbors.
+pub fn initialize_network<R: Rng + ?Sized>(
+    number_of_nodes: i32,
+    number_of_gateways: i32,
+    number_of_min_possible_neighbors: i32,
+    number_of_max_possible_neighbors: i32,
+    rng: &mut R,
+) -> NodesVec {
+    let mut nodes: NodesVec = create_nodes(
+        number_of_nodes,
+        number_of_gateways,
+        number_of_min_possible_neighbors,
+        number_of_max_possible_neighbors,
+        rng,
+    );
+    let mut open = OpenNodes::new(&nodes);
+    // marks[k] == i + 1 while node i is being connected and k is already its neighbor
+    let mut marks: Vec<usize> = vec![0; nodes.len()];
+
+    for i in 0..nodes.len() {
+        if !open.contains(i) {
+            continue;
+        }
+        let mark = i + 1;
+        let mut open_neighbors: usize = 0;
+        for &neighbor in nodes[i].neighbors.iter() {
+            marks[neighbor] = mark;
+            if open.contains(neighbor) {
+                open_neighbors += 1;
+            }
+        }
+
+        while nodes[i].neighbors.len() < nodes[i].max_possible_neighbors {
+            // Open nodes other than the current node and its neighbors
+            let number_of_candidates = open.nodes.len() - 1 - open_neighbors;
+            if number_of_candidates == 0 {
+                break;
+            }
+            let neighbor: usize = loop {
+                let candidate = open.nodes[rng.gen_range(0..open.nodes.len())];
+                if candidate != i && marks[candidate] != mark {
+                    break candidate;
+                }
+            };
+            // Add each node to the other's list of neighbors
+            nodes[i].neighbors.push(neighbor);
+            nodes[neighbor].neighbors.push(i);
+            marks[neighbor] = mark;
+            if nodes[neighbor].neighbors.len() < nodes[neighbor].max_possible_neighbors {
+                open_neighbors += 1;
+            } else {
+                open.remove(neighbor);
+            }
+        }
+        open.remove(i);
+    }
+
+    nodes
+}
+
+// Same as initialize_network with a generator seeded from `seed`
+pub fn initialize_network_from_seed(
+    number_of_nodes: i32,
+    number_of_gateways: i32,
+    number_of_min_possible_neighbors: i32,
+    number_of_max_possible_neighbors: i32,
+    seed: u64,
+) -> NodesVec {
+    initialize_network(
+        number_of_nodes,
+        number_of_gateways,
+        number_of_min_possible_neighbors,
+        number_of_max_possible_neighbors,
+        &mut rng_from_seed(seed),
+    )
+}
+
+// Original generator rescanning every node for each neighbor slot, O(n^2 * k).
+// Only kept as the baseline of the topology benchmark and of the tests.
+#[doc(hidden)]
+pub fn initialize_network_reference<R: Rng + ?Sized>(
+    number_of_nodes: i32,
+    number_of_gateways: i32,
+    number_of_min_possible_neighbors: i32,
+    number_of_max_possible_neighbors: i32,
+    rng: &mut R,
+) -> NodesVec {
+    let mut nodes: NodesVec = create_nodes(
+        number_of_nodes,
+        number_of_gateways,
+        number_of_min_possible_neighbors,
+        number_of_max_possible_neighbors,
+        rng,
+    );
+
+    // Start adding neighbors to each node
+    for i in 0..nodes.len() {
+        let current_node_id: usize = nodes[i].id;
+        let number_of_current_neighbors: usize = nodes[i].neighbors.len();
+        let number_of_current_max_possible_neighbors: usize = nodes[i].max_possible_neighbors;
+        let number_of_current_remaining_possible_neighbors: usize =
+            number_of_current_max_possible_neighbors - number_of_current_neighbors;
+        // For each remaining possible neighbor, add it to the current node if it is not already a neighbor
+        for _j in 0..number_of_current_remaining_possible_neighbors {
+            // Get the list of nodes that are not already neighbors of the current node and that are not the current node itself and that do not have the maximum number of neighbors
+            let possible_neighbors: Vec<usize> = (0..nodes.len())
+                .filter(|&k| k != i)
+                .filter(|&k| !nodes[i].neighbors.contains(&k))
+                .filter(|&k| nodes[k].neighbors.len() < nodes[k].max_possible_neighbors)
+                .collect();
+
+            // If there are no possible neighbors, break the loop
+            if possible_neighbors.is_empty() {
+                break;
+            }
+
+            // Pick a random node from the list of possible neighbors
+            let neighbor_index: usize = rng.gen_range(0..possible_neighbors.len());
+            let neighbor: usize = possible_neighbors[neighbor_index];
+            let neighbor_id: usize = nodes[neighbor].id;
+            // Add the neighbor id to the current node's list of neighbors
+            nodes[i].neighbors.push(neighbor_id);
+            // Add the current node id to the neighbor's list of neighbors
+            nodes[neighbor].neighbors.push(current_node_id);
+        }
+    }
+
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Degree bound, symmetric links without self or duplicate links, and no two open nodes left
+    // that could still be linked
+    fn assert_valid(nodes: &NodesVec) {
+        for node in nodes.iter() {
+            assert!(node.neighbors.len() <= node.max_possible_neighbors);
+            let mut neighbors = node.neighbors.clone();
+            neighbors.sort_unstable();
+            neighbors.dedup();
+            assert_eq!(neighbors.len(), node.neighbors.len(), "duplicate link");
+            for &neighbor in node.neighbors.iter() {
+                assert_ne!(neighbor, node.id, "self link");
+                assert!(nodes[neighbor].neighbors.contains(&node.id), "one-way link");
+            }
+        }
+        let open: Vec<&Node> = nodes
+            .iter()
+            .filter(|node| node.neighbors.len() < node.max_possible_neighbors)
+            .collect();
+        for (k, first) in open.iter().enumerate() {
+            for second in open[k + 1..].iter() {
+                assert!(
+                    first.neighbors.contains(&second.id),
+                    "open nodes {} and {} left unlinked",
+                    first.id,
+                    second.id
+                );
+            }
+        }
+    }
+
+    fn mean_degree(nodes: &NodesVec) -> f64 {
+        nodes.iter().map(|node| node.neighbors.len()).sum::<usize>() as f64 / nodes.len() as f64
+    }
+
+    #[test]
+    fn both_generators_respect_the_degree_guarantees() {
+        for (nodes, min, max) in [(40, 2, 5), (60, 0, 8), (10, 8, 12), (2, 1, 1)] {
+            for seed in 0..20 {
+                let fast =
+                    initialize_network(nodes, nodes / 10, min, max, &mut rng_from_seed(seed));
+                let reference = initialize_network_reference(
+                    nodes,
+                    nodes / 10,
+                    min,
+                    max,
+                    &mut rng_from_seed(seed),
+                );
+                assert_valid(&fast);
+                assert_valid(&reference);
+                // Both start from the same nodes and degree targets
+                let targets = |nodes: &NodesVec| -> Vec<(NodeType, usize)> {
+                    nodes
+                        .iter()
+                        .map(|node| (node.kind, node.max_possible_neighbors))
+                        .collect()
+                };
+                assert_eq!(targets(&fast), targets(&reference));
+            }
+        }
+    }
+
+    // The generators consume the random stream differently, so only their degrees are compared
+    #[test]
+    fn both_generators_reach_the_same_mean_degree() {
+        let (mut fast, mut reference) = (0.0, 0.0);
+        for seed in 0..50 {
+            fast += mean_degree(&initialize_network(
+                100,
+                10,
+                3,
+                10,
+                &mut rng_from_seed(seed),
+            ));
+            reference += mean_degree(&initialize_network_reference(
+                100,
+                10,
+                3,
+                10,
+                &mut rng_from_seed(seed),
+            ));
+        }
+        assert!((fast - reference).abs() / reference < 0.02);
+    }
+}